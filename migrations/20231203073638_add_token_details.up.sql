@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE
+    token_details
+(
+    token_uuid STRING        NOT NULL PRIMARY KEY,
+    user_id    STRING        NOT NULL,
+    family_id  STRING        NOT NULL,
+    expires_in BIGINT        NOT NULL,
+    max_age    BIGINT        NOT NULL,
+    revoked    BOOLEAN       NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP(),
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP()
+);
@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE nexus_refresh_tokens
+(
+    token_uuid STRING       NOT NULL PRIMARY KEY,
+    family_id  STRING       NOT NULL,
+    user_id    STRING       NOT NULL,
+    expires_at BIGINT       NOT NULL,
+    used_at    TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP()
+);
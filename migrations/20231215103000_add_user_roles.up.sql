@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE nexus_user_roles
+(
+    user_id    STRING       NOT NULL,
+    role       VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP(),
+    PRIMARY KEY (user_id, role)
+);
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE nexus_user_attributes
+(
+    user_id    STRING       NOT NULL,
+    name       VARCHAR(100) NOT NULL,
+    value      STRING,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP(),
+    PRIMARY KEY (user_id, name)
+);
@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE nexus_api_keys
+(
+    key_id     STRING       NOT NULL,
+    user_id    STRING       NOT NULL,
+    name       VARCHAR(100) NOT NULL,
+    scopes     STRING       NOT NULL,
+    key_hash   STRING       NOT NULL,
+    issued_at  BIGINT       NOT NULL,
+    expires_at BIGINT       NOT NULL,
+    revoked_at TIMESTAMP,
+    PRIMARY KEY (key_id)
+);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE nexus_users ADD COLUMN disabled_at TIMESTAMP;
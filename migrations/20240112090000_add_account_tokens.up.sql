@@ -0,0 +1,14 @@
+-- Add up migration script here
+ALTER TABLE nexus_users ADD COLUMN email_verified_at TIMESTAMP;
+UPDATE nexus_users SET email_verified_at = COALESCE(created_at, CURRENT_TIMESTAMP());
+
+CREATE TABLE nexus_account_tokens
+(
+    token_uuid STRING      NOT NULL,
+    user_id    STRING      NOT NULL,
+    purpose    VARCHAR(32) NOT NULL,
+    expires_at BIGINT      NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP(),
+    PRIMARY KEY (token_uuid)
+);
@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE nexus_invites
+(
+    invite_id  STRING       NOT NULL,
+    code_hash  STRING       NOT NULL,
+    created_by STRING       NOT NULL,
+    email      VARCHAR(255),
+    roles      STRING       NOT NULL,
+    issued_at  BIGINT       NOT NULL,
+    expires_at BIGINT       NOT NULL,
+    used_by    VARCHAR(255),
+    used_at    TIMESTAMP,
+    PRIMARY KEY (invite_id)
+);
@@ -0,0 +1,89 @@
+-- The nexus tables for a SQLite database, to run nexus locally and in tests.
+-- Referenced by the `init_script` of a sqlite database in the configuration, and safe to run on every start.
+
+CREATE TABLE IF NOT EXISTS nexus_users
+(
+    id                TEXT NOT NULL PRIMARY KEY,
+    name              TEXT NOT NULL,
+    email             TEXT NOT NULL UNIQUE,
+    password          TEXT NOT NULL,
+    created_at        TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at        TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    disabled_at       TIMESTAMP,
+    email_verified_at TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS token_details
+(
+    token_uuid TEXT    NOT NULL PRIMARY KEY,
+    user_id    TEXT    NOT NULL,
+    family_id  TEXT    NOT NULL,
+    expires_in BIGINT  NOT NULL,
+    max_age    BIGINT  NOT NULL,
+    revoked    BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS nexus_refresh_tokens
+(
+    token_uuid TEXT   NOT NULL PRIMARY KEY,
+    family_id  TEXT   NOT NULL,
+    user_id    TEXT   NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at    TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS nexus_user_roles
+(
+    user_id    TEXT NOT NULL,
+    role       TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, role)
+);
+
+CREATE TABLE IF NOT EXISTS nexus_user_attributes
+(
+    user_id    TEXT NOT NULL,
+    name       TEXT NOT NULL,
+    value      TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS nexus_api_keys
+(
+    key_id     TEXT   NOT NULL PRIMARY KEY,
+    user_id    TEXT   NOT NULL,
+    name       TEXT   NOT NULL,
+    scopes     TEXT   NOT NULL,
+    key_hash   TEXT   NOT NULL,
+    issued_at  BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS nexus_account_tokens
+(
+    token_uuid TEXT   NOT NULL PRIMARY KEY,
+    user_id    TEXT   NOT NULL,
+    purpose    TEXT   NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS nexus_invites
+(
+    invite_id  TEXT   NOT NULL PRIMARY KEY,
+    code_hash  TEXT   NOT NULL,
+    created_by TEXT   NOT NULL,
+    email      TEXT,
+    roles      TEXT   NOT NULL,
+    issued_at  BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_by    TEXT,
+    used_at    TIMESTAMP
+);
@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use axum::extract::{Request, State};
 use axum::http::StatusCode;
@@ -10,31 +12,66 @@ use serde::Serialize;
 use tracing::info;
 use uuid::Uuid;
 
-use crate::services::token_service::TokenClaims;
+use crate::errors::{ApiError, ApiResult};
+use crate::services::api_key_service::is_api_key;
+use crate::services::token_service::{TokenClaims, TokenType};
 use crate::AppState;
 
+pub const ADMIN_ROLE: &str = "admin";
+
 const AUTHORIZATION: &str = "Authorization";
 const BEARER: &str = "Bearer ";
+pub const API_KEY_HEADER: &str = "X-API-Key";
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ValidatedTokenDetails {
     pub token: String,
     pub token_uuid: Uuid,
     pub user_id: String,
+    pub family_id: Option<String>,
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+    /// Set when the caller authenticated with an API key: the endpoints the key may call
+    pub scopes: Option<Vec<String>>,
     pub expires_in: i64,
 }
 
+impl ValidatedTokenDetails {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub status: &'static str,
     pub message: String,
 }
 
+/// Authenticates the caller by a bearer access token, issued by nexus or by the configured OpenID Connect provider, or by
+/// an API key sent in `X-API-Key` or as the bearer token
 pub async fn validate_jwt_token(
     State(app_state): State<AppState>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    if let Some(api_key) = api_key(&request) {
+        let validated_token_details = app_state
+            .api_key_service
+            .authenticate(&api_key)
+            .await
+            .map_err(|e| match e {
+                ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            })?;
+        request.extensions_mut().insert(validated_token_details);
+        return Ok(next.run(request).await);
+    }
+
     let bearer_token = request
         .headers()
         .get(AUTHORIZATION)
@@ -42,25 +79,57 @@ pub async fn validate_jwt_token(
         .to_str()
         .map_err(|_e| StatusCode::UNAUTHORIZED)?;
 
-    info!("Bearer token: {}", bearer_token);
-
     if !bearer_token.starts_with(BEARER) {
         return Err(StatusCode::UNAUTHORIZED);
     }
 
     let access_token = &bearer_token[BEARER.len()..];
 
-    info!("Access token: {}", access_token);
+    if let Some(oidc_service) = app_state
+        .oidc_service
+        .as_ref()
+        .filter(|oidc| oidc.accepts(access_token))
+    {
+        let validated_token_details = oidc_service.validate(access_token).await.map_err(|e| match e {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+        request.extensions_mut().insert(validated_token_details);
+        return Ok(next.run(request).await);
+    }
 
     let access_token_secret = app_state.config.api.access_token_secret;
     let validated_token_details = verify_and_decode_jwt_token(access_token, &access_token_secret)?;
 
+    let revoked = app_state
+        .token_service
+        .is_revoked(&validated_token_details.token_uuid.to_string())
+        .await
+        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if revoked {
+        info!("Rejecting revoked token {}", validated_token_details.token_uuid);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
     request.extensions_mut().insert(validated_token_details);
 
     let response = next.run(request).await;
     Ok(response)
 }
 
+fn api_key(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    if let Some(api_key) = headers.get(API_KEY_HEADER) {
+        return api_key.to_str().ok().map(str::to_string);
+    }
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER))
+        .filter(|token| is_api_key(token))
+        .map(str::to_string)
+}
+
 fn verify_and_decode_jwt_token(token: &str, token_secret: &str) -> Result<ValidatedTokenDetails, StatusCode> {
     let validation = Validation::new(Algorithm::default());
 
@@ -71,10 +140,9 @@ fn verify_and_decode_jwt_token(token: &str, token_secret: &str) -> Result<Valida
     )
     .map_err(|_e| StatusCode::BAD_REQUEST)?;
 
-    //FIXME - Need to figure out how best we could factor in the force-revoked tokens (yanked from Redis)
-    // Update: TokenService is in the app_state. TokenService wraps TokenRepository, which must make this task easier.
-
-    info!("Token claims: {:?}", decoded.claims);
+    if decoded.claims.token_type != TokenType::Access {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
 
     let user_id = decoded.claims.sub.as_str();
     let token_uuid = Uuid::from_str(decoded.claims.token_uuid.as_str()).unwrap();
@@ -84,6 +152,142 @@ fn verify_and_decode_jwt_token(token: &str, token_secret: &str) -> Result<Valida
         token: token.to_string(),
         token_uuid,
         user_id: user_id.into(),
+        family_id: decoded.claims.family_id,
+        roles: decoded.claims.roles,
+        attributes: decoded.claims.attributes,
+        scopes: None,
         expires_in: decoded.claims.exp,
     })
 }
+
+/// Lets only the users holding one of the roles through; no roles at all means any authenticated user. Must run after
+/// `validate_jwt_token`.
+pub async fn require_roles(
+    State(allowed_roles): State<Arc<Vec<String>>>,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    let validated_token = request
+        .extensions()
+        .get::<ValidatedTokenDetails>()
+        .ok_or_else(|| ApiError::Unauthorized("missing access token".to_string()))?;
+    if !allowed_roles.is_empty() && !allowed_roles.iter().any(|role| validated_token.has_role(role)) {
+        info!("User {} lacks the roles {:?}", validated_token.user_id, allowed_roles);
+        return Err(ApiError::Forbidden);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Lets API keys through only to the endpoints in their scopes; access tokens are not scoped. Must run after
+/// `validate_jwt_token`.
+pub async fn require_scope(State(endpoint): State<Arc<String>>, request: Request, next: Next) -> ApiResult<Response> {
+    let validated_token = request
+        .extensions()
+        .get::<ValidatedTokenDetails>()
+        .ok_or_else(|| ApiError::Unauthorized("missing access token".to_string()))?;
+    if let Some(scopes) = &validated_token.scopes {
+        if !scopes.contains(&endpoint) {
+            info!("API key {} is not scoped to {}", validated_token.token_uuid, endpoint);
+            return Err(ApiError::Forbidden);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Turns API keys away. API keys are meant for data endpoints, so managing users and credentials takes a login. Must
+/// run after `validate_jwt_token`.
+pub async fn require_access_token(request: Request, next: Next) -> ApiResult<Response> {
+    if authenticated_by_api_key(&request) {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Lets only the users holding the `admin` role through, with an access token. Must run after `validate_jwt_token`.
+pub async fn require_admin(request: Request, next: Next) -> ApiResult<Response> {
+    if authenticated_by_api_key(&request) {
+        return Err(ApiError::Forbidden);
+    }
+    require_roles(State(Arc::new(vec![ADMIN_ROLE.to_string()])), request, next).await
+}
+
+fn authenticated_by_api_key(request: &Request) -> bool {
+    request
+        .extensions()
+        .get::<ValidatedTokenDetails>()
+        .is_some_and(ValidatedTokenDetails::is_api_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::middleware::{self, Next};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::auth::{require_roles, verify_and_decode_jwt_token, ValidatedTokenDetails};
+    use crate::config::AppConfig;
+    use crate::datasource::sqlite::test_source;
+    use crate::repositories::token_repository::TokenRepository;
+    use crate::services::token_service::TokenService;
+
+    async fn status_for(roles: &[&str]) -> StatusCode {
+        let token = ValidatedTokenDetails {
+            token: String::new(),
+            token_uuid: Uuid::new_v4(),
+            user_id: "user".to_string(),
+            family_id: None,
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            attributes: Default::default(),
+            scopes: None,
+            expires_in: 0,
+        };
+        let app = Router::new()
+            .route("/data", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::new(vec!["analyst".to_string(), "admin".to_string()]),
+                require_roles,
+            ))
+            .route_layer(middleware::from_fn(move |mut request: Request, next: Next| {
+                request.extensions_mut().insert(token.clone());
+                next.run(request)
+            }));
+        let request = Request::builder().uri("/data").body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_roles_are_enforced() {
+        assert_eq!(status_for(&["analyst"]).await, StatusCode::OK);
+        assert_eq!(status_for(&["viewer"]).await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(&[]).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_a_refresh_token_is_not_an_access_token() {
+        let mut config = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        config.api.refresh_token_secret = config.api.access_token_secret.clone();
+        let tokens = TokenService::new(config.clone(), Arc::new(TokenRepository::new(test_source())));
+        let family = tokens.new_token_family();
+
+        let refresh_token = tokens
+            .generate_refresh_token("u-1".to_string(), family.clone())
+            .await
+            .unwrap();
+        let access_token = tokens
+            .generate_access_token("u-1".to_string(), family, vec![], Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            verify_and_decode_jwt_token(&refresh_token, &config.api.access_token_secret).err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert!(verify_and_decode_jwt_token(&access_token, &config.api.access_token_secret).is_ok());
+    }
+}
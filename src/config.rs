@@ -1,51 +1,1054 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
 use std::net::{AddrParseError, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
 
-use anyhow::Result as AResult;
+use anyhow::{bail, Context, Result as AResult};
 use config::{Config, Environment, FileFormat};
 use dotenv::dotenv;
+use regex::Regex;
 use secrecy::Secret;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use tracing::info;
 
 use crate::errors::ApiError;
 use crate::errors::ApiError::ApplicationStartup;
+use crate::secrets;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AppConfig {
+    pub endpoints_dir: String,
+    #[serde(skip_deserializing)]
+    pub endpoints: Vec<EndpointConfig>,
+    /// The Snowflake account of the ODBC data sources that give no connection string of their own
+    #[serde(default)]
+    pub db: Option<DbConfig>,
+    /// Holds the nexus tables, and serves the endpoints that name no `datasource:`
+    #[serde(default)]
+    pub database: DataSourceConfig,
+    /// Databases that endpoints pick by name with `datasource:`, each with a pool of its own
+    #[serde(default)]
+    pub datasources: HashMap<String, DataSourceConfig>,
+    #[serde(default)]
+    pub odbc: OdbcConfig,
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// Sends the mails of the email verification and password reset flows. They are only logged without it.
+    #[serde(default)]
+    pub mailer: Option<MailerConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EndpointConfig {
     pub name: String,
-    pub request: HashMap<String, String>,
-    pub response: HashMap<String, String>,
+    pub endpoint: String,
+    #[serde(default)]
+    pub request: HashMap<String, ParamConfig>,
+    #[serde(default)]
+    pub response: HashMap<String, ColumnConfig>,
     pub sql: String,
-    //pub db: DbConfig,
-    pub api: ApiConfig,
+    #[serde(default)]
+    pub template_mode: TemplateMode,
+    /// Stream `application/json` responses as a chunked array instead of buffering the whole result
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    /// Roles allowed to call the endpoint; any authenticated user when empty
+    #[serde(default)]
+    pub allowed_roles: Vec<String>,
+    /// Name of the entry of `datasources:` that runs the queries of the endpoint, instead of `database:`
+    #[serde(default)]
+    pub datasource: Option<String>,
 }
 
-/*#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct DbConfig {
-    pub driver: String,
-    pub hostname: String,
-    pub username: String,
-    #[serde(skip_serializing)]
-    pub password: Secret<String>,
-    pub database: String,
-    pub warehouse: String,
-    pub schema: String,
-    pub role: String,
-}*/
+/// Paging of the buffered JSON responses through the `page_size` and `page_token` query parameters.
+///
+/// `offset` wraps the templated SQL with `LIMIT`/`OFFSET` and relies on the template for a stable order. `keyset`
+/// orders by `sort_key` and continues after the last key returned, which stays fast and consistent on large tables.
+/// Rows sharing a key at the end of a page would be skipped, so the key must either be unique (`sort_key_unique`) or be
+/// followed by a unique `tie_breaker` column.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaginationConfig {
+    #[serde(default)]
+    pub mode: PaginationMode,
+    #[serde(default = "default_page_size")]
+    pub default_page_size: usize,
+    #[serde(default = "max_page_size")]
+    pub max_page_size: usize,
+    pub sort_key: Option<String>,
+    #[serde(default)]
+    pub sort_key_unique: bool,
+    pub tie_breaker: Option<String>,
+    #[serde(default)]
+    pub include_total: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaginationMode {
+    #[default]
+    Offset,
+    Keyset,
+}
+
+fn default_page_size() -> usize {
+    100
+}
+
+fn max_page_size() -> usize {
+    1000
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            mode: PaginationMode::default(),
+            default_page_size: default_page_size(),
+            max_page_size: max_page_size(),
+            sort_key: None,
+            sort_key_unique: false,
+            tie_breaker: None,
+            include_total: false,
+        }
+    }
+}
+
+impl PaginationConfig {
+    /// The columns a keyset page is ordered by: `sort_key`, then `tie_breaker`
+    pub fn keyset_columns(&self) -> Vec<&str> {
+        self.sort_key
+            .iter()
+            .chain(&self.tie_breaker)
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn validate(&self, endpoint: &str, response: &HashMap<String, ColumnConfig>) -> AResult<()> {
+        if self.default_page_size == 0 || self.default_page_size > self.max_page_size {
+            bail!(
+                "default_page_size of endpoint {} must be between 1 and max_page_size",
+                endpoint
+            );
+        }
+        if self.mode == PaginationMode::Keyset {
+            let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
+            if self.sort_key.is_none() {
+                bail!(
+                    "keyset pagination of endpoint {} needs a sort_key column name",
+                    endpoint
+                );
+            }
+            if self.tie_breaker.is_none() && !self.sort_key_unique {
+                bail!(
+                    "keyset pagination of endpoint {} needs a tie_breaker column, or sort_key_unique: true",
+                    endpoint
+                );
+            }
+            for column in self.keyset_columns() {
+                if !identifier.is_match(column) {
+                    bail!("{} of endpoint {} is not a column name", column, endpoint);
+                }
+                if !response.keys().any(|name| name.eq_ignore_ascii_case(column)) {
+                    bail!(
+                        "the keyset column {} of endpoint {} must be declared in its response",
+                        column,
+                        endpoint
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Declaration of a response column, either just the type (`age: int`) or `{ type: int, mask: ... }`. Columns returned
+/// by the query but not declared here are typed from the column metadata of the database.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "ColumnConfigDef")]
+pub struct ColumnConfig {
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<MaskConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColumnConfigDef {
+    Type(ColumnType),
+    Full {
+        #[serde(rename = "type")]
+        column_type: ColumnType,
+        #[serde(default)]
+        mask: Option<MaskConfig>,
+    },
+}
+
+impl From<ColumnConfigDef> for ColumnConfig {
+    fn from(def: ColumnConfigDef) -> Self {
+        match def {
+            ColumnConfigDef::Type(column_type) => ColumnConfig {
+                column_type,
+                mask: None,
+            },
+            ColumnConfigDef::Full { column_type, mask } => ColumnConfig { column_type, mask },
+        }
+    }
+}
+
+/// Masking of a column for every caller holding none of `unmasked_roles`:
+///
+/// ```yaml
+/// name:
+///   type: String
+///   mask:
+///     policy: partial
+///     keep_last: 2
+///     unmasked_roles: [pii_reader]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MaskConfig {
+    pub policy: MaskPolicy,
+    #[serde(default)]
+    pub unmasked_roles: Vec<String>,
+    /// `partial` only: characters left visible at the start of the value
+    #[serde(default)]
+    pub keep_first: usize,
+    /// `partial` only: characters left visible at the end of the value
+    #[serde(default = "keep_last")]
+    pub keep_last: usize,
+}
+
+fn keep_last() -> usize {
+    4
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskPolicy {
+    /// Full HMAC-SHA256 of the value with `api.masking_secret`, in hex
+    Hash,
+    /// `*` in place of every character but `keep_first` and `keep_last`
+    Partial,
+    /// Always `null`
+    Null,
+    /// Keyed digest of the value (HMAC-SHA256 with `api.masking_secret`), stable across requests so that masked values
+    /// can still be joined and counted, but not looked up without the key
+    #[serde(alias = "tokenize")]
+    Tokenise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", rename_all = "lowercase")]
+pub enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Decimal,
+    Date,
+    Timestamp,
+    Json,
+}
+
+impl TryFrom<String> for ColumnType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "string" | "str" | "text" | "varchar" => Ok(ColumnType::String),
+            "int" | "integer" | "long" | "bigint" => Ok(ColumnType::Int),
+            "float" | "double" | "real" => Ok(ColumnType::Float),
+            "bool" | "boolean" => Ok(ColumnType::Bool),
+            "decimal" | "numeric" | "number" => Ok(ColumnType::Decimal),
+            "date" => Ok(ColumnType::Date),
+            "timestamp" | "datetime" => Ok(ColumnType::Timestamp),
+            "json" | "variant" | "object" | "array" => Ok(ColumnType::Json),
+            _ => Err(format!("unknown column type {}", value)),
+        }
+    }
+}
+
+/// Declaration of a single request parameter. Either just the type (`customer_id: String`) or the full form:
+///
+/// ```yaml
+/// age:
+///   type: int
+///   required: true
+///   min: 18
+///   max: 120
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "ParamConfigDef")]
+pub struct ParamConfig {
+    #[serde(rename = "type")]
+    pub param_type: ParamType,
+    pub required: bool,
+    pub default: Option<String>,
+    pub pattern: Option<String>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub values: Vec<String>,
+    pub items: Option<ParamType>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParamConfigDef {
+    Type(ParamType),
+    Full {
+        #[serde(rename = "type")]
+        param_type: ParamType,
+        #[serde(default)]
+        required: bool,
+        default: Option<Scalar>,
+        pattern: Option<String>,
+        min: Option<Scalar>,
+        max: Option<Scalar>,
+        #[serde(default)]
+        values: Vec<Scalar>,
+        items: Option<ParamType>,
+    },
+}
+
+/// Scalar YAML value (`18`, `true`, `F`) kept as text, to be coerced to the declared parameter type later
+struct Scalar(String);
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ScalarVisitor;
+
+        impl<'de> Visitor<'de> for ScalarVisitor {
+            type Value = Scalar;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string, number or boolean")
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+impl From<ParamConfigDef> for ParamConfig {
+    fn from(def: ParamConfigDef) -> Self {
+        match def {
+            ParamConfigDef::Type(param_type) => ParamConfig {
+                param_type,
+                required: false,
+                default: None,
+                pattern: None,
+                min: None,
+                max: None,
+                values: vec![],
+                items: None,
+            },
+            ParamConfigDef::Full {
+                param_type,
+                required,
+                default,
+                pattern,
+                min,
+                max,
+                values,
+                items,
+            } => ParamConfig {
+                param_type,
+                required,
+                default: default.map(|s| s.0),
+                pattern,
+                min: min.map(|s| s.0),
+                max: max.map(|s| s.0),
+                values: values.into_iter().map(|s| s.0).collect(),
+                items,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", rename_all = "lowercase")]
+pub enum ParamType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+    Timestamp,
+    Enum,
+    List,
+}
+
+impl TryFrom<String> for ParamType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "string" | "str" | "text" | "varchar" => Ok(ParamType::String),
+            "int" | "integer" | "long" | "bigint" => Ok(ParamType::Int),
+            "float" | "double" | "number" | "decimal" => Ok(ParamType::Float),
+            "bool" | "boolean" => Ok(ParamType::Bool),
+            "date" => Ok(ParamType::Date),
+            "timestamp" | "datetime" => Ok(ParamType::Timestamp),
+            "enum" => Ok(ParamType::Enum),
+            "list" | "array" => Ok(ParamType::List),
+            _ => Err(format!("unknown parameter type {}", value)),
+        }
+    }
+}
+
+/// How request parameters reach the SQL of an endpoint.
+///
+/// `bind` turns every `{{param}}` into a `?` marker and binds the value as a statement parameter, so the value can never
+/// change the statement; tags such as `{% if %}` still see the values, and filters are refused. `render` substitutes the
+/// raw text into the SQL and must only be used with trusted values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateMode {
+    #[default]
+    Bind,
+    Render,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiConfig {
     pub port: u16,
     pub host: String,
-    pub endpoint: String,
     pub rust_log: String,
     #[serde(skip_serializing)]
     pub access_token_secret: String,
     pub access_token_expires_in: String,
     pub access_token_max_age: usize,
     #[serde(skip_serializing)]
-    pub password_salt: String,
+    pub refresh_token_secret: String,
+    pub refresh_token_expires_in: String,
+    pub refresh_token_max_age: usize,
+    /// How long the revocation status of an access token is cached before the token store is asked again
+    #[serde(default = "revocation_cache_ttl_secs")]
+    pub revocation_cache_ttl_secs: u64,
+    /// Longest lifetime of an API key, also given to keys created without an expiry
+    #[serde(default = "api_key_max_age_days")]
+    pub api_key_max_age_days: u32,
+    #[serde(default)]
+    pub password_hashing: PasswordHashingConfig,
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+    #[serde(default)]
+    pub login_throttling: LoginThrottlingConfig,
+    /// Signs the email verification and password reset tokens
+    #[serde(default = "empty_secret", skip_serializing)]
+    pub account_token_secret: Secret<String>,
+    /// Who may call `/api/users/register`
+    #[serde(default)]
+    pub registration: RegistrationMode,
+    /// Longest lifetime of an invite, also given to invites created without an expiry
+    #[serde(default = "invite_max_age_days")]
+    pub invite_max_age_days: u32,
+    /// New users have to verify their email address before they can log in
+    #[serde(default = "require_email_verification")]
+    pub require_email_verification: bool,
+    #[serde(default = "email_verification_max_age_mins")]
+    pub email_verification_max_age_mins: u64,
+    #[serde(default = "password_reset_max_age_mins")]
+    pub password_reset_max_age_mins: u64,
+    /// Key of the `hash` and `tokenise` column masking policies
+    #[serde(default = "empty_secret", skip_serializing)]
+    pub masking_secret: Secret<String>,
+}
+
+fn empty_secret() -> Secret<String> {
+    Secret::new(String::new())
+}
+
+fn revocation_cache_ttl_secs() -> u64 {
+    30
+}
+
+fn api_key_max_age_days() -> u32 {
+    365
+}
+
+fn invite_max_age_days() -> u32 {
+    7
+}
+
+fn require_email_verification() -> bool {
+    true
+}
+
+fn email_verification_max_age_mins() -> u64 {
+    24 * 60
+}
+
+fn password_reset_max_age_mins() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    /// Anyone who reaches the service can register; an invite code is optional
+    #[default]
+    Open,
+    /// Registering takes an invite code created by an admin
+    InviteOnly,
+    /// Nobody can register; users are created with `nexus create-admin`
+    Closed,
+}
+
+/// Argon2id cost parameters. Stored hashes made with other parameters are replaced at the next login of their user.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasswordHashingConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// The RFC 9106 recommendation for memory-constrained environments
+impl Default for PasswordHashingConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 65536,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Rules a new password must follow
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasswordPolicyConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// A file of known breached passwords, one per line, that cannot be used
+    pub breached_passwords_file: Option<String>,
+}
+
+impl Default for PasswordPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            breached_passwords_file: None,
+        }
+    }
+}
+
+/// Limits on failed logins, counted per account and per client address. The n-th failure makes the next attempt wait
+/// `base_delay_secs * 2^(n-1)` seconds, up to `max_delay_secs`, and the last allowed failure locks out for
+/// `lockout_secs`. Failures are forgotten `failure_window_secs` after the last one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoginThrottlingConfig {
+    pub enabled: bool,
+    pub max_account_failures: u32,
+    pub max_address_failures: u32,
+    pub base_delay_secs: u64,
+    pub max_delay_secs: u64,
+    pub lockout_secs: u64,
+    pub failure_window_secs: u64,
+    /// Read the client address from `X-Forwarded-For`, which only a proxy in front of nexus may set
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for LoginThrottlingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_account_failures: 5,
+            max_address_failures: 20,
+            base_delay_secs: 1,
+            max_delay_secs: 60,
+            lockout_secs: 900,
+            failure_window_secs: 3600,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+/// Where the mails nexus sends go:
+///
+/// ```yaml
+/// mailer:
+///   from: nexus <no-reply@example.com>
+///   link_base_url: https://portal.example.com
+///   transport: smtp
+///   host: smtp.example.com
+///   username: nexus
+///   password: ...
+/// ```
+///
+/// `transport: file` writes each mail to `directory` instead, and `transport: log` logs it, so that the flows can be
+/// tried without a mail server.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MailerConfig {
+    pub from: String,
+    /// Mails link to `<link_base_url>/verify-email?token=...` and `<link_base_url>/reset-password?token=...`, pages
+    /// that post the token to the API. Without it, mails carry the bare token.
+    #[serde(default)]
+    pub link_base_url: Option<String>,
+    #[serde(flatten)]
+    pub transport: MailTransportConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum MailTransportConfig {
+    Smtp(SmtpConfig),
+    File { directory: String },
+    Log,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the port of the `tls` mode: 587, 465 or 25
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade the connection with STARTTLS, as on port 587
+    #[default]
+    Starttls,
+    /// Connect over TLS, as on port 465
+    Tls,
+    /// Plain text, for a local relay only
+    None,
+}
+
+/// A database that queries run against, picked by `backend`:
+///
+/// ```yaml
+/// datasources:
+///   warehouse_eu:
+///     backend: odbc
+///     dsn: snowflake_eu
+///     pool:
+///       min_size: 1
+///       max_size: 8
+///       validation_query: SELECT 1
+///     username: NEXUS_READER
+///     password_file: /run/secrets/snowflake_eu
+///   operations:
+///     backend: postgres
+///     url: postgres://ops-db.internal/operations
+///     username_env: OPS_DB_USER
+///     password_env: OPS_DB_PASSWORD
+/// ```
+///
+/// `backend: odbc` takes either a full `connection_string` or the name of a `dsn`, and otherwise connects to the `db:`
+/// account, or to `DSN=snowflake` without one. `backend: postgres` connects to a `url` such as
+/// `postgres://localhost/nexus`, and `backend: sqlite` opens the file at `path`, which is created when missing.
+/// `table_prefix` qualifies the nexus tables, so that `nexus_db.public` makes them `nexus_db.public.nexus_users` and so
+/// on.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum DataSourceConfig {
+    /// Any database with an ODBC driver, Snowflake by default
+    Odbc {
+        #[serde(default)]
+        connection_string: Option<String>,
+        #[serde(default)]
+        dsn: Option<String>,
+        #[serde(default)]
+        pool: PoolConfig,
+        #[serde(default = "odbc_table_prefix")]
+        table_prefix: Option<String>,
+        #[serde(flatten)]
+        credentials: Credentials,
+    },
+    /// PostgreSQL over its native protocol, without TLS
+    Postgres {
+        url: String,
+        #[serde(default)]
+        pool: PoolConfig,
+        #[serde(default)]
+        table_prefix: Option<String>,
+        #[serde(flatten)]
+        credentials: Credentials,
+    },
+    Sqlite {
+        /// A file, or `:memory:` for a database that lives as long as the process on a single connection
+        path: String,
+        #[serde(default)]
+        pool: PoolConfig,
+        #[serde(default)]
+        table_prefix: Option<String>,
+        /// SQL run once when the database is opened, such as `schema/sqlite.sql` to create the nexus tables
+        #[serde(default)]
+        init_script: Option<String>,
+    },
+}
+
+impl DataSourceConfig {
+    pub fn pool(&self) -> &PoolConfig {
+        match self {
+            DataSourceConfig::Odbc { pool, .. }
+            | DataSourceConfig::Postgres { pool, .. }
+            | DataSourceConfig::Sqlite { pool, .. } => pool,
+        }
+    }
+}
+
+impl Default for DataSourceConfig {
+    fn default() -> Self {
+        DataSourceConfig::Odbc {
+            connection_string: None,
+            dsn: None,
+            pool: PoolConfig::default(),
+            table_prefix: odbc_table_prefix(),
+            credentials: Credentials::default(),
+        }
+    }
+}
+
+fn odbc_table_prefix() -> Option<String> {
+    Some("nexus_db.public".to_string())
+}
+
+/// The connections of a data source. Idle connections are closed after `idle_timeout_secs` and every connection
+/// after `max_lifetime_secs`, 0 for never, down to `min_size`. A connection is checked with `validation_query` before it is handed
+/// out, and replaced when the query fails.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PoolConfig {
+    #[serde(default)]
+    pub min_size: u32,
+    #[serde(default = "pool_max_size")]
+    pub max_size: u32,
+    /// How long a query waits for a connection before it fails
+    #[serde(default = "pool_acquire_timeout_secs")]
+    pub acquire_timeout_secs: u64,
+    #[serde(default = "pool_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    #[serde(default = "pool_max_lifetime_secs")]
+    pub max_lifetime_secs: u64,
+    /// Such as `SELECT 1`
+    #[serde(default)]
+    pub validation_query: Option<String>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 0,
+            max_size: pool_max_size(),
+            acquire_timeout_secs: pool_acquire_timeout_secs(),
+            idle_timeout_secs: pool_idle_timeout_secs(),
+            max_lifetime_secs: pool_max_lifetime_secs(),
+            validation_query: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.acquire_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        Some(self.idle_timeout_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        Some(self.max_lifetime_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    fn validate(&self, name: &str) -> AResult<()> {
+        if self.max_size == 0 || self.min_size > self.max_size {
+            bail!(
+                "The pool of the data source {} needs 0 <= min_size <= max_size and max_size > 0",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+fn pool_max_size() -> u32 {
+    4
+}
+
+fn pool_acquire_timeout_secs() -> u64 {
+    30
+}
+
+fn pool_idle_timeout_secs() -> u64 {
+    600
+}
+
+fn pool_max_lifetime_secs() -> u64 {
+    1800
+}
+
+/// How ODBC calls are run. They block, so the queries of the repositories, and the wait for a pooled connection (opening
+/// it if need be), run on one of `threads` dedicated threads; calls beyond that wait for a thread to be free. A
+/// streamed query reads its rows on a thread of its own instead, so that a slow consumer never holds one of them;
+/// `threads` does not limit those, the `max_size` of the pool does. The pool opens its `min_size` connections and
+/// closes expired ones on threads of r2d2.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OdbcConfig {
+    #[serde(default = "odbc_threads")]
+    pub threads: usize,
+}
+
+impl Default for OdbcConfig {
+    fn default() -> Self {
+        Self {
+            threads: odbc_threads(),
+        }
+    }
+}
+
+fn odbc_threads() -> usize {
+    8
+}
+
+/// The user and password a data source logs in with, kept out of the configuration file. The username is given inline
+/// or, like the password, read from the environment variable named by `*_env` or from the secret file at `*_file`.
+/// What is not given either way is read from `vault`, when set.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Credentials {
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub username_env: Option<String>,
+    #[serde(default)]
+    pub username_file: Option<String>,
+    #[serde(default)]
+    pub password_env: Option<String>,
+    #[serde(default)]
+    pub password_file: Option<String>,
+    #[serde(default)]
+    pub vault: Option<VaultConfig>,
+}
+
+impl Credentials {
+    /// Reads the username and the password, asking the vault at most once
+    pub fn resolve(&self) -> AResult<(Option<String>, Option<Secret<String>>)> {
+        let username = read_credential(
+            "username",
+            self.username.as_deref(),
+            self.username_env.as_deref(),
+            self.username_file.as_deref(),
+        )?;
+        let password = read_credential(
+            "password",
+            None,
+            self.password_env.as_deref(),
+            self.password_file.as_deref(),
+        )?;
+        let Some(vault) = self.vault.as_ref().filter(|_| username.is_none() || password.is_none()) else {
+            return Ok((username, password.map(Secret::new)));
+        };
+
+        let mut secret = secrets::read_vault_secret(vault)?;
+        let username = username.or_else(|| secret.remove(&vault.username_key));
+        let password = match password {
+            Some(password) => password,
+            None => secret
+                .remove(&vault.password_key)
+                .with_context(|| format!("The vault secret {} has no {}", vault.path, vault.password_key))?,
+        };
+        Ok((username, Some(Secret::new(password))))
+    }
+}
+
+fn read_credential(name: &str, inline: Option<&str>, env: Option<&str>, file: Option<&str>) -> AResult<Option<String>> {
+    match (inline, env, file) {
+        (None, None, None) => Ok(None),
+        (Some(value), None, None) => Ok(Some(value.to_string())),
+        (None, Some(env), None) => std::env::var(env)
+            .map(Some)
+            .with_context(|| format!("The environment variable {} holding the {} is not set", env, name)),
+        (None, None, Some(file)) => {
+            let value =
+                fs::read_to_string(file).with_context(|| format!("Unable to read the {} from {}", name, file))?;
+            Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        _ => bail!("The {} of a data source must come from a single place", name),
+    }
+}
+
+/// A secret of a Vault server, or of anything answering `GET {address}/v1/{path}` the same way. Both the KV version 2
+/// layout (`data.data`) and the flat one (`data`) are read.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct VaultConfig {
+    pub address: String,
+    /// Such as `secret/data/nexus/snowflake`
+    pub path: String,
+    /// The environment variable holding the token sent as `X-Vault-Token`
+    #[serde(default = "vault_token_env")]
+    pub token_env: String,
+    /// A file holding the token instead, such as a mounted Kubernetes secret
+    #[serde(default)]
+    pub token_file: Option<String>,
+    #[serde(default = "vault_username_key")]
+    pub username_key: String,
+    #[serde(default = "vault_password_key")]
+    pub password_key: String,
+    #[serde(default = "vault_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn vault_token_env() -> String {
+    "VAULT_TOKEN".to_string()
+}
+
+fn vault_username_key() -> String {
+    "username".to_string()
+}
+
+fn vault_password_key() -> String {
+    "password".to_string()
+}
+
+fn vault_timeout_secs() -> u64 {
+    10
+}
+
+/// The Snowflake account nexus connects to over ODBC when a data source gives neither a `connection_string` nor a
+/// `dsn`:
+///
+/// ```yaml
+/// db:
+///   driver: SnowflakeDSIIDriver
+///   hostname: gxs-dev.snowflakecomputing.com
+///   database: nexus_db
+///   schema: public
+///   warehouse: nexus_wh
+///   role: nexus_reader
+///   username_env: SNOWFLAKE_USER
+///   password_file: /var/run/secrets/snowflake/password
+/// ```
+///
+/// The credentials are read once, when the configuration is loaded, into `username` and `password`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "DbConfigFile")]
+pub struct DbConfig {
+    pub driver: String,
+    pub hostname: String,
+    pub database: String,
+    pub schema: String,
+    pub warehouse: String,
+    pub role: String,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password: Secret<String>,
+}
+
+/// The `db:` block as it is written, with credentials still to be read
+#[derive(Deserialize)]
+struct DbConfigFile {
+    driver: String,
+    hostname: String,
+    database: String,
+    schema: String,
+    warehouse: String,
+    role: String,
+    #[serde(flatten)]
+    credentials: Credentials,
+}
+
+impl TryFrom<DbConfigFile> for DbConfig {
+    type Error = String;
+
+    fn try_from(file: DbConfigFile) -> Result<Self, Self::Error> {
+        let (username, password) = file
+            .credentials
+            .resolve()
+            .map_err(|e| format!("Unable to read the db credentials: {:#}", e))?;
+        Ok(Self {
+            driver: file.driver,
+            hostname: file.hostname,
+            database: file.database,
+            schema: file.schema,
+            warehouse: file.warehouse,
+            role: file.role,
+            username: username.ok_or("The db block needs a username")?,
+            password: password.ok_or("The db block needs a password")?,
+        })
+    }
+}
+
+/// An external OpenID Connect provider whose JWTs are accepted next to the tokens nexus issues itself:
+///
+/// ```yaml
+/// oidc:
+///   issuer: https://idp.example.com/realms/data
+///   audience: nexus
+///   jwks_url: https://idp.example.com/realms/data/protocol/openid-connect/certs
+///   roles_claim: realm_access.roles
+///   role_mapping: { data-analysts: analyst }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// The JWKS endpoint of the provider; either this or `jwks_file`
+    pub jwks_url: Option<String>,
+    /// A local copy of the provider keys
+    pub jwks_file: Option<String>,
+    /// How long the keys are used before they are read again. A token signed by an unknown key also triggers a read.
+    #[serde(default = "jwks_refresh_secs")]
+    pub jwks_refresh_secs: u64,
+    #[serde(default = "user_id_claim")]
+    pub user_id_claim: String,
+    /// Claim holding the roles or groups of the user, as a list or a single string. Dots reach into nested claims.
+    pub roles_claim: Option<String>,
+    /// Provider role to nexus role. Provider roles without a mapping grant nothing.
+    #[serde(default)]
+    pub role_mapping: HashMap<String, String>,
+    /// nexus attribute (`auth.attributes.<name>` in templates) to the claim it is read from
+    #[serde(default)]
+    pub attribute_claims: HashMap<String, String>,
+}
+
+fn jwks_refresh_secs() -> u64 {
+    3600
+}
+
+fn user_id_claim() -> String {
+    "sub".to_string()
 }
 
 impl AppConfig {
@@ -62,11 +1065,74 @@ impl AppConfig {
             )
             .build()?;
 
-        let app_cfg: AppConfig = config.try_deserialize()?;
+        let mut app_cfg: AppConfig = config.try_deserialize()?;
+        app_cfg.endpoints = Self::load_endpoints(&app_cfg.endpoints_dir)?;
+        app_cfg.check_datasources()?;
         info!("Loaded configuration: {:?}", app_cfg);
 
         Ok(app_cfg)
     }
+
+    pub fn load_endpoints(endpoints_dir: &str) -> AResult<Vec<EndpointConfig>> {
+        let mut paths = fs::read_dir(endpoints_dir)
+            .with_context(|| format!("Unable to read endpoints directory {}", endpoints_dir))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| is_yaml_file(path))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut names = HashSet::new();
+        let mut routes = HashSet::new();
+        let mut endpoints = Vec::with_capacity(paths.len());
+        for path in paths {
+            let endpoint: EndpointConfig = Config::builder()
+                .add_source(config::File::from(path.as_path()).format(FileFormat::Yaml))
+                .build()
+                .and_then(|cfg| cfg.try_deserialize())
+                .with_context(|| format!("Unable to load endpoint configuration {}", path.display()))?;
+
+            if !names.insert(endpoint.name.clone()) {
+                bail!("Duplicate endpoint name {} in {}", endpoint.name, path.display());
+            }
+            if !routes.insert(endpoint.endpoint.clone()) {
+                bail!("Duplicate endpoint path {} in {}", endpoint.endpoint, path.display());
+            }
+            endpoint.pagination.validate(&endpoint.name, &endpoint.response)?;
+            info!("Loaded endpoint {} from {}", endpoint.name, path.display());
+            endpoints.push(endpoint);
+        }
+
+        if endpoints.is_empty() {
+            bail!("No endpoint definitions found in {}", endpoints_dir);
+        }
+        Ok(endpoints)
+    }
+
+    /// Fails on an endpoint that names a data source missing from `datasources:`, and on an impossible pool
+    fn check_datasources(&self) -> AResult<()> {
+        self.database.pool().validate("database")?;
+        for (name, datasource) in &self.datasources {
+            datasource.pool().validate(name)?;
+        }
+        for endpoint in &self.endpoints {
+            if let Some(datasource) = &endpoint.datasource {
+                if !self.datasources.contains_key(datasource) {
+                    let mut known = self.datasources.keys().map(String::as_str).collect::<Vec<_>>();
+                    known.sort();
+                    bail!(
+                        "Endpoint {} uses the data source {}, which is not one of [{}]",
+                        endpoint.name,
+                        datasource,
+                        known.join(", ")
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_socket_address(app_cfg: &AppConfig) -> Result<SocketAddr, ApiError> {
         let address = format!("{}:{}", app_cfg.api.host, app_cfg.api.port);
         address.parse::<SocketAddr>().map_err(|e: AddrParseError| {
@@ -76,19 +1142,59 @@ impl AppConfig {
     }
 }
 
+fn is_yaml_file(path: &Path) -> bool {
+    path.is_file() && matches!(path.extension().and_then(|ext| ext.to_str()), Some("yaml" | "yml"))
+}
+
 #[cfg(test)]
 mod tests {
-    use secrecy::ExposeSecret;
+    use secrecy::{ExposeSecret, Secret};
 
-    use crate::config::AppConfig;
+    use crate::config::{AppConfig, DataSourceConfig, MailTransportConfig, MailerConfig, SmtpConfig, SmtpTls};
 
     #[test]
     fn test_file_and_dotenv_load() {
-        let app_cfg = AppConfig::get_configuration("tests/test_customer_master.yaml").unwrap();
-        assert_eq!(app_cfg.name, "customer_master");
-        assert_eq!(app_cfg.api.endpoint, "/api/nexus/customer_master");
+        let app_cfg = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        assert_eq!(app_cfg.endpoints.len(), 1);
+        assert_eq!(app_cfg.endpoints[0].name, "customer_master");
+        assert_eq!(app_cfg.endpoints[0].endpoint, "/api/nexus/customer_master");
         assert_eq!(app_cfg.api.port, 8080);
-        assert_ne!(app_cfg.db.username, "PLACEHOLDER_USERNAME");
-        assert_ne!(app_cfg.db.password.expose_secret(), "PLACEHOLDER_PASSWORD");
+        let DataSourceConfig::Postgres { pool, credentials, .. } = &app_cfg.datasources["operations"] else {
+            panic!("operations must be a postgres data source");
+        };
+        assert_eq!(pool.max_size, 2);
+        assert_eq!(pool.validation_query.as_deref(), Some("SELECT 1"));
+        assert_eq!(credentials.username.as_deref(), Some("nexus"));
+        assert_eq!(credentials.password_env.as_deref(), Some("OPS_DB_PASSWORD"));
+        let db = app_cfg.db.as_ref().unwrap();
+        assert_ne!(db.username, "PLACEHOLDER_USERNAME");
+        assert_ne!(db.password.expose_secret(), "PLACEHOLDER_PASSWORD");
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_when_the_configuration_is_logged() {
+        let mut app_cfg = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        app_cfg.mailer = Some(MailerConfig {
+            from: "nexus@example.com".to_string(),
+            link_base_url: None,
+            transport: MailTransportConfig::Smtp(SmtpConfig {
+                host: "smtp.example.com".to_string(),
+                port: None,
+                username: Some("nexus".to_string()),
+                password: Some(Secret::new("smtp-password".to_string())),
+                tls: SmtpTls::Starttls,
+            }),
+        });
+        app_cfg.api.masking_secret = Secret::new("masking-secret".to_string());
+        let logged = format!("{:?}", app_cfg);
+        assert!(logged.contains("smtp.example.com"));
+        for secret in [
+            app_cfg.api.account_token_secret.expose_secret().as_str(),
+            "masking-secret",
+            "smtp-password",
+            "not-a-real-password",
+        ] {
+            assert!(!logged.contains(secret), "{} is logged", secret);
+        }
     }
 }
@@ -0,0 +1,114 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Context};
+use tokio::sync::oneshot;
+use tracing::error;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads for blocking database calls, so that however many slow queries are running, they never
+/// take the Tokio workers that serve every other request. Work waits in a queue while all threads are busy.
+pub struct BlockingExecutor {
+    name: String,
+    sender: Mutex<Sender<Job>>,
+}
+
+impl BlockingExecutor {
+    pub fn new(name: &str, threads: usize) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("{}-{}", name, i))
+                .spawn(move || work(&receiver))
+                .with_context(|| format!("Unable to start the {} threads", name))?;
+        }
+        Ok(Self {
+            name: name.to_string(),
+            sender: Mutex::new(sender),
+        })
+    }
+
+    /// Runs `task` on one of the threads once one is free, and waits for its result
+    pub async fn run<T: Send + 'static>(&self, task: impl FnOnce() -> T + Send + 'static) -> anyhow::Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.spawn(move || {
+            let _ = sender.send(task());
+        })?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("A task of the {} threads panicked", self.name))
+    }
+
+    /// Queues `task` without waiting for it
+    pub fn spawn(&self, task: impl FnOnce() + Send + 'static) -> anyhow::Result<()> {
+        self.sender
+            .lock()
+            .map_err(|_| anyhow!("The {} queue is poisoned", self.name))?
+            .send(Box::new(task))
+            .map_err(|_| anyhow!("The {} threads have stopped", self.name))
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        //The lock is only held while waiting, so that the other threads pick up work as soon as this one does
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            error!("A blocking task panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::datasource::executor::BlockingExecutor;
+
+    #[tokio::test]
+    async fn test_no_more_tasks_run_at_once_than_there_are_threads() {
+        let executor = Arc::new(BlockingExecutor::new("test", 2).unwrap());
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..6).map(|i| {
+            let executor = executor.clone();
+            let running = running.clone();
+            let most_running = most_running.clone();
+            tokio::spawn(async move {
+                executor
+                    .run(move || {
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_running.fetch_max(now_running, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        i
+                    })
+                    .await
+                    .unwrap()
+            })
+        });
+        let mut results = Vec::new();
+        for task in tasks.collect::<Vec<_>>() {
+            results.push(task.await.unwrap());
+        }
+
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+        assert!(executor.run(|| panic!("boom")).await.is_err());
+        assert_eq!(executor.run(|| 7).await.unwrap(), 7);
+    }
+}
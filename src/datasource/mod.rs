@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::config::{ColumnType, DataSourceConfig, DbConfig};
+use crate::datasource::executor::BlockingExecutor;
+use crate::datasource::pool::{PoolMetrics, PoolStatus};
+use crate::domain::params::ParamValue;
+use crate::repositories::sql_template::CompiledSql;
+
+pub mod executor;
+pub mod odbc;
+pub mod pool;
+pub mod postgres;
+pub mod sqlite;
+
+/// Number of fetched batches that may wait for a slow consumer before fetching pauses
+pub const FETCH_BUFFER_BATCHES: usize = 2;
+
+/// A statement with `?` placeholders and the values bound to them, in placeholder order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<ParamValue>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>, params: Vec<ParamValue>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+        }
+    }
+}
+
+impl From<CompiledSql> for Statement {
+    fn from(compiled: CompiledSql) -> Self {
+        Self::new(compiled.sql, compiled.params)
+    }
+}
+
+/// A fetched row. Values are read as text whatever the backend, and typed later by the endpoint or the repository.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Row(pub Vec<Option<String>>);
+
+impl Row {
+    pub fn get(&self, col: usize) -> Option<&str> {
+        self.0.get(col).and_then(|value| value.as_deref())
+    }
+
+    /// The value of the column, or an empty string when it is NULL
+    pub fn text(&self, col: usize) -> &str {
+        self.get(col).unwrap_or_default()
+    }
+}
+
+/// What a query produces while it is being fetched: the names and types of its columns once, then its rows
+#[derive(Debug)]
+pub enum FetchEvent {
+    Columns(Vec<(String, ColumnType)>),
+    Rows(Vec<Row>),
+}
+
+pub type FetchReceiver = mpsc::Receiver<anyhow::Result<FetchEvent>>;
+
+/// A database that nexus runs queries against. Statements use `?` placeholders on every backend, and the nexus tables
+/// are named through `table`, so that the repositories work unchanged on each of them.
+#[async_trait]
+pub trait DataSource: Send + Sync {
+    /// Name of the backend, for logs
+    fn backend(&self) -> &'static str;
+
+    /// The name of a nexus table, qualified by the `table_prefix` of the source
+    fn table(&self, name: &str) -> String;
+
+    /// Runs a query on a connection of its own and hands over its columns, then its rows `batch_size` at a time.
+    /// Fetching pauses while the receiver is full and stops once it is dropped.
+    async fn fetch(&self, statement: Statement, batch_size: usize) -> anyhow::Result<FetchReceiver>;
+
+    /// Runs a query and returns all its rows, for the small lookups of the repositories
+    async fn query(&self, statement: Statement) -> anyhow::Result<Vec<Row>>;
+
+    /// Runs a statement that returns no rows and returns the number of rows it changed
+    async fn execute(&self, statement: Statement) -> anyhow::Result<u64>;
+
+    /// Runs statements that return no rows in a single transaction, so that either all of them or none take effect,
+    /// and returns the number of rows they changed
+    async fn execute_batch(&self, statements: Vec<Statement>) -> anyhow::Result<u64>;
+
+    /// The connections of the pool of the source
+    fn pool_status(&self) -> PoolStatus;
+
+    /// Closes the connections that were idle or open for too long and opens those missing below the minimum size,
+    /// for pools that do not do it on their own
+    async fn maintain(&self) {}
+}
+
+/// Opens the database of the configuration
+pub fn from_config(
+    name: &str,
+    config: &DataSourceConfig,
+    db: Option<&DbConfig>,
+    odbc_executor: &Arc<BlockingExecutor>,
+) -> anyhow::Result<Arc<dyn DataSource>> {
+    let source: Arc<dyn DataSource> = match config {
+        DataSourceConfig::Odbc {
+            connection_string,
+            dsn,
+            pool,
+            table_prefix,
+            credentials,
+        } => Arc::new(odbc::OdbcDataSource::new(
+            &odbc::connection_string(connection_string.as_deref(), dsn.as_deref(), credentials, db)?,
+            pool,
+            table_prefix.clone(),
+            PoolMetrics::new(name),
+            odbc_executor.clone(),
+        )),
+        DataSourceConfig::Postgres {
+            url,
+            pool,
+            table_prefix,
+            credentials,
+        } => Arc::new(postgres::PostgresDataSource::new(
+            url,
+            credentials,
+            pool,
+            table_prefix.clone(),
+            PoolMetrics::new(name),
+        )?),
+        DataSourceConfig::Sqlite {
+            path,
+            pool,
+            table_prefix,
+            init_script,
+        } => Arc::new(sqlite::SqliteDataSource::new(
+            path,
+            pool,
+            table_prefix.clone(),
+            init_script.as_deref(),
+            PoolMetrics::new(name),
+        )?),
+    };
+    info!("Using a {} database as {}", source.backend(), name);
+    pool::spawn_maintenance(source.clone(), PoolMetrics::new(name));
+    Ok(source)
+}
+
+/// Opens every named data source, each with a pool of its own
+pub fn from_configs(
+    configs: &HashMap<String, DataSourceConfig>,
+    db: Option<&DbConfig>,
+    odbc_executor: &Arc<BlockingExecutor>,
+) -> anyhow::Result<HashMap<String, Arc<dyn DataSource>>> {
+    configs
+        .iter()
+        .map(|(name, config)| {
+            let source = from_config(name, config, db, odbc_executor)
+                .with_context(|| format!("Unable to open the data source {}", name))?;
+            Ok((name.clone(), source))
+        })
+        .collect()
+}
+
+/// Qualifies a table name with the prefix of a source
+fn qualify(table_prefix: Option<&str>, name: &str) -> String {
+    match table_prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}.{}", prefix, name),
+        _ => name.to_string(),
+    }
+}
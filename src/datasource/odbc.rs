@@ -0,0 +1,447 @@
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use axum_odbc::odbc::buffers::TextRowSet;
+use axum_odbc::odbc::parameter::{InputParameter, WithDataType};
+use axum_odbc::odbc::{
+    self, sys, Bit, Connection, ConnectionOptions, Cursor, DataType, Environment, IntoParameter, ResultSetMetadata,
+};
+use chrono::{Datelike, Timelike};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use secrecy::ExposeSecret;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::config::{ColumnType, Credentials, DbConfig, PoolConfig};
+use crate::datasource::executor::BlockingExecutor;
+use crate::datasource::pool::{r2d2_pool, PoolMetrics, PoolStatus};
+use crate::datasource::{qualify, DataSource, FetchEvent, FetchReceiver, Row, Statement, FETCH_BUFFER_BATCHES};
+use crate::domain::params::ParamValue;
+
+const DEFAULT_CONNECTION_STRING: &str = "DSN=snowflake";
+/// Longest text read from a column by `query`, which serves the nexus tables
+const QUERY_MAX_TEXT_LENGTH: usize = 4096;
+const QUERY_BATCH_SIZE: usize = 100;
+
+/// Any database with an ODBC driver, Snowflake in particular. Connections are taken from the pool, and the queries of
+/// the repositories run, on the threads of `executor`; a streamed query reads its rows on a thread of its own.
+pub struct OdbcDataSource {
+    pool: Pool<OdbcConnectionManager>,
+    table_prefix: Option<String>,
+    metrics: PoolMetrics,
+    executor: Arc<BlockingExecutor>,
+}
+
+impl OdbcDataSource {
+    pub fn new(
+        connection_string: &str,
+        pool: &PoolConfig,
+        table_prefix: Option<String>,
+        metrics: PoolMetrics,
+        executor: Arc<BlockingExecutor>,
+    ) -> Self {
+        let manager = OdbcConnectionManager {
+            connection_string: connection_string.to_string(),
+            validation_query: pool.validation_query.clone(),
+        };
+        Self {
+            pool: r2d2_pool(manager, pool),
+            table_prefix,
+            metrics,
+            executor,
+        }
+    }
+
+    /// Runs `work` on a connection of the pool, on the executor. The wait for a connection includes the wait for a
+    /// thread.
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(PooledConnection<OdbcConnectionManager>) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let pool = self.pool.clone();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        self.executor
+            .run(move || {
+                let conn = pool.get().context("Unable to get an ODBC connection")?;
+                metrics.acquired(started);
+                work(conn)
+            })
+            .await?
+    }
+}
+
+/// Opens the connections of an ODBC pool, and tells which of them can still be used
+pub struct OdbcConnectionManager {
+    connection_string: String,
+    validation_query: Option<String>,
+}
+
+impl ManageConnection for OdbcConnectionManager {
+    type Connection = OdbcConnection;
+    type Error = odbc::Error;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        let conn =
+            environment()?.connect_with_connection_string(&self.connection_string, ConnectionOptions::default())?;
+        Ok(OdbcConnection { conn, in_batch: false })
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        if let Some(validation_query) = &self.validation_query {
+            conn.execute(validation_query, ())?;
+        }
+        Ok(())
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.in_batch || conn.is_dead().unwrap_or(true)
+    }
+}
+
+/// A connection of the pool. One left `in_batch`, with autocommit off, by a batch that failed to end or panicked is
+/// closed instead of returning to the pool, where later writes on it would never be committed.
+pub struct OdbcConnection {
+    conn: Connection<'static>,
+    in_batch: bool,
+}
+
+impl Deref for OdbcConnection {
+    type Target = Connection<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+fn environment() -> Result<&'static Environment, odbc::Error> {
+    static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
+    if let Some(environment) = ENVIRONMENT.get() {
+        return Ok(environment);
+    }
+    let environment = Environment::new()?;
+    Ok(ENVIRONMENT.get_or_init(|| environment))
+}
+
+/// The connection string of an ODBC data source: its `connection_string` or `dsn`, or else the `db:` account, or else
+/// `DSN=snowflake`, with the user and password of `credentials` appended
+pub fn connection_string(
+    connection_string: Option<&str>,
+    dsn: Option<&str>,
+    credentials: &Credentials,
+    db: Option<&DbConfig>,
+) -> anyhow::Result<String> {
+    let mut connection_string = match (connection_string, dsn, db) {
+        (Some(_), Some(_), _) => bail!("An ODBC data source takes either a connection_string or a dsn"),
+        (Some(connection_string), None, _) => connection_string.trim_end_matches(';').to_string(),
+        (None, Some(dsn), _) => format!("DSN={}", attribute_value(dsn)),
+        (None, None, Some(db)) if credentials == &Credentials::default() => return Ok(db_connection_string(db)),
+        (None, None, Some(_)) => bail!("An ODBC data source using the db account takes its credentials from db"),
+        (None, None, None) => DEFAULT_CONNECTION_STRING.to_string(),
+    };
+    let (username, password) = credentials.resolve()?;
+    if let Some(username) = username {
+        connection_string.push_str(&format!(";UID={}", attribute_value(&username)));
+    }
+    if let Some(password) = password {
+        connection_string.push_str(&format!(";PWD={}", attribute_value(password.expose_secret())));
+    }
+    Ok(connection_string)
+}
+
+/// The connection string of the Snowflake account of the `db:` block
+pub fn db_connection_string(db: &DbConfig) -> String {
+    format!(
+        "Driver={};server={};database={};schema={};warehouse={};role={};UID={};PWD={}",
+        attribute_value(&db.driver),
+        attribute_value(&db.hostname),
+        attribute_value(&db.database),
+        attribute_value(&db.schema),
+        attribute_value(&db.warehouse),
+        attribute_value(&db.role),
+        attribute_value(&db.username),
+        attribute_value(db.password.expose_secret())
+    )
+}
+
+/// Braces a value that would otherwise end its attribute early
+fn attribute_value(value: &str) -> String {
+    if value.contains([';', '{', '}']) || value.starts_with(' ') || value.ends_with(' ') {
+        format!("{{{}}}", value.replace('}', "}}"))
+    } else {
+        value.to_string()
+    }
+}
+
+#[async_trait]
+impl DataSource for OdbcDataSource {
+    fn backend(&self) -> &'static str {
+        "odbc"
+    }
+
+    fn table(&self, name: &str) -> String {
+        qualify(self.table_prefix.as_deref(), name)
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus::of_r2d2(&self.pool)
+    }
+
+    async fn fetch(&self, statement: Statement, batch_size: usize) -> anyhow::Result<FetchReceiver> {
+        let conn = self.with_connection(Ok).await?;
+        spawn_fetch(conn, move |conn, on_event| {
+            fetch_batches(conn, statement, batch_size, None, on_event)
+        })
+    }
+
+    async fn query(&self, statement: Statement) -> anyhow::Result<Vec<Row>> {
+        self.with_connection(move |conn| {
+            let mut rows = Vec::new();
+            fetch_batches(
+                &conn,
+                statement,
+                QUERY_BATCH_SIZE,
+                Some(QUERY_MAX_TEXT_LENGTH),
+                |event| {
+                    if let FetchEvent::Rows(batch) = event {
+                        rows.extend(batch);
+                    }
+                    true
+                },
+            )?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    async fn execute(&self, statement: Statement) -> anyhow::Result<u64> {
+        self.with_connection(move |conn| execute_statement(&conn, statement))
+            .await
+    }
+
+    /// Turns autocommit off for the statements, and back on before the connection returns to the pool. Unless that
+    /// succeeds, the connection stays `in_batch` and the pool closes it.
+    async fn execute_batch(&self, statements: Vec<Statement>) -> anyhow::Result<u64> {
+        self.with_connection(move |mut conn| {
+            conn.in_batch = true;
+            conn.set_autocommit(false)?;
+            let result = statements.into_iter().try_fold(0, |changed, statement| {
+                Ok(changed + execute_statement(&conn, statement)?)
+            });
+            match &result {
+                Ok(_) => conn.commit()?,
+                Err(_) => conn.rollback()?,
+            }
+            conn.set_autocommit(true)?;
+            conn.in_batch = false;
+            result
+        })
+        .await
+    }
+}
+
+fn execute_statement(conn: &Connection<'_>, statement: Statement) -> anyhow::Result<u64> {
+    let bound_params = bind_params(statement.params);
+    let mut prepared = conn.prepare(&statement.sql)?;
+    prepared.execute(bound_params.as_slice())?;
+    Ok(prepared.row_count()?.unwrap_or_default() as u64)
+}
+
+/// Runs `fetch` on a thread of its own, which waits whenever the consumer falls behind and holds its connection until
+/// the stream ends. On the executor, a few stalled consumers would take every thread and leave the short queries of
+/// logins and repositories queued behind them. There are no more of these threads than connections in the pool.
+fn spawn_fetch<C: Send + 'static>(
+    conn: C,
+    fetch: impl FnOnce(&C, &mut dyn FnMut(FetchEvent) -> bool) -> anyhow::Result<()> + Send + 'static,
+) -> anyhow::Result<FetchReceiver> {
+    let (sender, receiver) = mpsc::channel(FETCH_BUFFER_BATCHES);
+    thread::Builder::new()
+        .name("odbc-fetch".to_string())
+        .spawn(move || {
+            let result = fetch(&conn, &mut |event| sender.blocking_send(Ok(event)).is_ok());
+            if let Err(e) = result {
+                let _ = sender.blocking_send(Err(e));
+            }
+        })
+        .context("Unable to start a thread to fetch the rows")?;
+    Ok(receiver)
+}
+
+/// Executes the statement and reports its columns, then each rowset, to `on_event` until the cursor is exhausted or
+/// `on_event` returns false
+fn fetch_batches(
+    conn: &Connection<'_>,
+    statement: Statement,
+    batch_size: usize,
+    max_text_length: Option<usize>,
+    mut on_event: impl FnMut(FetchEvent) -> bool,
+) -> anyhow::Result<()> {
+    info!("Executing query: {}", statement.sql);
+
+    let bound_params = bind_params(statement.params);
+    let mut cursor = match conn.execute(&statement.sql, bound_params.as_slice()) {
+        Err(e) => {
+            error!("Error while executing query: {}", e);
+            return Err(anyhow!("StatementExecutionError: {}", e));
+        }
+        Ok(None) => {
+            error!("No results returned");
+            return Err(anyhow!("NoResultsError"));
+        }
+        Ok(Some(cursor)) => cursor,
+    };
+
+    let columns = result_columns(&mut cursor)?;
+    let num_cols = columns.len();
+    if !on_event(FetchEvent::Columns(columns)) {
+        return Ok(());
+    }
+
+    let mut buffers = TextRowSet::for_cursor(batch_size, &mut cursor, max_text_length)?;
+    let mut rows_cursor = cursor.bind_buffer(&mut buffers)?;
+
+    while let Some(rowset) = rows_cursor.fetch()? {
+        let rows = (0..rowset.num_rows())
+            .map(|rowi| {
+                Row((0..num_cols)
+                    .map(|coli| rowset.at_as_str(coli, rowi).ok().flatten().map(str::to_string))
+                    .collect())
+            })
+            .collect();
+        if !on_event(FetchEvent::Rows(rows)) {
+            info!("Consumer stopped reading, closing the cursor");
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Names and types of the result set columns, in the order returned by the query
+fn result_columns(cursor: &mut impl ResultSetMetadata) -> anyhow::Result<Vec<(String, ColumnType)>> {
+    let num_cols = cursor.num_result_cols()? as u16;
+    let mut columns = Vec::with_capacity(num_cols as usize);
+    for col_number in 1..=num_cols {
+        let name = cursor.col_name(col_number)?;
+        let data_type = cursor.col_data_type(col_number)?;
+        columns.push((name, metadata_column_type(data_type)));
+    }
+    Ok(columns)
+}
+
+fn metadata_column_type(data_type: DataType) -> ColumnType {
+    match data_type {
+        DataType::TinyInt | DataType::SmallInt | DataType::Integer | DataType::BigInt => ColumnType::Int,
+        DataType::Numeric { scale: 0, .. } | DataType::Decimal { scale: 0, .. } => ColumnType::Int,
+        DataType::Numeric { .. } | DataType::Decimal { .. } => ColumnType::Decimal,
+        DataType::Real | DataType::Float { .. } | DataType::Double => ColumnType::Float,
+        DataType::Bit => ColumnType::Bool,
+        DataType::Date => ColumnType::Date,
+        DataType::Timestamp { .. } => ColumnType::Timestamp,
+        _ => ColumnType::String,
+    }
+}
+
+fn bind_params(params: Vec<ParamValue>) -> Vec<Box<dyn InputParameter>> {
+    params.into_iter().map(into_input_parameter).collect()
+}
+
+fn into_input_parameter(value: ParamValue) -> Box<dyn InputParameter> {
+    match value {
+        ParamValue::Null => Box::new(None::<String>.into_parameter()),
+        ParamValue::Bool(b) => Box::new(Bit::from_bool(b)),
+        ParamValue::Int(i) => Box::new(i),
+        ParamValue::Float(f) => Box::new(f),
+        ParamValue::Str(s) => Box::new(s.into_parameter()),
+        ParamValue::Date(d) => Box::new(sys::Date {
+            year: d.year() as i16,
+            month: d.month() as u16,
+            day: d.day() as u16,
+        }),
+        ParamValue::Timestamp(ts) => Box::new(WithDataType {
+            value: sys::Timestamp {
+                year: ts.year() as i16,
+                month: ts.month() as u16,
+                day: ts.day() as u16,
+                hour: ts.hour() as u16,
+                minute: ts.minute() as u16,
+                second: ts.second() as u16,
+                fraction: ts.nanosecond(),
+            },
+            data_type: DataType::Timestamp { precision: 9 },
+        }),
+        //Lists are expanded into one parameter per item while compiling the template
+        ParamValue::List(items) => Box::new(
+            items
+                .iter()
+                .map(|item| serde_json::to_string(item).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(",")
+                .into_parameter(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::config::Credentials;
+    use crate::datasource::odbc::{connection_string, spawn_fetch};
+    use crate::datasource::{FetchEvent, Row};
+
+    #[test]
+    fn test_credentials_are_appended_to_the_connection_string() {
+        let password_file = std::env::temp_dir().join(format!("nexus-password-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&password_file, "se;cr}et\n").unwrap();
+        let credentials = Credentials {
+            username: Some("NEXUS_READER".to_string()),
+            password_file: Some(password_file.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            connection_string(None, Some("snowflake_eu"), &credentials, None).unwrap(),
+            "DSN=snowflake_eu;UID=NEXUS_READER;PWD={se;cr}}et}"
+        );
+        assert_eq!(
+            connection_string(None, None, &Credentials::default(), None).unwrap(),
+            "DSN=snowflake"
+        );
+        assert!(connection_string(
+            Some("Driver=SnowflakeDSIIDriver"),
+            Some("snowflake"),
+            &credentials,
+            None
+        )
+        .is_err());
+
+        let conflicting = Credentials {
+            password_env: Some("NEXUS_TEST_PASSWORD".to_string()),
+            ..credentials
+        };
+        assert!(connection_string(None, None, &conflicting, None).is_err());
+        std::fs::remove_file(password_file).unwrap();
+    }
+
+    #[test]
+    fn test_a_stream_is_read_on_a_thread_of_its_own_until_it_is_dropped() {
+        let (stopped, stopped_receiver) = std::sync::mpsc::channel();
+        let stalled = spawn_fetch((), move |_, on_event| {
+            assert_eq!(thread::current().name(), Some("odbc-fetch"));
+            while on_event(FetchEvent::Rows(vec![Row::default()])) {}
+            stopped.send(()).unwrap();
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(stopped_receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(stalled);
+        assert!(stopped_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
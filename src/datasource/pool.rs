@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use r2d2::{ManageConnection, Pool};
+use tokio::runtime::Handle;
+
+use crate::config::PoolConfig;
+use crate::datasource::DataSource;
+
+/// How often the pools are maintained and their gauges published
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Connections of a pool at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub in_use: u32,
+    pub idle: u32,
+}
+
+impl PoolStatus {
+    pub fn of_r2d2<M: ManageConnection>(pool: &Pool<M>) -> Self {
+        let state = pool.state();
+        Self {
+            in_use: state.connections - state.idle_connections,
+            idle: state.idle_connections,
+        }
+    }
+}
+
+/// Publishes the state of the pool of a data source through `metrics`
+#[derive(Debug, Clone)]
+pub struct PoolMetrics {
+    labels: [(&'static str, String); 1],
+}
+
+impl PoolMetrics {
+    pub fn new(datasource: &str) -> Self {
+        Self {
+            labels: [("datasource", datasource.to_string())],
+        }
+    }
+
+    /// Records how long getting a connection that was asked for at `started` took
+    pub fn acquired(&self, started: Instant) {
+        metrics::histogram!(
+            "db_pool_acquire_wait_seconds",
+            started.elapsed().as_secs_f64(),
+            &self.labels
+        );
+    }
+
+    pub fn report(&self, status: PoolStatus) {
+        metrics::gauge!("db_pool_connections_in_use", status.in_use as f64, &self.labels);
+        metrics::gauge!("db_pool_connections_idle", status.idle as f64, &self.labels);
+    }
+}
+
+/// An r2d2 pool as `config` describes it. Connections are opened as queries need them, so that an unreachable
+/// database fails its queries rather than the startup.
+pub fn r2d2_pool<M: ManageConnection>(manager: M, config: &PoolConfig) -> Pool<M> {
+    Pool::builder()
+        .min_idle(Some(config.min_size))
+        .max_size(config.max_size)
+        .connection_timeout(config.acquire_timeout())
+        .idle_timeout(config.idle_timeout())
+        .max_lifetime(config.max_lifetime())
+        .test_on_check_out(config.validation_query.is_some())
+        .build_unchecked(manager)
+}
+
+/// Maintains the pool of `source` and publishes its gauges for as long as the process runs. Does nothing outside of a
+/// Tokio runtime, as when a command line tool opens a source.
+pub fn spawn_maintenance(source: Arc<dyn DataSource>, metrics: PoolMetrics) {
+    let Ok(handle) = Handle::try_current() else {
+        return;
+    };
+    handle.spawn(async move {
+        let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+        loop {
+            interval.tick().await;
+            source.maintain().await;
+            metrics.report(source.pool_status());
+        }
+    });
+}
@@ -0,0 +1,435 @@
+use std::error::Error;
+use std::time::Instant;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+use deadpool_postgres::tokio_postgres::types::{to_sql_checked, Format, FromSql, IsNull, Kind, ToSql, Type};
+use deadpool_postgres::tokio_postgres::{self, NoTls};
+use deadpool_postgres::{Config, ManagerConfig, Object, Pool, RecyclingMethod, Runtime, Timeouts};
+use secrecy::ExposeSecret;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::{ColumnType, Credentials, PoolConfig};
+use crate::datasource::pool::{PoolMetrics, PoolStatus};
+use crate::datasource::{qualify, DataSource, FetchEvent, FetchReceiver, Row, Statement, FETCH_BUFFER_BATCHES};
+use crate::domain::params::ParamValue;
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+/// PostgreSQL over its native protocol. Parameters are sent as text and typed by the server, as literals would be.
+pub struct PostgresDataSource {
+    pool: Pool,
+    table_prefix: Option<String>,
+    config: PoolConfig,
+    metrics: PoolMetrics,
+}
+
+impl PostgresDataSource {
+    /// Connects to `url`, as the user of `credentials` when it names one
+    pub fn new(
+        url: &str,
+        credentials: &Credentials,
+        pool: &PoolConfig,
+        table_prefix: Option<String>,
+        metrics: PoolMetrics,
+    ) -> anyhow::Result<Self> {
+        let (username, password) = credentials.resolve()?;
+        let recycling_method = match &pool.validation_query {
+            Some(validation_query) => RecyclingMethod::Custom(validation_query.clone()),
+            None => RecyclingMethod::Fast,
+        };
+        let timeout = Some(pool.acquire_timeout());
+        let config = Config {
+            url: Some(url.to_string()),
+            user: username,
+            password: password.map(|password| password.expose_secret().clone()),
+            manager: Some(ManagerConfig { recycling_method }),
+            pool: Some(deadpool_postgres::PoolConfig {
+                max_size: pool.max_size as usize,
+                timeouts: Timeouts {
+                    wait: timeout,
+                    create: timeout,
+                    recycle: timeout,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let pool_config = pool.clone();
+        let pool = config
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .context("Invalid PostgreSQL url")?;
+        Ok(Self {
+            pool,
+            table_prefix,
+            config: pool_config,
+            metrics,
+        })
+    }
+
+    async fn connection(&self) -> anyhow::Result<Object> {
+        let started = Instant::now();
+        let client = self.pool.get().await.context("Unable to get a PostgreSQL connection")?;
+        self.metrics.acquired(started);
+        Ok(client)
+    }
+}
+
+#[async_trait]
+impl DataSource for PostgresDataSource {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn table(&self, name: &str) -> String {
+        qualify(self.table_prefix.as_deref(), name)
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        let status = self.pool.status();
+        PoolStatus {
+            in_use: (status.size - status.available) as u32,
+            idle: status.available as u32,
+        }
+    }
+
+    /// deadpool neither expires nor pre-opens connections, so idle and old ones are dropped here, then connections are
+    /// opened up to the minimum size
+    async fn maintain(&self) {
+        let idle_timeout = self.config.idle_timeout();
+        let max_lifetime = self.config.max_lifetime();
+        self.pool.retain(|_, metrics| {
+            idle_timeout.is_none_or(|timeout| metrics.last_used() < timeout)
+                && max_lifetime.is_none_or(|lifetime| metrics.age() < lifetime)
+        });
+
+        let mut opened = Vec::new();
+        while self.pool.status().size < self.config.min_size as usize && opened.len() < self.config.min_size as usize {
+            match self.pool.get().await {
+                Ok(client) => opened.push(client),
+                Err(e) => {
+                    warn!("Unable to open a PostgreSQL connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads the rows through a portal, `batch_size` at a time, so that the server stops producing them once the
+    /// receiver is dropped
+    async fn fetch(&self, statement: Statement, batch_size: usize) -> anyhow::Result<FetchReceiver> {
+        let mut client = self.connection().await?;
+        let (sender, receiver) = mpsc::channel(FETCH_BUFFER_BATCHES);
+        tokio::spawn(async move {
+            let result = async {
+                info!("Executing query: {}", statement.sql);
+                let transaction = client.transaction().await?;
+                let prepared = transaction
+                    .prepare_cached(&numbered_placeholders(&statement.sql))
+                    .await?;
+                let columns = prepared
+                    .columns()
+                    .iter()
+                    .map(|column| (column.name().to_string(), column_type(column.type_())))
+                    .collect();
+                if sender.send(Ok(FetchEvent::Columns(columns))).await.is_err() {
+                    return Ok(());
+                }
+                let portal = transaction.bind_raw(&prepared, text_params(statement.params)).await?;
+                loop {
+                    let rows = transaction.query_portal(&portal, batch_size as i32).await?;
+                    if rows.is_empty() {
+                        return Ok(());
+                    }
+                    let rows = rows.iter().map(read_row).collect::<anyhow::Result<Vec<_>>>()?;
+                    if sender.send(Ok(FetchEvent::Rows(rows))).await.is_err() {
+                        info!("Consumer stopped reading, closing the portal");
+                        return Ok(());
+                    }
+                }
+            };
+            let result: anyhow::Result<()> = result.await;
+            if let Err(e) = result {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        Ok(receiver)
+    }
+
+    async fn query(&self, statement: Statement) -> anyhow::Result<Vec<Row>> {
+        let client = self.connection().await?;
+        let prepared = client.prepare_cached(&numbered_placeholders(&statement.sql)).await?;
+        let rows = client.query_raw(&prepared, text_params(statement.params)).await?;
+        let rows: Vec<tokio_postgres::Row> = futures_util::TryStreamExt::try_collect(rows).await?;
+        rows.iter().map(read_row).collect()
+    }
+
+    async fn execute(&self, statement: Statement) -> anyhow::Result<u64> {
+        let client = self.connection().await?;
+        let prepared = client.prepare_cached(&numbered_placeholders(&statement.sql)).await?;
+        Ok(client.execute_raw(&prepared, text_params(statement.params)).await?)
+    }
+
+    async fn execute_batch(&self, statements: Vec<Statement>) -> anyhow::Result<u64> {
+        let mut client = self.connection().await?;
+        let transaction = client.transaction().await?;
+        let mut changed = 0;
+        for statement in statements {
+            let prepared = transaction
+                .prepare_cached(&numbered_placeholders(&statement.sql))
+                .await?;
+            changed += transaction
+                .execute_raw(&prepared, text_params(statement.params))
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(changed)
+    }
+}
+
+/// Turns the `?` placeholders into the `$1`, `$2`... of PostgreSQL, leaving quoted text and comments alone. The `?`
+/// operators of `jsonb` cannot be used for that reason; `jsonb_exists` and friends do the same.
+fn numbered_placeholders(sql: &str) -> String {
+    let mut numbered = String::with_capacity(sql.len() + 8);
+    let mut count = 0;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '?' => {
+                count += 1;
+                numbered.push_str(&format!("${}", count));
+            }
+            '\'' | '"' => {
+                numbered.push(c);
+                for quoted in chars.by_ref() {
+                    numbered.push(quoted);
+                    if quoted == c {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                numbered.push(c);
+                for commented in chars.by_ref() {
+                    numbered.push(commented);
+                    if commented == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                numbered.push(c);
+                let mut previous = ' ';
+                for commented in chars.by_ref() {
+                    numbered.push(commented);
+                    if previous == '*' && commented == '/' {
+                        break;
+                    }
+                    previous = commented;
+                }
+            }
+            _ => numbered.push(c),
+        }
+    }
+    numbered
+}
+
+fn column_type(ty: &Type) -> ColumnType {
+    match *ty {
+        Type::INT2 | Type::INT4 | Type::INT8 | Type::OID => ColumnType::Int,
+        Type::NUMERIC => ColumnType::Decimal,
+        Type::FLOAT4 | Type::FLOAT8 => ColumnType::Float,
+        Type::BOOL => ColumnType::Bool,
+        Type::DATE => ColumnType::Date,
+        Type::TIMESTAMP | Type::TIMESTAMPTZ => ColumnType::Timestamp,
+        _ => ColumnType::String,
+    }
+}
+
+fn read_row(row: &tokio_postgres::Row) -> anyhow::Result<Row> {
+    row.columns()
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            row.try_get::<_, PgText>(i)
+                .map(|text| text.0)
+                .with_context(|| format!("Unable to read column {}", column.name()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(Row)
+}
+
+fn text_params(params: Vec<ParamValue>) -> Vec<TextParam> {
+    params.into_iter().map(TextParam::from).collect()
+}
+
+/// A parameter sent in the text format, which the server parses into the type it inferred for the placeholder
+#[derive(Debug)]
+struct TextParam(Option<String>);
+
+impl From<ParamValue> for TextParam {
+    fn from(value: ParamValue) -> Self {
+        TextParam(match value {
+            ParamValue::Null => None,
+            ParamValue::Bool(b) => Some(b.to_string()),
+            ParamValue::Int(i) => Some(i.to_string()),
+            ParamValue::Float(f) => Some(f.to_string()),
+            ParamValue::Str(s) => Some(s),
+            ParamValue::Date(d) => Some(d.format("%Y-%m-%d").to_string()),
+            ParamValue::Timestamp(ts) => Some(ts.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+            //Lists are expanded into one parameter per item while compiling the template
+            ParamValue::List(items) => Some(
+                items
+                    .iter()
+                    .map(|item| serde_json::to_string(item).unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        })
+    }
+}
+
+impl ToSql for TextParam {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        match &self.0 {
+            Some(text) => {
+                out.extend_from_slice(text.as_bytes());
+                Ok(IsNull::No)
+            }
+            None => Ok(IsNull::Yes),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _ty: &Type) -> Format {
+        Format::Text
+    }
+
+    to_sql_checked!();
+}
+
+/// A column value rendered as the text ODBC drivers would return
+struct PgText(Option<String>);
+
+impl<'a> FromSql<'a> for PgText {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let text = match *ty {
+            Type::BOOL => bool::from_sql(ty, raw)?.to_string(),
+            Type::INT2 => i16::from_sql(ty, raw)?.to_string(),
+            Type::INT4 => i32::from_sql(ty, raw)?.to_string(),
+            Type::INT8 => i64::from_sql(ty, raw)?.to_string(),
+            Type::OID => u32::from_sql(ty, raw)?.to_string(),
+            Type::FLOAT4 => f32::from_sql(ty, raw)?.to_string(),
+            Type::FLOAT8 => f64::from_sql(ty, raw)?.to_string(),
+            Type::NUMERIC => numeric_text(raw)?,
+            Type::DATE => NaiveDate::from_sql(ty, raw)?.format("%Y-%m-%d").to_string(),
+            Type::TIME => NaiveTime::from_sql(ty, raw)?.to_string(),
+            Type::TIMESTAMP => NaiveDateTime::from_sql(ty, raw)?
+                .format("%Y-%m-%d %H:%M:%S%.f")
+                .to_string(),
+            Type::TIMESTAMPTZ => DateTime::<Utc>::from_sql(ty, raw)?.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            Type::UUID => uuid::Uuid::from_sql(ty, raw)?.to_string(),
+            Type::JSON | Type::JSONB => serde_json::Value::from_sql(ty, raw)?.to_string(),
+            Type::BYTEA => hex::encode(raw),
+            _ if <&str as FromSql>::accepts(ty) || matches!(ty.kind(), Kind::Enum(_)) => {
+                std::str::from_utf8(raw)?.to_string()
+            }
+            _ => return Err(format!("unsupported column type {}, cast it to text in the query", ty).into()),
+        };
+        Ok(PgText(Some(text)))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, BoxError> {
+        Ok(PgText(None))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Renders a `NUMERIC` from its binary format: digit count, weight of the first digit, sign and display scale, then
+/// the digits in base 10000
+fn numeric_text(raw: &[u8]) -> Result<String, BoxError> {
+    if raw.len() < 8 || !raw.len().is_multiple_of(2) {
+        return Err("invalid NUMERIC value".into());
+    }
+    let words = raw
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]))
+        .collect::<Vec<_>>();
+    let (ndigits, weight, sign, dscale) = (words[0] as usize, words[1] as i16 as i64, words[2], words[3] as usize);
+    let digits = &words[4..];
+    if digits.len() != ndigits {
+        return Err("invalid NUMERIC value".into());
+    }
+    let negative = match sign {
+        0x0000 => false,
+        0x4000 => true,
+        0xC000 => return Ok("NaN".to_string()),
+        0xD000 => return Ok("Infinity".to_string()),
+        0xF000 => return Ok("-Infinity".to_string()),
+        _ => return Err("invalid NUMERIC sign".into()),
+    };
+    let digit = |i: i64| if i < 0 { 0 } else { digits.get(i as usize).copied().unwrap_or(0) };
+
+    let mut text = String::new();
+    if negative {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(0).to_string());
+        for i in 1..=weight {
+            text.push_str(&format!("{:04}", digit(i)));
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::with_capacity(dscale + 4);
+        let mut i = weight + 1;
+        while fraction.len() < dscale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+        fraction.truncate(dscale);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datasource::postgres::{numbered_placeholders, numeric_text};
+
+    #[test]
+    fn test_placeholders_are_numbered_outside_quotes_and_comments() {
+        assert_eq!(
+            numbered_placeholders("SELECT * FROM t WHERE a = ? AND b IN (?, ?) -- why?\nAND c = '?' AND \"d?\" = ?"),
+            "SELECT * FROM t WHERE a = $1 AND b IN ($2, $3) -- why?\nAND c = '?' AND \"d?\" = $4"
+        );
+        assert_eq!(numbered_placeholders("/* ? */ SELECT ?"), "/* ? */ SELECT $1");
+    }
+
+    #[test]
+    fn test_numeric_values_are_rendered_exactly() {
+        let numeric = |words: &[u16]| {
+            let raw = words.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<_>>();
+            numeric_text(&raw).unwrap()
+        };
+        // ndigits, weight, sign, dscale, digits
+        assert_eq!(numeric(&[3, 1, 0x0000, 3, 1, 2345, 6780]), "12345.678");
+        assert_eq!(numeric(&[1, 0xFFFF, 0x4000, 4, 12]), "-0.0012");
+        assert_eq!(numeric(&[1, 0xFFFE, 0x0000, 5, 1000]), "0.00001");
+        assert_eq!(numeric(&[1, 1, 0x0000, 0, 7]), "70000");
+        assert_eq!(numeric(&[0, 0, 0x0000, 2]), "0.00");
+        assert_eq!(numeric(&[0, 0, 0xC000, 0]), "NaN");
+    }
+}
@@ -0,0 +1,385 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use r2d2::{ManageConnection, Pool};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use tokio::sync::mpsc;
+use tokio::task;
+use tracing::info;
+
+use crate::config::{ColumnType, PoolConfig};
+use crate::datasource::pool::{r2d2_pool, PoolMetrics, PoolStatus};
+use crate::datasource::{qualify, DataSource, FetchEvent, FetchReceiver, Row, Statement, FETCH_BUFFER_BATCHES};
+use crate::domain::params::ParamValue;
+
+pub const IN_MEMORY: &str = ":memory:";
+/// How long a statement waits for another connection to release its lock on the file
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A SQLite database file, mostly to run nexus locally and in tests
+pub struct SqliteDataSource {
+    pool: Pool<SqliteManager>,
+    table_prefix: Option<String>,
+    metrics: PoolMetrics,
+}
+
+impl SqliteDataSource {
+    /// Opens the database and runs `init_script` on it. An in-memory database lives on a single connection that is
+    /// never closed, as it would be lost with it.
+    pub fn new(
+        path: &str,
+        pool: &PoolConfig,
+        table_prefix: Option<String>,
+        init_script: Option<&str>,
+        metrics: PoolMetrics,
+    ) -> anyhow::Result<Self> {
+        let pool = if path == IN_MEMORY {
+            let manager = SqliteManager {
+                inner: SqliteConnectionManager::memory(),
+                validation_query: None,
+            };
+            Pool::builder()
+                .max_size(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connection_timeout(pool.acquire_timeout())
+                .build(manager)
+                .with_context(|| format!("Unable to open the SQLite database {}", path))?
+        } else {
+            let manager = SqliteManager {
+                inner: SqliteConnectionManager::file(path).with_init(|conn| conn.busy_timeout(BUSY_TIMEOUT)),
+                validation_query: pool.validation_query.clone(),
+            };
+            r2d2_pool(manager, pool)
+        };
+
+        if let Some(init_script) = init_script {
+            let sql = std::fs::read_to_string(init_script)
+                .with_context(|| format!("Unable to read the SQLite init script {}", init_script))?;
+            pool.get()
+                .with_context(|| format!("Unable to open the SQLite database {}", path))?
+                .execute_batch(&sql)
+                .with_context(|| format!("Unable to run the SQLite init script {}", init_script))?;
+            info!("Ran {} on the SQLite database {}", init_script, path);
+        }
+        Ok(Self {
+            pool,
+            table_prefix,
+            metrics,
+        })
+    }
+
+    /// Runs `work` away from the runtime, on a connection of the pool
+    fn with_connection<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> impl Future<Output = anyhow::Result<T>> + 'static {
+        let pool = self.pool.clone();
+        let metrics = self.metrics.clone();
+        let handle = task::spawn_blocking(move || {
+            let started = Instant::now();
+            let conn = pool.get().context("Unable to get a SQLite connection")?;
+            metrics.acquired(started);
+            work(&conn)
+        });
+        async move { handle.await? }
+    }
+}
+
+/// An in-memory database with the nexus schema, for the tests of the repositories and services
+#[cfg(test)]
+pub fn test_source() -> std::sync::Arc<SqliteDataSource> {
+    std::sync::Arc::new(
+        SqliteDataSource::new(
+            IN_MEMORY,
+            &PoolConfig::default(),
+            None,
+            Some("schema/sqlite.sql"),
+            PoolMetrics::new("test"),
+        )
+        .expect("the in-memory test database opens"),
+    )
+}
+
+/// Opens SQLite connections, and checks them with the validation query of the pool
+pub struct SqliteManager {
+    inner: SqliteConnectionManager,
+    validation_query: Option<String>,
+}
+
+impl ManageConnection for SqliteManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> Result<Connection, rusqlite::Error> {
+        self.inner.connect()
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        match &self.validation_query {
+            Some(validation_query) => conn.prepare(validation_query)?.query([])?.next().map(|_| ()),
+            None => self.inner.is_valid(conn),
+        }
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        self.inner.has_broken(conn)
+    }
+}
+
+#[async_trait]
+impl DataSource for SqliteDataSource {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn table(&self, name: &str) -> String {
+        qualify(self.table_prefix.as_deref(), name)
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus::of_r2d2(&self.pool)
+    }
+
+    async fn fetch(&self, statement: Statement, batch_size: usize) -> anyhow::Result<FetchReceiver> {
+        let (sender, receiver) = mpsc::channel(FETCH_BUFFER_BATCHES);
+        let error_sender = sender.clone();
+        let work = self.with_connection(move |conn| {
+            fetch_batches(conn, statement, batch_size, |event| {
+                sender.blocking_send(Ok(event)).is_ok()
+            })
+        });
+        tokio::spawn(async move {
+            if let Err(e) = work.await {
+                let _ = error_sender.send(Err(e)).await;
+            }
+        });
+        Ok(receiver)
+    }
+
+    async fn query(&self, statement: Statement) -> anyhow::Result<Vec<Row>> {
+        self.with_connection(move |conn| {
+            let mut rows = Vec::new();
+            fetch_batches(conn, statement, usize::MAX, |event| {
+                if let FetchEvent::Rows(batch) = event {
+                    rows.extend(batch);
+                }
+                true
+            })?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    async fn execute(&self, statement: Statement) -> anyhow::Result<u64> {
+        self.with_connection(move |conn| {
+            let changed = conn.execute(
+                &statement.sql,
+                params_from_iter(statement.params.into_iter().map(to_value)),
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn execute_batch(&self, statements: Vec<Statement>) -> anyhow::Result<u64> {
+        self.with_connection(move |conn| {
+            let transaction = conn.unchecked_transaction()?;
+            let mut changed = 0;
+            for statement in statements {
+                changed += transaction.execute(
+                    &statement.sql,
+                    params_from_iter(statement.params.into_iter().map(to_value)),
+                )? as u64;
+            }
+            transaction.commit()?;
+            Ok(changed)
+        })
+        .await
+    }
+}
+
+fn fetch_batches(
+    conn: &Connection,
+    statement: Statement,
+    batch_size: usize,
+    mut on_event: impl FnMut(FetchEvent) -> bool,
+) -> anyhow::Result<()> {
+    info!("Executing query: {}", statement.sql);
+
+    let mut prepared = conn.prepare(&statement.sql)?;
+    let columns = prepared
+        .columns()
+        .iter()
+        .map(|column| (column.name().to_string(), column_type(column.decl_type())))
+        .collect::<Vec<_>>();
+    let num_cols = columns.len();
+    if !on_event(FetchEvent::Columns(columns)) {
+        return Ok(());
+    }
+
+    let mut rows = prepared.query(params_from_iter(statement.params.into_iter().map(to_value)))?;
+    let mut batch = Vec::new();
+    while let Some(row) = rows.next()? {
+        let values = (0..num_cols)
+            .map(|i| row.get_ref(i).map(value_text))
+            .collect::<Result<Vec<_>, _>>()?;
+        batch.push(Row(values));
+        if batch.len() >= batch_size && !on_event(FetchEvent::Rows(std::mem::take(&mut batch))) {
+            info!("Consumer stopped reading, closing the statement");
+            return Ok(());
+        }
+    }
+    if !batch.is_empty() {
+        on_event(FetchEvent::Rows(batch));
+    }
+    Ok(())
+}
+
+/// Types a column by its declared type, the way SQLite derives column affinity. Expressions have no declared type
+/// and read as strings, unless the endpoint declares them.
+fn column_type(decl_type: Option<&str>) -> ColumnType {
+    let Some(decl_type) = decl_type.map(str::to_uppercase) else {
+        return ColumnType::String;
+    };
+    if decl_type.contains("BOOL") {
+        ColumnType::Bool
+    } else if decl_type.contains("INT") {
+        ColumnType::Int
+    } else if decl_type.contains("DEC") || decl_type.contains("NUMERIC") {
+        ColumnType::Decimal
+    } else if decl_type.contains("REAL") || decl_type.contains("FLOA") || decl_type.contains("DOUB") {
+        ColumnType::Float
+    } else if decl_type.contains("TIMESTAMP") || decl_type.contains("DATETIME") {
+        ColumnType::Timestamp
+    } else if decl_type == "DATE" {
+        ColumnType::Date
+    } else {
+        ColumnType::String
+    }
+}
+
+fn value_text(value: ValueRef<'_>) -> Option<String> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(text) => Some(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(blob) => Some(hex::encode(blob)),
+    }
+}
+
+fn to_value(value: ParamValue) -> Value {
+    match value {
+        ParamValue::Null => Value::Null,
+        ParamValue::Bool(b) => Value::Integer(b as i64),
+        ParamValue::Int(i) => Value::Integer(i),
+        ParamValue::Float(f) => Value::Real(f),
+        ParamValue::Str(s) => Value::Text(s),
+        ParamValue::Date(d) => Value::Text(d.format("%Y-%m-%d").to_string()),
+        ParamValue::Timestamp(ts) => Value::Text(ts.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        //Lists are expanded into one parameter per item while compiling the template
+        ParamValue::List(items) => Value::Text(
+            items
+                .iter()
+                .map(|item| serde_json::to_string(item).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{ColumnType, PoolConfig};
+    use crate::datasource::pool::{PoolMetrics, PoolStatus};
+    use crate::datasource::sqlite::{test_source, SqliteDataSource};
+    use crate::datasource::{DataSource, FetchEvent, Statement};
+    use crate::domain::RegisterUserDto;
+    use crate::repositories::user_repository::UserRepository;
+
+    #[tokio::test]
+    async fn test_users_round_trip_through_the_sqlite_schema() {
+        let source = test_source();
+        let users = UserRepository::new(source.clone());
+        let register_user = RegisterUserDto {
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            password: "unused".to_string(),
+            invite_code: None,
+        };
+
+        assert!(!users.does_user_exist("ada@example.com").await.unwrap());
+        users.create_user(register_user, "hash".to_string()).await.unwrap();
+        assert!(users.does_user_exist("ada@example.com").await.unwrap());
+
+        let user = users.get_user_by_email("ada@example.com").await.unwrap();
+        assert_eq!(user.password, "hash");
+        assert!(user.created_at.is_some());
+        assert!(!user.is_disabled());
+
+        users
+            .set_user_roles(&user.id, &["admin".to_string(), "analyst".to_string()])
+            .await
+            .unwrap();
+        let mut roles = users.get_user_roles(&user.id).await.unwrap();
+        roles.sort();
+        assert_eq!(roles, vec!["admin", "analyst"]);
+
+        let mut receiver = source
+            .fetch(
+                Statement::new("SELECT name, created_at, disabled_at FROM nexus_users", vec![]),
+                10,
+            )
+            .await
+            .unwrap();
+        let Some(Ok(FetchEvent::Columns(columns))) = receiver.recv().await else {
+            panic!("Columns must come first");
+        };
+        assert_eq!(columns[0], ("name".to_string(), ColumnType::String));
+        assert_eq!(columns[1], ("created_at".to_string(), ColumnType::Timestamp));
+        let Some(Ok(FetchEvent::Rows(rows))) = receiver.recv().await else {
+            panic!("The user must be fetched");
+        };
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get(0), Some("Ada"));
+        assert_eq!(rows[0].get(2), None);
+    }
+
+    #[tokio::test]
+    async fn test_pool_validates_connections_and_reports_its_status() {
+        let path = std::env::temp_dir().join(format!("nexus-pool-{}.db", uuid::Uuid::new_v4().simple()));
+        let path = path.to_string_lossy().into_owned();
+        let open = |validation_query: &str| {
+            let pool = PoolConfig {
+                max_size: 2,
+                acquire_timeout_secs: 1,
+                validation_query: Some(validation_query.to_string()),
+                ..Default::default()
+            };
+            SqliteDataSource::new(&path, &pool, None, None, PoolMetrics::new("test")).unwrap()
+        };
+
+        let source = open("SELECT 1");
+        source
+            .execute(Statement::new("CREATE TABLE t (x INTEGER)", vec![]))
+            .await
+            .unwrap();
+        assert_eq!(source.pool_status(), PoolStatus { in_use: 0, idle: 1 });
+        let held = source.pool.get().unwrap();
+        assert_eq!(source.pool_status(), PoolStatus { in_use: 1, idle: 0 });
+        drop(held);
+
+        let broken = open("SELECT * FROM missing_table");
+        let error = broken
+            .query(Statement::new("SELECT x FROM t", vec![]))
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Unable to get a SQLite connection"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::{DateTime, NaiveDateTime};
+use serde_json::{Number, Value};
+
+use crate::config::{ColumnConfig, ColumnType, MaskPolicy};
+use crate::domain::masking::ColumnMask;
+
+const TIMESTAMP_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"];
+const TIMESTAMP_OUTPUT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// A column of a result set, named and typed the way it is returned to the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultColumn {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub mask: Option<ColumnMask>,
+}
+
+impl ResultColumn {
+    /// Converts a fetched value to JSON, masking it first when the column is masked for the caller
+    pub fn to_json(&self, raw: Option<&str>) -> Value {
+        match (&self.mask, raw) {
+            (Some(mask), Some(raw)) => mask.apply(raw).map(Value::String).unwrap_or(Value::Null),
+            _ => self.column_type.to_json(raw),
+        }
+    }
+}
+
+/// The `response:` section of an endpoint
+#[derive(Debug, Clone, Default)]
+pub struct ResponseSchema {
+    columns: HashMap<String, (String, ColumnConfig)>,
+    masking_key: Arc<[u8]>,
+}
+
+impl ResponseSchema {
+    pub fn new(response: &HashMap<String, ColumnConfig>, masking_secret: &str) -> anyhow::Result<Self> {
+        for (name, config) in response {
+            let keyed = config
+                .mask
+                .as_ref()
+                .is_some_and(|m| matches!(m.policy, MaskPolicy::Hash | MaskPolicy::Tokenise));
+            if masking_secret.is_empty() && keyed {
+                bail!(
+                    "Column {} is hashed or tokenised but api.masking_secret is not set",
+                    name
+                );
+            }
+        }
+        let columns = response
+            .iter()
+            .map(|(name, config)| (name.to_lowercase(), (name.clone(), config.clone())))
+            .collect();
+        Ok(Self {
+            columns,
+            masking_key: Arc::from(masking_secret.as_bytes()),
+        })
+    }
+
+    /// The schema as seen by a caller: columns stay masked unless the caller holds one of their `unmasked_roles`
+    pub fn for_roles(&self, roles: &[String]) -> Self {
+        let mut schema = self.clone();
+        for (_, config) in schema.columns.values_mut() {
+            if config
+                .mask
+                .as_ref()
+                .is_some_and(|mask| mask.unmasked_roles.iter().any(|role| roles.contains(role)))
+            {
+                config.mask = None;
+            }
+        }
+        schema
+    }
+
+    /// Matches the columns of a result set with the declared ones. Names are compared case-insensitively because
+    /// warehouses such as Snowflake upper-case unquoted identifiers; a declared column keeps its declared name, the
+    /// rest keep the name and the type reported by the driver.
+    pub fn resolve(&self, columns: Vec<(String, ColumnType)>) -> Vec<ResultColumn> {
+        columns
+            .into_iter()
+            .map(|(name, metadata_type)| match self.columns.get(&name.to_lowercase()) {
+                Some((declared_name, config)) => {
+                    let mask = config
+                        .mask
+                        .as_ref()
+                        .map(|mask| ColumnMask::new(mask, self.masking_key.clone()));
+                    ResultColumn {
+                        name: declared_name.clone(),
+                        column_type: mask
+                            .as_ref()
+                            .map_or(config.column_type, |mask| mask.column_type(config.column_type)),
+                        mask,
+                    }
+                }
+                None => ResultColumn {
+                    name,
+                    column_type: metadata_type,
+                    mask: None,
+                },
+            })
+            .collect()
+    }
+}
+
+impl ColumnType {
+    /// Converts the text representation of a value fetched from the database to JSON. A value that cannot be read as
+    /// the declared type is returned as a string rather than failing the whole response.
+    pub fn to_json(&self, raw: Option<&str>) -> Value {
+        let Some(raw) = raw else {
+            return Value::Null;
+        };
+        let converted = match self {
+            ColumnType::String | ColumnType::Decimal | ColumnType::Date => None,
+            ColumnType::Int => raw
+                .parse::<i64>()
+                .ok()
+                .map(Value::from)
+                .or_else(|| integral_float(raw).map(Value::from)),
+            ColumnType::Float => raw.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number),
+            ColumnType::Bool => parse_bool(raw).map(Value::Bool),
+            ColumnType::Timestamp => {
+                parse_timestamp(raw).map(|ts| Value::String(ts.format(TIMESTAMP_OUTPUT_FORMAT).to_string()))
+            }
+            ColumnType::Json => serde_json::from_str(raw).ok(),
+        };
+        converted.unwrap_or_else(|| Value::String(raw.to_string()))
+    }
+}
+
+/// Some drivers report integers of NUMBER(38,0) columns as `30.000`
+fn integral_float(raw: &str) -> Option<i64> {
+    raw.parse::<f64>()
+        .ok()
+        .filter(|f| f.fract() == 0.0 && f.abs() < i64::MAX as f64)
+        .map(|f| f as i64)
+}
+
+pub fn parse_bool(raw: &str) -> Option<bool> {
+    match raw.to_lowercase().as_str() {
+        "1" | "true" | "t" | "y" | "yes" => Some(true),
+        "0" | "false" | "f" | "n" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_timestamp(raw: &str) -> Option<NaiveDateTime> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(raw) {
+        return Some(ts.naive_utc());
+    }
+    TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(raw, format).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::config::{ColumnConfig, ColumnType};
+    use crate::domain::columns::ResponseSchema;
+
+    #[test]
+    fn test_declared_types_take_precedence_over_metadata() {
+        let schema = ResponseSchema::new(
+            &HashMap::from([(
+                "age".to_string(),
+                ColumnConfig {
+                    column_type: ColumnType::Int,
+                    mask: None,
+                },
+            )]),
+            "",
+        )
+        .unwrap();
+
+        let columns = schema.resolve(vec![
+            ("AGE".to_string(), ColumnType::Decimal),
+            ("BALANCE".to_string(), ColumnType::Decimal),
+        ]);
+        assert_eq!(columns[0].name, "age");
+        assert_eq!(columns[0].column_type.to_json(Some("30.000")), json!(30));
+        assert_eq!(columns[1].name, "BALANCE");
+        assert_eq!(columns[1].column_type.to_json(Some("10.50")), json!("10.50"));
+        assert_eq!(columns[1].column_type.to_json(None), json!(null));
+        assert_eq!(ColumnType::Json.to_json(Some(r#"{"a": [1]}"#)), json!({"a": [1]}));
+    }
+}
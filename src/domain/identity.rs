@@ -0,0 +1,27 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::auth::ValidatedTokenDetails;
+
+/// Name under which the caller is exposed to SQL templates. Request parameters cannot use it, so a template can trust
+/// `{{ auth.user_id }}`, `{{ auth.roles }}` and `{{ auth.attributes.<name> }}`.
+pub const AUTH_VARIABLE: &str = "auth";
+
+/// The authenticated caller of a data endpoint, as stated by its access token
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AuthContext {
+    pub user_id: String,
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl From<&ValidatedTokenDetails> for AuthContext {
+    fn from(token: &ValidatedTokenDetails) -> Self {
+        Self {
+            user_id: token.user_id.clone(),
+            roles: token.roles.clone(),
+            attributes: token.attributes.clone(),
+        }
+    }
+}
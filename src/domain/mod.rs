@@ -1,8 +1,17 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::repositories::api_key_repository::ApiKeyEntity;
+use crate::repositories::invite_repository::InviteEntity;
 use crate::repositories::user_repository::UserEntity;
 
+pub mod columns;
+pub mod identity;
+pub mod masking;
+pub mod pagination;
+pub mod params;
 pub mod req_res;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -10,10 +19,19 @@ pub struct UserDto {
     pub id: String,
     pub name: String,
     pub email: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
     #[serde(rename = "createdAt")]
     pub created_at: Option<DateTime<Utc>>,
     #[serde(rename = "updatedAt")]
     pub updated_at: Option<DateTime<Utc>>,
+    /// Set while the account is disabled
+    #[serde(rename = "disabledAt")]
+    pub disabled_at: Option<DateTime<Utc>>,
+    #[serde(rename = "emailVerifiedAt")]
+    pub email_verified_at: Option<DateTime<Utc>>,
     pub access_token: Option<String>,
     pub refresh_token: Option<String>,
 }
@@ -24,8 +42,12 @@ impl From<UserEntity> for UserDto {
             id: entity.id,
             name: entity.name,
             email: entity.email,
+            roles: vec![],
+            attributes: BTreeMap::new(),
             created_at: entity.created_at,
             updated_at: entity.updated_at,
+            disabled_at: entity.disabled_at,
+            email_verified_at: entity.email_verified_at,
             access_token: None,
             refresh_token: None,
         }
@@ -38,6 +60,9 @@ pub struct RegisterUserDto {
     pub email: String,
     #[serde(skip_serializing)]
     pub password: String,
+    /// Required when `api.registration` is `invite_only`
+    #[serde(default, skip_serializing)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -45,3 +70,74 @@ pub struct LoginUserDto {
     pub email: String,
     pub password: String,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokensDto {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// An API key as listed to its owner. The key itself is only returned once, when it is created.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKeyDto {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeyEntity> for ApiKeyDto {
+    fn from(entity: ApiKeyEntity) -> Self {
+        Self {
+            id: entity.key_id,
+            name: entity.name,
+            scopes: entity.scopes,
+            created_at: DateTime::from_timestamp(entity.issued_at, 0),
+            expires_at: DateTime::from_timestamp(entity.expires_at, 0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyDto {
+    #[serde(flatten)]
+    pub key: ApiKeyDto,
+    pub api_key: String,
+}
+
+/// An invite as listed to admins. The code itself is only returned once, when the invite is created.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InviteDto {
+    pub id: String,
+    pub email: Option<String>,
+    pub roles: Vec<String>,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<InviteEntity> for InviteDto {
+    fn from(entity: InviteEntity) -> Self {
+        Self {
+            id: entity.invite_id,
+            email: entity.email,
+            roles: entity.roles,
+            created_by: entity.created_by,
+            created_at: DateTime::from_timestamp(entity.issued_at, 0),
+            expires_at: DateTime::from_timestamp(entity.expires_at, 0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreatedInviteDto {
+    #[serde(flatten)]
+    pub invite: InviteDto,
+    pub invite_code: String,
+}
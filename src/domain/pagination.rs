@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{PaginationConfig, PaginationMode};
+use crate::domain::params::{ParamValue, ParamViolation};
+use crate::domain::req_res::DataRow;
+use crate::errors::ApiError;
+use crate::errors::ApiError::InternalServerErrorWithContext;
+use crate::repositories::sql_template::CompiledSql;
+
+pub const PAGE_SIZE_PARAM: &str = "page_size";
+pub const PAGE_TOKEN_PARAM: &str = "page_token";
+
+/// Opaque continuation handed to the client as `next_page_token`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageToken {
+    /// Number of rows already returned (offset mode)
+    Offset(u64),
+    /// Keyset columns of the last row returned (keyset mode)
+    After(Vec<Value>),
+}
+
+impl PageToken {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(raw: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(raw).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn matches(&self, mode: PaginationMode) -> bool {
+        matches!(
+            (self, mode),
+            (PageToken::Offset(_), PaginationMode::Offset) | (PageToken::After(_), PaginationMode::Keyset)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest {
+    pub page_size: usize,
+    pub token: Option<PageToken>,
+}
+
+impl PageRequest {
+    /// Takes the pagination parameters out of the query string, leaving only the endpoint's own parameters for the
+    /// request schema.
+    pub fn from_params(
+        params: &mut HashMap<String, String>,
+        config: &PaginationConfig,
+    ) -> Result<Self, Vec<ParamViolation>> {
+        let mut violations = vec![];
+
+        let page_size = match params.remove(PAGE_SIZE_PARAM) {
+            None => config.default_page_size,
+            Some(raw) => match raw.parse::<usize>() {
+                Ok(size) if (1..=config.max_page_size).contains(&size) => size,
+                _ => {
+                    violations.push(ParamViolation::new(
+                        PAGE_SIZE_PARAM,
+                        format!("expected an integer between 1 and {}", config.max_page_size),
+                    ));
+                    config.default_page_size
+                }
+            },
+        };
+
+        let token = match params.remove(PAGE_TOKEN_PARAM) {
+            None => None,
+            Some(raw) => match PageToken::decode(&raw) {
+                Some(token) if token.matches(config.mode) => Some(token),
+                _ => {
+                    violations.push(ParamViolation::new(PAGE_TOKEN_PARAM, "invalid page token"));
+                    None
+                }
+            },
+        };
+
+        if violations.is_empty() {
+            Ok(Self { page_size, token })
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Wraps the templated query so that it returns a single page. One row more than the page size is fetched to find
+/// out whether another page follows.
+pub fn paginate(compiled: CompiledSql, config: &PaginationConfig, page: &PageRequest) -> CompiledSql {
+    let inner = trim_statement(&compiled.sql);
+    let limit = page.page_size + 1;
+    let mut params = compiled.params;
+
+    let keys = config.keyset_columns();
+    let sql = match config.mode {
+        PaginationMode::Keyset if !keys.is_empty() => {
+            let filter = match &page.token {
+                Some(PageToken::After(last)) if last.len() == keys.len() => {
+                    let (filter, after_params) = after_keys(&keys, last);
+                    params.extend(after_params);
+                    format!(" WHERE {}", filter)
+                }
+                _ => String::new(),
+            };
+            format!(
+                "SELECT * FROM (\n{}\n) nexus_page{} ORDER BY {} LIMIT {}",
+                inner,
+                filter,
+                keys.join(", "),
+                limit
+            )
+        }
+        _ => {
+            let offset = match &page.token {
+                Some(PageToken::Offset(offset)) => *offset,
+                _ => 0,
+            };
+            format!(
+                "SELECT * FROM (\n{}\n) nexus_page LIMIT {} OFFSET {}",
+                inner, limit, offset
+            )
+        }
+    };
+
+    CompiledSql { sql, params }
+}
+
+/// Counts all the rows of the templated query, ignoring pagination
+pub fn count_query(compiled: &CompiledSql) -> CompiledSql {
+    CompiledSql {
+        sql: format!(
+            "SELECT COUNT(*) AS total_count FROM (\n{}\n) nexus_count",
+            trim_statement(&compiled.sql)
+        ),
+        params: compiled.params.clone(),
+    }
+}
+
+/// Drops the look-ahead row, if it was fetched, and returns the token of the page after it. Fails when the keyset
+/// columns are missing from the rows, as when the SQL aliases them, rather than ending the result early.
+pub fn next_page_token(
+    rows: &mut Vec<DataRow>,
+    config: &PaginationConfig,
+    page: &PageRequest,
+) -> Result<Option<String>, ApiError> {
+    if rows.len() <= page.page_size {
+        return Ok(None);
+    }
+    rows.truncate(page.page_size);
+
+    let keys = config.keyset_columns();
+    let token = match (config.mode, rows.last()) {
+        (PaginationMode::Keyset, Some(last)) if !keys.is_empty() => PageToken::After(
+            keys.iter()
+                .map(|key| {
+                    last.iter()
+                        .find(|(column, _)| column.eq_ignore_ascii_case(key))
+                        .map(|(_, value)| value.clone())
+                        .ok_or_else(|| {
+                            InternalServerErrorWithContext(format!("The keyset column {} is not in the result", key))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => {
+            let offset = match &page.token {
+                Some(PageToken::Offset(offset)) => *offset,
+                _ => 0,
+            };
+            PageToken::Offset(offset + page.page_size as u64)
+        }
+    };
+    Ok(Some(token.encode()))
+}
+
+/// Rows after `last` in the order of `keys`: `a > ? OR (a = ? AND b > ?)`, spelled out for the databases that do not
+/// compare rows
+fn after_keys(keys: &[&str], last: &[Value]) -> (String, Vec<ParamValue>) {
+    let mut filters = Vec::with_capacity(keys.len());
+    let mut params = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        let mut terms = Vec::with_capacity(i + 1);
+        for (equal_key, value) in keys[..i].iter().zip(last) {
+            terms.push(format!("{} = ?", equal_key));
+            params.push(json_to_param(value));
+        }
+        terms.push(format!("{} > ?", key));
+        params.push(json_to_param(&last[i]));
+        filters.push(terms.join(" AND "));
+    }
+    let filter = match filters.as_slice() {
+        [single] => single.clone(),
+        _ => filters
+            .iter()
+            .map(|f| format!("({})", f))
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    };
+    (filter, params)
+}
+
+fn trim_statement(sql: &str) -> &str {
+    sql.trim().trim_end_matches(';').trim_end()
+}
+
+fn json_to_param(value: &Value) -> ParamValue {
+    match value {
+        Value::Null => ParamValue::Null,
+        Value::Bool(b) => ParamValue::Bool(*b),
+        Value::Number(n) => n
+            .as_i64()
+            .map(ParamValue::Int)
+            .unwrap_or_else(|| ParamValue::Float(n.as_f64().unwrap_or_default())),
+        Value::String(s) => ParamValue::Str(s.clone()),
+        other => ParamValue::Str(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::config::{PaginationConfig, PaginationMode};
+    use crate::domain::pagination::{next_page_token, paginate, PageRequest, PageToken};
+    use crate::domain::params::ParamValue;
+    use crate::domain::req_res::DataRow;
+    use crate::repositories::sql_template::CompiledSql;
+
+    #[test]
+    fn test_keyset_pagination_continues_after_last_key() {
+        let config = PaginationConfig {
+            mode: PaginationMode::Keyset,
+            sort_key: Some("created".to_string()),
+            tie_breaker: Some("id".to_string()),
+            ..PaginationConfig::default()
+        };
+        let compiled = CompiledSql {
+            sql: "select id, created from customer_master where gender = ?;\n".to_string(),
+            params: vec![ParamValue::Str("F".to_string())],
+        };
+
+        let first = PageRequest {
+            page_size: 2,
+            token: None,
+        };
+        let mut rows = (1..=3)
+            .map(|id| {
+                DataRow::from_iter([
+                    ("ID".to_string(), json!(id)),
+                    ("CREATED".to_string(), json!("2024-01-01")),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let token = next_page_token(&mut rows, &config, &first).unwrap().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            PageToken::decode(&token),
+            Some(PageToken::After(vec![json!("2024-01-01"), json!(2)]))
+        );
+
+        let second = PageRequest {
+            page_size: 2,
+            token: PageToken::decode(&token),
+        };
+        let page_sql = paginate(compiled, &config, &second);
+        assert_eq!(
+            page_sql.sql,
+            "SELECT * FROM (\nselect id, created from customer_master where gender = ?\n) nexus_page \
+             WHERE (created > ?) OR (created = ? AND id > ?) ORDER BY created, id LIMIT 3"
+        );
+        assert_eq!(
+            page_sql.params,
+            vec![
+                ParamValue::Str("F".to_string()),
+                ParamValue::Str("2024-01-01".to_string()),
+                ParamValue::Str("2024-01-01".to_string()),
+                ParamValue::Int(2)
+            ]
+        );
+
+        let mut aliased = (1..=3)
+            .map(|id| DataRow::from_iter([("CUSTOMER_ID".to_string(), json!(id))]))
+            .collect::<Vec<_>>();
+        assert!(next_page_token(&mut aliased, &config, &first).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::config::{ParamConfig, ParamType};
+use crate::domain::columns::parse_timestamp;
+use crate::domain::identity::AUTH_VARIABLE;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const LIST_SEPARATOR: char = ',';
+
+/// A request parameter coerced to its declared type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+    List(Vec<ParamValue>),
+}
+
+impl From<&str> for ParamValue {
+    fn from(value: &str) -> Self {
+        ParamValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> Self {
+        ParamValue::Str(value)
+    }
+}
+
+impl From<i64> for ParamValue {
+    fn from(value: i64) -> Self {
+        ParamValue::Int(value)
+    }
+}
+
+impl From<Option<&str>> for ParamValue {
+    fn from(value: Option<&str>) -> Self {
+        value.map(ParamValue::from).unwrap_or(ParamValue::Null)
+    }
+}
+
+pub type RequestParams = HashMap<String, ParamValue>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamViolation {
+    pub parameter: String,
+    pub message: String,
+}
+
+impl ParamViolation {
+    pub fn new(parameter: &str, message: impl Into<String>) -> Self {
+        Self {
+            parameter: parameter.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The `request:` section of an endpoint, with patterns compiled and defaults/ranges coerced up front
+#[derive(Debug, Clone)]
+pub struct RequestSchema {
+    params: HashMap<String, ParamRule>,
+}
+
+#[derive(Debug, Clone)]
+struct ParamRule {
+    config: ParamConfig,
+    pattern: Option<Regex>,
+    default: Option<ParamValue>,
+    min: Option<ParamValue>,
+    max: Option<ParamValue>,
+}
+
+impl RequestSchema {
+    pub fn new(request: &HashMap<String, ParamConfig>) -> anyhow::Result<Self> {
+        let mut params = HashMap::with_capacity(request.len());
+        for (name, config) in request {
+            if name == AUTH_VARIABLE {
+                return Err(anyhow!(
+                    "Parameter {} is reserved for the authenticated user",
+                    AUTH_VARIABLE
+                ));
+            }
+            let pattern = config
+                .pattern
+                .as_ref()
+                .map(|p| Regex::new(&format!("^(?:{})$", p)))
+                .transpose()
+                .with_context(|| format!("Invalid pattern for parameter {}", name))?;
+            if config.param_type == ParamType::Enum && config.values.is_empty() {
+                return Err(anyhow!("Enum parameter {} must declare its values", name));
+            }
+
+            let mut rule = ParamRule {
+                config: config.clone(),
+                pattern,
+                default: None,
+                min: None,
+                max: None,
+            };
+            rule.default = config
+                .default
+                .as_ref()
+                .map(|raw| rule.coerce(raw))
+                .transpose()
+                .map_err(|e| anyhow!("Invalid default for parameter {}: {}", name, e))?;
+            rule.min = config
+                .min
+                .as_ref()
+                .map(|raw| rule.coerce_bound(raw))
+                .transpose()
+                .map_err(|e| anyhow!("Invalid min for parameter {}: {}", name, e))?;
+            rule.max = config
+                .max
+                .as_ref()
+                .map(|raw| rule.coerce_bound(raw))
+                .transpose()
+                .map_err(|e| anyhow!("Invalid max for parameter {}: {}", name, e))?;
+
+            params.insert(name.clone(), rule);
+        }
+        Ok(Self { params })
+    }
+
+    /// Coerces the raw query string against the schema, collecting every violation instead of stopping at the first.
+    pub fn validate(&self, raw_params: &HashMap<String, String>) -> Result<RequestParams, Vec<ParamViolation>> {
+        let mut violations = raw_params
+            .keys()
+            .filter(|name| !self.params.contains_key(*name))
+            .map(|name| ParamViolation::new(name, "unknown parameter"))
+            .collect::<Vec<_>>();
+
+        let mut params = RequestParams::with_capacity(self.params.len());
+        for (name, rule) in &self.params {
+            match raw_params.get(name) {
+                Some(raw) => match rule.coerce(raw).and_then(|value| rule.check(raw, value)) {
+                    Ok(value) => {
+                        params.insert(name.clone(), value);
+                    }
+                    Err(message) => violations.push(ParamViolation::new(name, message)),
+                },
+                None => match &rule.default {
+                    Some(default) => {
+                        params.insert(name.clone(), default.clone());
+                    }
+                    None if rule.config.required => violations.push(ParamViolation::new(name, "is required")),
+                    None => {}
+                },
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(params)
+        } else {
+            violations.sort_by(|a, b| a.parameter.cmp(&b.parameter));
+            Err(violations)
+        }
+    }
+}
+
+impl ParamRule {
+    fn coerce(&self, raw: &str) -> Result<ParamValue, String> {
+        match self.config.param_type {
+            ParamType::List => {
+                let item_type = self.config.items.unwrap_or(ParamType::String);
+                if raw.trim().is_empty() {
+                    return Ok(ParamValue::List(vec![]));
+                }
+                raw.split(LIST_SEPARATOR)
+                    .map(|item| coerce_scalar(item_type, &self.config.values, item.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(ParamValue::List)
+            }
+            param_type => coerce_scalar(param_type, &self.config.values, raw),
+        }
+    }
+
+    /// Range bounds of a list apply to its items, so they are coerced to the item type
+    fn coerce_bound(&self, raw: &str) -> Result<ParamValue, String> {
+        match self.config.param_type {
+            ParamType::List => coerce_scalar(self.config.items.unwrap_or(ParamType::String), &[], raw),
+            param_type => coerce_scalar(param_type, &[], raw),
+        }
+    }
+
+    fn check(&self, raw: &str, value: ParamValue) -> Result<ParamValue, String> {
+        if let Some(pattern) = &self.pattern {
+            let matches = match &value {
+                ParamValue::List(_) => raw.split(LIST_SEPARATOR).all(|item| pattern.is_match(item.trim())),
+                _ => pattern.is_match(raw),
+            };
+            if !matches {
+                return Err(format!("does not match pattern {}", pattern.as_str()));
+            }
+        }
+
+        let items = match &value {
+            ParamValue::List(items) => items.as_slice(),
+            single => std::slice::from_ref(single),
+        };
+        for item in items {
+            if let Some(min) = &self.min {
+                if compare(item, min) == Some(Ordering::Less) {
+                    return Err(format!("must not be less than {}", display(min)));
+                }
+            }
+            if let Some(max) = &self.max {
+                if compare(item, max) == Some(Ordering::Greater) {
+                    return Err(format!("must not be greater than {}", display(max)));
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn coerce_scalar(param_type: ParamType, values: &[String], raw: &str) -> Result<ParamValue, String> {
+    match param_type {
+        ParamType::String => Ok(ParamValue::Str(raw.to_string())),
+        ParamType::Int => raw
+            .parse::<i64>()
+            .map(ParamValue::Int)
+            .map_err(|_e| format!("expected an integer but got '{}'", raw)),
+        ParamType::Float => raw
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(ParamValue::Float)
+            .ok_or_else(|| format!("expected a number but got '{}'", raw)),
+        ParamType::Bool => match raw.to_lowercase().as_str() {
+            //Stricter than the database values read by `columns::parse_bool`: no single letter is taken for a boolean
+            "true" | "1" | "yes" => Ok(ParamValue::Bool(true)),
+            "false" | "0" | "no" => Ok(ParamValue::Bool(false)),
+            _ => Err(format!("expected a boolean but got '{}'", raw)),
+        },
+        ParamType::Date => NaiveDate::parse_from_str(raw, DATE_FORMAT)
+            .map(ParamValue::Date)
+            .map_err(|_e| format!("expected a date (YYYY-MM-DD) but got '{}'", raw)),
+        ParamType::Timestamp => parse_timestamp(raw)
+            .map(ParamValue::Timestamp)
+            .ok_or_else(|| format!("expected an ISO-8601 timestamp but got '{}'", raw)),
+        ParamType::Enum => {
+            if values.iter().any(|v| v == raw) {
+                Ok(ParamValue::Str(raw.to_string()))
+            } else {
+                Err(format!("expected one of [{}] but got '{}'", values.join(", "), raw))
+            }
+        }
+        ParamType::List => Err("nested lists are not supported".to_string()),
+    }
+}
+
+/// Numbers, dates and timestamps compare by value; strings by their length
+fn compare(value: &ParamValue, bound: &ParamValue) -> Option<Ordering> {
+    match (value, bound) {
+        (ParamValue::Int(v), ParamValue::Int(b)) => Some(v.cmp(b)),
+        (ParamValue::Float(v), ParamValue::Float(b)) => v.partial_cmp(b),
+        (ParamValue::Date(v), ParamValue::Date(b)) => Some(v.cmp(b)),
+        (ParamValue::Timestamp(v), ParamValue::Timestamp(b)) => Some(v.cmp(b)),
+        (ParamValue::Str(v), ParamValue::Str(b)) => b.parse::<usize>().ok().map(|len| v.chars().count().cmp(&len)),
+        _ => None,
+    }
+}
+
+fn display(value: &ParamValue) -> String {
+    match value {
+        ParamValue::Str(s) => s.clone(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::config::{ParamConfig, ParamType};
+    use crate::domain::params::{ParamValue, RequestSchema};
+
+    fn param(param_type: ParamType) -> ParamConfig {
+        ParamConfig {
+            param_type,
+            required: false,
+            default: None,
+            pattern: None,
+            min: None,
+            max: None,
+            values: vec![],
+            items: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_coerces_and_reports_every_violation() {
+        let schema = RequestSchema::new(&HashMap::from([
+            (
+                "customer_id".to_string(),
+                ParamConfig {
+                    required: true,
+                    pattern: Some("C[0-9]+".to_string()),
+                    ..param(ParamType::String)
+                },
+            ),
+            (
+                "age".to_string(),
+                ParamConfig {
+                    min: Some("18".to_string()),
+                    ..param(ParamType::Int)
+                },
+            ),
+            (
+                "gender".to_string(),
+                ParamConfig {
+                    values: vec!["M".to_string(), "F".to_string()],
+                    default: Some("F".to_string()),
+                    ..param(ParamType::Enum)
+                },
+            ),
+            (
+                "ids".to_string(),
+                ParamConfig {
+                    items: Some(ParamType::Int),
+                    ..param(ParamType::List)
+                },
+            ),
+        ]))
+        .unwrap();
+
+        let params = schema
+            .validate(&HashMap::from([
+                ("customer_id".to_string(), "C42".to_string()),
+                ("age".to_string(), "30".to_string()),
+                ("ids".to_string(), "1, 2".to_string()),
+            ]))
+            .unwrap();
+        assert_eq!(params["age"], ParamValue::Int(30));
+        assert_eq!(params["gender"], ParamValue::Str("F".to_string()));
+        assert_eq!(
+            params["ids"],
+            ParamValue::List(vec![ParamValue::Int(1), ParamValue::Int(2)])
+        );
+
+        let violations = schema
+            .validate(&HashMap::from([
+                ("age".to_string(), "12".to_string()),
+                ("gender".to_string(), "X".to_string()),
+                ("limit".to_string(), "10".to_string()),
+            ]))
+            .unwrap_err();
+        let violated = violations.iter().map(|v| v.parameter.as_str()).collect::<Vec<_>>();
+        assert_eq!(violated, vec!["age", "customer_id", "gender", "limit"]);
+    }
+
+    #[test]
+    fn test_request_booleans_are_spelled_out() {
+        let schema = RequestSchema::new(&HashMap::from([("active".to_string(), param(ParamType::Bool))])).unwrap();
+        let validate = |raw: &str| schema.validate(&HashMap::from([("active".to_string(), raw.to_string())]));
+
+        assert_eq!(validate("Yes").unwrap()["active"], ParamValue::Bool(true));
+        assert_eq!(validate("0").unwrap()["active"], ParamValue::Bool(false));
+        for raw in ["t", "y", "f", "n", "on"] {
+            assert!(validate(raw).is_err(), "{}", raw);
+        }
+    }
+}
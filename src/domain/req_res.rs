@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
@@ -14,4 +14,97 @@ pub struct LoginUserResponse {
     pub user: UserDto,
 }
 
-pub type DataResponse = Vec<HashMap<String, String>>;
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UpdateRolesRequest {
+    pub roles: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UpdateAttributesRequest {
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    /// Names of the data endpoints the key may call
+    pub scopes: Vec<String>,
+    /// Defaults to `api.api_key_max_age_days`, which it cannot exceed
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+/// Fields of the profile to change; those left out keep their value
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UpdateProfileRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// A token mailed by the email verification flow
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+/// Starts a flow that mails the user, such as a password reset
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ListUsersQuery {
+    /// Part of the name or of the email to look for
+    #[serde(default)]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CreateInviteRequest {
+    /// Restricts the invite to that address, which is mailed the code and counts as verified
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Roles given to the user who registers with the invite
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Defaults to `api.invite_max_age_days`, which it cannot exceed
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+/// A result row keyed by column name, in the column order of the query
+pub type DataRow = serde_json::Map<String, serde_json::Value>;
+
+pub type DataResponse = Vec<DataRow>;
+
+#[derive(Clone, Serialize, Debug)]
+pub struct DataPage {
+    pub items: DataResponse,
+    pub next_page_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<u64>,
+}
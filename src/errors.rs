@@ -1,8 +1,13 @@
+use axum::http::header::RETRY_AFTER;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use axum_odbc::odbc;
+use serde_json::json;
 use thiserror::Error;
 
+use crate::domain::params::ParamViolation;
+
 use crate::errors::ApiError::{
     BadRequest, InternalServerErrorWithContext, InvalidLoginAttempt, NotFound, ObjectConflict,
 };
@@ -18,12 +23,17 @@ pub enum ApiError {
     InvalidLoginAttempt,
     #[error("user does not have privilege to access this resource")]
     Forbidden,
+    /// Seconds to wait before trying again
+    #[error("too many failed login attempts, retry in {0} seconds")]
+    TooManyRequests(u64),
     #[error("{0}")]
     NotFound(String),
     #[error("{0}")]
     ApplicationStartup(String),
     #[error("{0}")]
     BadRequest(String),
+    #[error("invalid request parameters")]
+    InvalidRequestParameters(Vec<ParamViolation>),
     #[error("unexpected error has occurred")]
     InternalServerError,
     #[error("{0}")]
@@ -38,9 +48,26 @@ pub enum ApiError {
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        if let ApiError::InvalidRequestParameters(violations) = self {
+            let body = json!({
+                "message": "invalid request parameters",
+                "violations": violations,
+            });
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+        if let ApiError::TooManyRequests(retry_after) = self {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+                self.to_string(),
+            )
+                .into_response();
+        }
+
         let (status, error_message) = match self {
             ApiError::Unauthorized(e) => (StatusCode::UNAUTHORIZED, e),
             InvalidLoginAttempt => (StatusCode::BAD_REQUEST, InvalidLoginAttempt.to_string()),
+            ApiError::Forbidden => (StatusCode::FORBIDDEN, ApiError::Forbidden.to_string()),
             NotFound(e) => (StatusCode::NOT_FOUND, e),
             BadRequest(e) => (StatusCode::BAD_REQUEST, e),
             InternalServerErrorWithContext(e) => (StatusCode::INTERNAL_SERVER_ERROR, e),
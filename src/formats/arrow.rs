@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Date32Array, Float64Array, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use bytes::Bytes;
+use chrono::{Datelike, NaiveDate};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+
+use crate::config::ColumnType;
+use crate::domain::columns::{parse_timestamp, ResultColumn};
+use crate::domain::req_res::DataRow;
+use crate::errors::{ApiError, ApiResult};
+use crate::formats::RowEncoder;
+
+/// `NaiveDate::num_days_from_ce` of 1970-01-01, the epoch of Arrow dates
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+/// Rows buffered in memory before a Parquet row group is written out
+const PARQUET_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Arrow IPC streaming format: the schema message, then one record batch per fetched batch
+#[derive(Default)]
+pub struct ArrowIpcEncoder {
+    columns: Vec<ResultColumn>,
+    schema: Option<SchemaRef>,
+    writer: Option<StreamWriter<Vec<u8>>>,
+}
+
+impl RowEncoder for ArrowIpcEncoder {
+    fn begin(&mut self, columns: &[ResultColumn]) -> ApiResult<Bytes> {
+        let schema = arrow_schema(columns);
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema).map_err(anyhow::Error::from)?;
+        let chunk = std::mem::take(writer.get_mut());
+        self.columns = columns.to_vec();
+        self.schema = Some(schema);
+        self.writer = Some(writer);
+        Ok(Bytes::from(chunk))
+    }
+
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes> {
+        let (Some(schema), Some(writer)) = (&self.schema, &mut self.writer) else {
+            return Err(not_started());
+        };
+        let batch = record_batch(schema, &self.columns, rows)?;
+        writer.write(&batch).map_err(anyhow::Error::from)?;
+        Ok(Bytes::from(std::mem::take(writer.get_mut())))
+    }
+
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        let writer = self.writer.as_mut().ok_or_else(not_started)?;
+        writer.finish().map_err(anyhow::Error::from)?;
+        Ok(Bytes::from(std::mem::take(writer.get_mut())))
+    }
+}
+
+/// A Parquet file. Row groups are sent as soon as they are complete; the footer, which a reader needs first, is only
+/// known once the whole result has been written.
+#[derive(Default)]
+pub struct ParquetEncoder {
+    columns: Vec<ResultColumn>,
+    schema: Option<SchemaRef>,
+    writer: Option<ArrowWriter<Vec<u8>>>,
+}
+
+impl RowEncoder for ParquetEncoder {
+    fn begin(&mut self, columns: &[ResultColumn]) -> ApiResult<Bytes> {
+        let schema = arrow_schema(columns);
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(PARQUET_ROW_GROUP_SIZE)
+            .build();
+        let writer = ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties)).map_err(anyhow::Error::from)?;
+        self.columns = columns.to_vec();
+        self.schema = Some(schema);
+        self.writer = Some(writer);
+        Ok(Bytes::new())
+    }
+
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes> {
+        let (Some(schema), Some(writer)) = (&self.schema, &mut self.writer) else {
+            return Err(not_started());
+        };
+        let batch = record_batch(schema, &self.columns, rows)?;
+        writer.write(&batch).map_err(anyhow::Error::from)?;
+        Ok(Bytes::from(std::mem::take(writer.inner_mut())))
+    }
+
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        let writer = self.writer.take().ok_or_else(not_started)?;
+        let remaining = writer.into_inner().map_err(anyhow::Error::from)?;
+        Ok(Bytes::from(remaining))
+    }
+}
+
+fn not_started() -> ApiError {
+    ApiError::InternalServerErrorWithContext("Rows were encoded before the columns were known".into())
+}
+
+/// Decimals are kept as text so that no precision is lost, JSON documents as their serialized text
+fn arrow_schema(columns: &[ResultColumn]) -> SchemaRef {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Int => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Bool => DataType::Boolean,
+                ColumnType::Date => DataType::Date32,
+                ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+                ColumnType::String | ColumnType::Decimal | ColumnType::Json => DataType::Utf8,
+            };
+            Field::new(column.name.as_str(), data_type, true)
+        })
+        .collect::<Vec<_>>();
+    Arc::new(Schema::new(fields))
+}
+
+/// A value that could not be converted to the column type was kept as a string in the row; it becomes null here.
+fn record_batch(schema: &SchemaRef, columns: &[ResultColumn], rows: &[DataRow]) -> ApiResult<RecordBatch> {
+    let arrays = columns
+        .iter()
+        .map(|column| {
+            let values = rows.iter().map(|row| row.get(&column.name).unwrap_or(&Value::Null));
+            let array: ArrayRef = match column.column_type {
+                ColumnType::Int => Arc::new(values.map(Value::as_i64).collect::<Int64Array>()),
+                ColumnType::Float => Arc::new(values.map(Value::as_f64).collect::<Float64Array>()),
+                ColumnType::Bool => Arc::new(values.map(Value::as_bool).collect::<BooleanArray>()),
+                ColumnType::Date => Arc::new(values.map(days_since_epoch).collect::<Date32Array>()),
+                ColumnType::Timestamp => Arc::new(
+                    values
+                        .map(|v| {
+                            v.as_str()
+                                .and_then(parse_timestamp)
+                                .map(|ts| ts.and_utc().timestamp_micros())
+                        })
+                        .collect::<TimestampMicrosecondArray>(),
+                ),
+                ColumnType::String | ColumnType::Decimal | ColumnType::Json => Arc::new(
+                    values
+                        .map(|v| match v {
+                            Value::Null => None,
+                            Value::String(s) => Some(s.clone()),
+                            other => Some(other.to_string()),
+                        })
+                        .collect::<StringArray>(),
+                ),
+            };
+            array
+        })
+        .collect::<Vec<_>>();
+    Ok(RecordBatch::try_new(schema.clone(), arrays).map_err(anyhow::Error::from)?)
+}
+
+fn days_since_epoch(value: &Value) -> Option<i32> {
+    let date = NaiveDate::parse_from_str(value.as_str()?, "%Y-%m-%d").ok()?;
+    Some(date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Array, Date32Array, Int64Array};
+    use arrow_ipc::reader::StreamReader;
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    use crate::config::ColumnType;
+    use crate::domain::columns::ResultColumn;
+    use crate::domain::req_res::DataRow;
+    use crate::formats::arrow::{ArrowIpcEncoder, ParquetEncoder, PARQUET_ROW_GROUP_SIZE};
+    use crate::formats::RowEncoder;
+
+    #[test]
+    fn test_arrow_stream_round_trip() {
+        let columns = vec![
+            ResultColumn {
+                name: "age".to_string(),
+                column_type: ColumnType::Int,
+                mask: None,
+            },
+            ResultColumn {
+                name: "born".to_string(),
+                column_type: ColumnType::Date,
+                mask: None,
+            },
+        ];
+        let rows = vec![
+            DataRow::from_iter([
+                ("age".to_string(), json!(30)),
+                ("born".to_string(), json!("1970-01-02")),
+            ]),
+            DataRow::from_iter([("age".to_string(), json!(null)), ("born".to_string(), json!(null))]),
+        ];
+
+        let mut encoder = ArrowIpcEncoder::default();
+        let mut body = encoder.begin(&columns).unwrap().to_vec();
+        body.extend_from_slice(&encoder.encode(&rows).unwrap());
+        body.extend_from_slice(&encoder.finish().unwrap());
+
+        let batches = StreamReader::try_new(body.as_slice(), None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let ages = batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(ages.value(0), 30);
+        assert!(ages.is_null(1));
+        let born = batches[0].column(1).as_any().downcast_ref::<Date32Array>().unwrap();
+        assert_eq!(born.value(0), 1);
+    }
+
+    #[test]
+    fn test_parquet_row_groups_are_sent_as_they_fill_up() {
+        let columns = vec![ResultColumn {
+            name: "id".to_string(),
+            column_type: ColumnType::Int,
+            mask: None,
+        }];
+        let batch_rows = PARQUET_ROW_GROUP_SIZE * 3 / 4;
+        let batch = |first: usize| {
+            (first..first + batch_rows)
+                .map(|id| DataRow::from_iter([("id".to_string(), json!(id))]))
+                .collect::<Vec<_>>()
+        };
+
+        let mut encoder = ParquetEncoder::default();
+        let mut body = encoder.begin(&columns).unwrap().to_vec();
+        let mut chunks = Vec::new();
+        for first in [0, batch_rows, 2 * batch_rows] {
+            let chunk = encoder.encode(&batch(first)).unwrap();
+            chunks.push(chunk.len());
+            body.extend_from_slice(&chunk);
+        }
+        body.extend_from_slice(&encoder.finish().unwrap());
+        assert!(
+            chunks.iter().any(|len| *len > 0),
+            "no row group was sent before the end"
+        );
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(body))
+            .unwrap()
+            .build()
+            .unwrap();
+        let ids = reader
+            .map(|batch| batch.unwrap())
+            .flat_map(|batch| {
+                let ids = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap().clone();
+                ids.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (0..3 * batch_rows as i64).collect::<Vec<_>>());
+    }
+}
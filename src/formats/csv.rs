@@ -0,0 +1,91 @@
+use bytes::Bytes;
+use serde_json::Value;
+
+use crate::domain::columns::ResultColumn;
+use crate::domain::req_res::DataRow;
+use crate::errors::ApiResult;
+use crate::formats::RowEncoder;
+
+/// RFC 4180 CSV with a header row. NULL is written as an empty field and JSON documents as their serialized text.
+#[derive(Default)]
+pub struct CsvEncoder {
+    columns: Vec<String>,
+}
+
+/// Writes the records of one chunk
+fn write_records<I, R>(records: I) -> ApiResult<Bytes>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    let mut writer = ::csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.write_record(record).map_err(anyhow::Error::from)?;
+    }
+    let buf = writer.into_inner().map_err(|e| anyhow::anyhow!("{}", e))?;
+    Ok(Bytes::from(buf))
+}
+
+impl RowEncoder for CsvEncoder {
+    fn begin(&mut self, columns: &[ResultColumn]) -> ApiResult<Bytes> {
+        self.columns = columns.iter().map(|column| column.name.clone()).collect();
+        write_records([&self.columns])
+    }
+
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes> {
+        write_records(rows.iter().map(|row| {
+            self.columns.iter().map(|column| match row.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+            })
+        }))
+    }
+
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        Ok(Bytes::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::config::ColumnType;
+    use crate::domain::columns::ResultColumn;
+    use crate::domain::req_res::DataRow;
+    use crate::formats::csv::CsvEncoder;
+    use crate::formats::RowEncoder;
+
+    #[test]
+    fn test_csv_quotes_fields_and_leaves_nulls_empty() {
+        let columns = ["name", "age", "tags"]
+            .into_iter()
+            .map(|name| ResultColumn {
+                name: name.to_string(),
+                column_type: ColumnType::String,
+                mask: None,
+            })
+            .collect::<Vec<_>>();
+        let rows = [
+            DataRow::from_iter([
+                ("name".to_string(), json!("Doe, \"Jane\"")),
+                ("age".to_string(), json!(30)),
+                ("tags".to_string(), json!(["a", "b"])),
+            ]),
+            DataRow::from_iter([("name".to_string(), json!("Roe")), ("age".to_string(), json!(null))]),
+        ];
+
+        let mut encoder = CsvEncoder::default();
+        let mut body = encoder.begin(&columns).unwrap().to_vec();
+        body.extend_from_slice(&encoder.encode(&rows[..1]).unwrap());
+        body.extend_from_slice(&encoder.encode(&rows[1..]).unwrap());
+        body.extend_from_slice(&encoder.finish().unwrap());
+
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "name,age,tags\n\"Doe, \"\"Jane\"\"\",30,\"[\"\"a\"\",\"\"b\"\"]\"\nRoe,,\n"
+        );
+    }
+}
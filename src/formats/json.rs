@@ -0,0 +1,55 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::domain::columns::ResultColumn;
+use crate::domain::req_res::DataRow;
+use crate::errors::ApiResult;
+use crate::formats::RowEncoder;
+
+/// A single JSON array, sent with chunked transfer encoding
+#[derive(Default)]
+pub struct JsonArrayEncoder {
+    rows_written: bool,
+}
+
+impl RowEncoder for JsonArrayEncoder {
+    fn begin(&mut self, _columns: &[ResultColumn]) -> ApiResult<Bytes> {
+        Ok(Bytes::from_static(b"["))
+    }
+
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes> {
+        let mut buf = BytesMut::new().writer();
+        for row in rows {
+            if self.rows_written {
+                buf.get_mut().put_u8(b',');
+            }
+            serde_json::to_writer(&mut buf, row).map_err(anyhow::Error::from)?;
+            self.rows_written = true;
+        }
+        Ok(buf.into_inner().freeze())
+    }
+
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        Ok(Bytes::from_static(b"]"))
+    }
+}
+
+pub struct NdjsonEncoder;
+
+impl RowEncoder for NdjsonEncoder {
+    fn begin(&mut self, _columns: &[ResultColumn]) -> ApiResult<Bytes> {
+        Ok(Bytes::new())
+    }
+
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes> {
+        let mut buf = BytesMut::new().writer();
+        for row in rows {
+            serde_json::to_writer(&mut buf, row).map_err(anyhow::Error::from)?;
+            buf.get_mut().put_u8(b'\n');
+        }
+        Ok(buf.into_inner().freeze())
+    }
+
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        Ok(Bytes::new())
+    }
+}
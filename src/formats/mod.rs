@@ -0,0 +1,219 @@
+use axum::body::Body;
+use axum::http::header::ACCEPT;
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use futures_util::stream;
+
+use crate::domain::columns::ResultColumn;
+use crate::domain::params::ParamViolation;
+use crate::domain::req_res::DataRow;
+use crate::errors::{ApiError, ApiResult};
+use crate::repositories::data_repository::{RowBatchReceiver, RowEvent};
+
+mod arrow;
+mod csv;
+mod json;
+
+/// Query parameter that picks the output format, taking precedence over the `Accept` header
+pub const FORMAT_PARAM: &str = "format";
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+pub const ARROW_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+pub const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+
+/// Wire formats of the data endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    Csv,
+    /// Arrow IPC streaming format
+    Arrow,
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Picks the format from the `format` query parameter, or else from the supported media type of the `Accept` header
+    /// with the highest quality (`q`), the first one listed on a tie. Anything else falls back to JSON.
+    pub fn negotiate(format_param: Option<&str>, headers: &HeaderMap) -> Result<OutputFormat, ParamViolation> {
+        if let Some(name) = format_param {
+            return OutputFormat::from_name(name)
+                .ok_or_else(|| ParamViolation::new(FORMAT_PARAM, "expected one of json, ndjson, csv, arrow, parquet"));
+        }
+        let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        let mut best: Option<(OutputFormat, f32)> = None;
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';');
+            let Some(format) = parts
+                .next()
+                .and_then(|media_type| OutputFormat::from_media_type(media_type.trim()))
+            else {
+                continue;
+            };
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                best = Some((format, quality));
+            }
+        }
+        Ok(best.map(|(format, _)| format).unwrap_or(OutputFormat::Json))
+    }
+
+    fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(OutputFormat::Json),
+            "ndjson" | "jsonl" => Some(OutputFormat::Ndjson),
+            "csv" => Some(OutputFormat::Csv),
+            "arrow" => Some(OutputFormat::Arrow),
+            "parquet" => Some(OutputFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<OutputFormat> {
+        match media_type.to_lowercase().as_str() {
+            JSON_CONTENT_TYPE => Some(OutputFormat::Json),
+            NDJSON_CONTENT_TYPE | "application/ndjson" | "application/jsonl" => Some(OutputFormat::Ndjson),
+            CSV_CONTENT_TYPE => Some(OutputFormat::Csv),
+            ARROW_CONTENT_TYPE | "application/vnd.apache.arrow.file" => Some(OutputFormat::Arrow),
+            PARQUET_CONTENT_TYPE | "application/x-parquet" => Some(OutputFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => JSON_CONTENT_TYPE,
+            OutputFormat::Ndjson => NDJSON_CONTENT_TYPE,
+            OutputFormat::Csv => CSV_CONTENT_TYPE,
+            OutputFormat::Arrow => ARROW_CONTENT_TYPE,
+            OutputFormat::Parquet => PARQUET_CONTENT_TYPE,
+        }
+    }
+
+    /// Plain JSON is the only format that is paginated; the others always stream the whole result
+    pub fn is_streamed(&self, stream_json: bool) -> bool {
+        *self != OutputFormat::Json || stream_json
+    }
+
+    fn encoder(&self) -> Box<dyn RowEncoder> {
+        match self {
+            OutputFormat::Json => Box::<json::JsonArrayEncoder>::default(),
+            OutputFormat::Ndjson => Box::new(json::NdjsonEncoder),
+            OutputFormat::Csv => Box::new(csv::CsvEncoder::default()),
+            OutputFormat::Arrow => Box::<arrow::ArrowIpcEncoder>::default(),
+            OutputFormat::Parquet => Box::<arrow::ParquetEncoder>::default(),
+        }
+    }
+}
+
+/// Writes a result set batch by batch. Each call returns the bytes that are ready to be sent.
+trait RowEncoder: Send {
+    fn begin(&mut self, columns: &[ResultColumn]) -> ApiResult<Bytes>;
+    fn encode(&mut self, rows: &[DataRow]) -> ApiResult<Bytes>;
+    fn finish(&mut self) -> ApiResult<Bytes>;
+}
+
+/// Turns the batches of a streaming query into a response body. The first event is awaited before responding, so
+/// that a statement which fails to execute is still reported with an error status; a failure after that can only
+/// abort the body.
+pub async fn stream_body(format: OutputFormat, mut receiver: RowBatchReceiver) -> ApiResult<Body> {
+    let first = match receiver.recv().await {
+        Some(Err(e)) => return Err(e),
+        first => first,
+    };
+
+    let state = BodyState {
+        encoder: format.encoder(),
+        receiver,
+        pending: first,
+        begun: false,
+        finished: false,
+    };
+
+    let body_stream = stream::unfold(state, |mut state| async move {
+        if state.finished {
+            return None;
+        }
+        let next = match state.pending.take() {
+            Some(event) => Some(event),
+            None => state.receiver.recv().await,
+        };
+        let chunk = match next {
+            Some(Ok(RowEvent::Columns(columns))) => {
+                state.begun = true;
+                state.encoder.begin(&columns)
+            }
+            Some(Ok(RowEvent::Rows(rows))) => state.encoder.encode(&rows),
+            Some(Err(e)) => Err(e),
+            None => state.finish(),
+        };
+        if chunk.is_err() {
+            state.finished = true;
+        }
+        Some((chunk, state))
+    });
+
+    Ok(Body::from_stream(body_stream))
+}
+
+struct BodyState {
+    encoder: Box<dyn RowEncoder>,
+    receiver: RowBatchReceiver,
+    pending: Option<Result<RowEvent, ApiError>>,
+    begun: bool,
+    finished: bool,
+}
+
+impl BodyState {
+    fn finish(&mut self) -> ApiResult<Bytes> {
+        self.finished = true;
+        let mut chunk = if self.begun { Vec::new() } else { self.encoder.begin(&[])?.to_vec() };
+        chunk.extend_from_slice(&self.encoder.finish()?);
+        Ok(Bytes::from(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::ACCEPT;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use crate::formats::OutputFormat;
+
+    #[test]
+    fn test_format_param_wins_over_accept_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("text/html;q=0.9, text/csv;q=0.8, */*"));
+
+        assert_eq!(OutputFormat::negotiate(None, &headers), Ok(OutputFormat::Csv));
+        assert_eq!(
+            OutputFormat::negotiate(Some("parquet"), &headers),
+            Ok(OutputFormat::Parquet)
+        );
+        assert_eq!(OutputFormat::negotiate(None, &HeaderMap::new()), Ok(OutputFormat::Json));
+        assert!(OutputFormat::negotiate(Some("xml"), &headers).is_err());
+    }
+
+    #[test]
+    fn test_accept_header_quality_decides() {
+        let negotiate = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static(accept));
+            OutputFormat::negotiate(None, &headers).unwrap()
+        };
+
+        assert_eq!(negotiate("text/csv;q=0.1, application/json"), OutputFormat::Json);
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/vnd.apache.parquet;q=0.9"),
+            OutputFormat::Parquet
+        );
+        assert_eq!(negotiate("text/csv, application/x-ndjson"), OutputFormat::Csv);
+        assert_eq!(negotiate("text/csv;q=0"), OutputFormat::Json);
+    }
+}
@@ -1,23 +1,47 @@
 extern crate core;
 
+use std::sync::Arc;
+
 use crate::config::AppConfig;
+use crate::services::api_key_service::ApiKeyService;
+use crate::services::oidc_service::OidcService;
+use crate::services::token_service::TokenService;
 
 pub mod auth;
 pub mod config;
+pub mod datasource;
 pub mod domain;
 pub mod errors;
+pub mod formats;
+pub mod mailer;
+pub mod openapi;
 pub mod repositories;
 pub mod routes;
+pub mod secrets;
 pub mod service_register;
 pub mod services;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: AppConfig,
+    pub token_service: Arc<TokenService>,
+    pub api_key_service: Arc<ApiKeyService>,
+    /// Set when the tokens of an external OpenID Connect provider are accepted
+    pub oidc_service: Option<Arc<OidcService>>,
 }
 
 impl AppState {
-    pub fn new(config: AppConfig) -> Self {
-        Self { config }
+    pub fn new(
+        config: AppConfig,
+        token_service: Arc<TokenService>,
+        api_key_service: Arc<ApiKeyService>,
+        oidc_service: Option<Arc<OidcService>>,
+    ) -> Self {
+        Self {
+            config,
+            token_service,
+            api_key_service,
+            oidc_service,
+        }
     }
 }
@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::{MailTransportConfig, MailerConfig, SmtpConfig, SmtpTls};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> anyhow::Result<()>;
+}
+
+/// The mailer of the `mailer:` block, or one that logs the mails when there is none
+pub fn from_config(config: Option<&MailerConfig>) -> anyhow::Result<Arc<dyn Mailer>> {
+    let Some(config) = config else {
+        warn!("No mailer is configured, mails are logged instead of sent");
+        return Ok(Arc::new(LogMailer));
+    };
+    let from = parse_mailbox(&config.from).context("Invalid mailer.from")?;
+    Ok(match &config.transport {
+        MailTransportConfig::Smtp(smtp) => Arc::new(SmtpMailer::new(from, smtp)?),
+        MailTransportConfig::File { directory } => Arc::new(FileMailer::new(from, directory)?),
+        MailTransportConfig::Log => Arc::new(LogMailer),
+    })
+}
+
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(from: Mailbox, config: &SmtpConfig) -> anyhow::Result<Self> {
+        let (builder, default_port) = match config.tls {
+            SmtpTls::Starttls => (AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?, 587),
+            SmtpTls::Tls => (AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?, 465),
+            SmtpTls::None => (
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+                25,
+            ),
+        };
+        let mut builder = builder.port(config.port.unwrap_or(default_port));
+        if let Some(username) = &config.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                config
+                    .password
+                    .as_ref()
+                    .map(|password| password.expose_secret().clone())
+                    .unwrap_or_default(),
+            ));
+        }
+        Ok(Self {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        let message = build_message(&self.from, email)?;
+        self.transport
+            .send(message)
+            .await
+            .with_context(|| format!("Unable to send mail to {}", email.to))?;
+        info!("Mail '{}' sent to {}", email.subject, email.to);
+        Ok(())
+    }
+}
+
+/// Writes every mail as an `.eml` file, which mail clients can open
+pub struct FileMailer {
+    from: Mailbox,
+    directory: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(from: Mailbox, directory: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(directory).with_context(|| format!("Unable to create mail directory {}", directory))?;
+        Ok(Self {
+            from,
+            directory: PathBuf::from(directory),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        let message = build_message(&self.from, email)?;
+        let path = self.directory.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4().simple()
+        ));
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .with_context(|| format!("Unable to write mail to {}", path.display()))?;
+        info!("Mail '{}' to {} written to {}", email.subject, email.to, path.display());
+        Ok(())
+    }
+}
+
+/// Logs every mail, tokens and links included: for local use only
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        info!("Mail to {}: {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
+
+fn build_message(from: &Mailbox, email: &Email) -> anyhow::Result<Message> {
+    Message::builder()
+        .from(from.clone())
+        .to(parse_mailbox(&email.to)?)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body.clone())
+        .context("Unable to build mail")
+}
+
+fn parse_mailbox(address: &str) -> anyhow::Result<Mailbox> {
+    address
+        .parse()
+        .with_context(|| format!("{} is not a valid mail address", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mailer::{Email, FileMailer, Mailer};
+
+    #[tokio::test]
+    async fn test_file_mailer_writes_eml_files() {
+        let directory = std::env::temp_dir().join(format!("nexus-mail-{}", uuid::Uuid::new_v4().simple()));
+        let mailer = FileMailer::new("nexus <nexus@localhost>".parse().unwrap(), directory.to_str().unwrap()).unwrap();
+        mailer
+            .send(&Email {
+                to: "alice@example.com".to_string(),
+                subject: "Verify your email address".to_string(),
+                body: "token: abc".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let files = std::fs::read_dir(&directory).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.contains("To: alice@example.com"));
+        assert!(contents.contains("Subject: Verify your email address"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
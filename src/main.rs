@@ -1,24 +1,50 @@
-use anyhow::{anyhow, Context};
-use axum_odbc::ODBCConnectionManager;
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use secrecy::ExposeSecret;
-use tera::Tera;
-use tracing::error;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
-use nexus::config::{AppConfig};
-use nexus::errors::ApiError;
+use nexus::config::AppConfig;
+use nexus::repositories::sql_template::SqlTemplates;
 use nexus::routes::AppController;
 use nexus::service_register::ServiceRegister;
 use nexus::AppState;
 
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    /// Path to the nexus configuration file
+    #[arg(short, long, env = "NEXUS_CONFIG", default_value = "config/nexus.yaml")]
+    config: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs the API server, which is the default
+    Serve,
+    /// Creates a user holding the admin role, such as the first admin of a deployment where registration is closed
+    CreateAdmin {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: String,
+        /// Read from standard input when not given
+        #[arg(long, env = "NEXUS_ADMIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
-    let config = AppConfig::get_configuration("config/customer_master.yaml")?; //TODO - get as args
+    let args = Args::parse();
+    let config = AppConfig::get_configuration(&args.config)?;
     let api_host = config.api.host.to_string();
     let api_port = config.api.port;
 
@@ -27,11 +53,28 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let pool = create_db_manager();
-    let tera = create_tera(&config).unwrap();
-    let app_state = AppState::new(config.clone());
+    let templates = SqlTemplates::new(&config.endpoints)?;
+    let service_register = ServiceRegister::new(config.clone(), templates)?;
+
+    if let Some(Command::CreateAdmin { name, email, password }) = args.command {
+        let password = match password {
+            Some(password) => password,
+            None => read_password()?,
+        };
+        let admin = service_register
+            .user_service
+            .create_admin_handler(&name, &email, &password)
+            .await?;
+        println!("Admin {} created with id {}", admin.email, admin.id);
+        return Ok(());
+    }
 
-    let service_register = ServiceRegister::new(config, pool, tera);
+    let app_state = AppState::new(
+        config,
+        service_register.token_service.clone(),
+        service_register.api_key_service.clone(),
+        service_register.oidc_service.clone(),
+    );
 
     AppController::serve(app_state, service_register)
         .await
@@ -40,30 +83,17 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn create_db_manager() -> ODBCConnectionManager {
-   /* let db_url = format!(
-        "Driver={};server={};database={};schema={};warehouse={};role={};UID={};PWD={}",
-        db_cfg.driver,
-        db_cfg.hostname,
-        db_cfg.database,
-        db_cfg.schema,
-        db_cfg.warehouse,
-        db_cfg.role,
-        db_cfg.username,
-        db_cfg.password.expose_secret()
-    );
-    ODBCConnectionManager::new(db_url, 4)*/
-    ODBCConnectionManager::new("DSN=snowflake", 4)
-}
-
-fn create_tera(app_cfg: &AppConfig) -> Result<Tera, ApiError> {
-    let mut tera = Tera::default();
-    tera.autoescape_on(vec![]); //TODO - This could protect from sql injection.  Need to investigate further
-
-    tera.add_raw_template("sql", &app_cfg.sql).map_err(|e| {
-        error!("Error while rendering template: {}", e);
-        anyhow!(format!("SqlTemplatingError {}", e.to_string()))
-    })?;
-
-    Ok(tera)
+fn read_password() -> anyhow::Result<String> {
+    eprint!("Password: ");
+    std::io::stderr().flush()?;
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Unable to read the password")?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        bail!("No password given");
+    }
+    Ok(password)
 }
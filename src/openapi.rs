@@ -0,0 +1,1041 @@
+use serde_json::{json, Map, Value};
+
+use crate::auth::API_KEY_HEADER;
+use crate::config::{AppConfig, ColumnConfig, ColumnType, EndpointConfig, MaskPolicy, ParamConfig, ParamType};
+use crate::domain::pagination::{PAGE_SIZE_PARAM, PAGE_TOKEN_PARAM};
+use crate::formats::{
+    ARROW_CONTENT_TYPE, CSV_CONTENT_TYPE, FORMAT_PARAM, JSON_CONTENT_TYPE, NDJSON_CONTENT_TYPE, PARQUET_CONTENT_TYPE,
+};
+
+const OPENAPI_VERSION: &str = "3.1.0";
+const BEARER_AUTH: &str = "bearerAuth";
+const API_KEY_AUTH: &str = "apiKeyAuth";
+
+/// Builds the OpenAPI document of the user routes and of every configured data endpoint
+pub fn openapi_document(config: &AppConfig) -> Value {
+    let mut paths = user_paths();
+    for endpoint in &config.endpoints {
+        paths.insert(
+            openapi_path(&endpoint.endpoint),
+            json!({ "get": data_operation(endpoint) }),
+        );
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "nexus",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                BEARER_AUTH: {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                },
+                API_KEY_AUTH: {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": API_KEY_HEADER,
+                },
+            },
+            "schemas": component_schemas(),
+        },
+    })
+}
+
+/// axum's `:param` path segments become OpenAPI's `{param}`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn user_paths() -> Map<String, Value> {
+    let mut paths = Map::new();
+    paths.insert(
+        "/api/users/register".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "registerUser",
+                "summary": "Registers a new user and returns its id",
+                "requestBody": json_body("RegisterUser"),
+                "responses": {
+                    "200": {
+                        "description": "Id of the new user",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "400": error_response("The invite code is missing, invalid, used or expired"),
+                    "403": error_response("Registration is closed"),
+                    "409": error_response("A user with that email already exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/login".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "loginUser",
+                "summary": "Exchanges an email and a password for an access token",
+                "requestBody": json_body("LoginUserRequest"),
+                "responses": {
+                    "200": {
+                        "description": "The user, with its access token",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("LoginUserResponse") } },
+                    },
+                    "400": error_response("The password is incorrect"),
+                    "401": error_response("The account is disabled or its email address is not verified"),
+                    "404": error_response("No user with that email exists"),
+                    "429": {
+                        "description": "Too many failed logins for the account or the client address",
+                        "headers": {
+                            "Retry-After": {
+                                "description": "Seconds to wait before trying again",
+                                "schema": { "type": "integer" },
+                            },
+                        },
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/refresh".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "refreshTokens",
+                "summary": "Exchanges a refresh token for a new access token and a new refresh token",
+                "requestBody": json_body("RefreshTokenRequest"),
+                "responses": {
+                    "200": {
+                        "description": "The new tokens; the refresh token that was sent can no longer be used",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("Tokens") } },
+                    },
+                    "401": error_response("The refresh token is invalid, expired, revoked or was already used"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/verify-email".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "verifyEmail",
+                "summary": "Verifies an email address with the token mailed to it",
+                "requestBody": json_body("VerifyEmailRequest"),
+                "responses": {
+                    "204": { "description": "The email address is verified" },
+                    "400": error_response("The token is invalid, was already used or has expired"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/resend-verification".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "resendVerification",
+                "summary": "Mails a new verification link if the address belongs to an unverified user",
+                "requestBody": json_body("EmailRequest"),
+                "responses": {
+                    "202": { "description": "Accepted, whether or not a mail was sent" },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/forgot-password".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "forgotPassword",
+                "summary": "Mails a password reset link if the address belongs to an active user",
+                "requestBody": json_body("EmailRequest"),
+                "responses": {
+                    "202": { "description": "Accepted, whether or not a mail was sent" },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/reset-password".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "resetPassword",
+                "summary": "Sets a new password with the token of a password reset mail and revokes every login",
+                "requestBody": json_body("ResetPasswordRequest"),
+                "responses": {
+                    "204": { "description": "The password was changed" },
+                    "400": error_response("The token is invalid, used or expired, or the password breaks the policy"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/logout".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "logoutUser",
+                "summary": "Revokes the access and refresh tokens of the current login",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "204": { "description": "Logged out" },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/api-keys".into(),
+        json!({
+            "get": {
+                "tags": ["users"],
+                "operationId": "listApiKeys",
+                "summary": "Lists the API keys of the current user that were not revoked",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "200": {
+                        "description": "The API keys, without their secrets",
+                        "content": {
+                            JSON_CONTENT_TYPE: { "schema": { "type": "array", "items": schema_ref("ApiKey") } }
+                        },
+                    },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "API keys cannot manage API keys" },
+                },
+            },
+            "post": {
+                "tags": ["users"],
+                "operationId": "createApiKey",
+                "summary": "Creates an API key scoped to some data endpoints",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": json_body("CreateApiKeyRequest"),
+                "responses": {
+                    "201": {
+                        "description": "The API key; `api_key` is not shown again",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("CreatedApiKey") } },
+                    },
+                    "400": error_response("The name, scopes or expiry are invalid"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "API keys cannot manage API keys" },
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/api/users/api-keys/{key_id}".into(),
+        json!({
+            "delete": {
+                "tags": ["users"],
+                "operationId": "revokeApiKey",
+                "summary": "Revokes an API key of the current user",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [{
+                    "name": "key_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "204": { "description": "The API key was revoked" },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "API keys cannot manage API keys" },
+                    "404": error_response("The user has no such API key"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/users".into(),
+        json!({
+            "get": {
+                "tags": ["admin"],
+                "operationId": "listUsers",
+                "summary": "Lists the users, oldest first, without their roles and attributes",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [
+                    {
+                        "name": "q",
+                        "in": "query",
+                        "description": "Part of the name or of the email to look for, ignoring case",
+                        "schema": { "type": "string" },
+                    },
+                    {
+                        "name": "limit",
+                        "in": "query",
+                        "schema": { "type": "integer", "minimum": 1, "maximum": 500, "default": 50 },
+                    },
+                    {
+                        "name": "offset",
+                        "in": "query",
+                        "schema": { "type": "integer", "minimum": 0, "default": 0 },
+                    },
+                ],
+                "responses": {
+                    "200": {
+                        "description": "A page of users",
+                        "content": {
+                            JSON_CONTENT_TYPE: { "schema": { "type": "array", "items": schema_ref("User") } },
+                        },
+                    },
+                    "400": error_response("The limit is out of range"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/users/{user_id}".into(),
+        json!({
+            "get": {
+                "tags": ["admin"],
+                "operationId": "getUser",
+                "summary": "Returns a user with its roles and attributes",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "responses": {
+                    "200": {
+                        "description": "The user",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                    },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            },
+            "delete": {
+                "tags": ["admin"],
+                "operationId": "deleteUser",
+                "summary": "Deletes a user with its roles, attributes and API keys, and revokes its sessions",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "responses": {
+                    "204": { "description": "The user was deleted" },
+                    "400": error_response("Admins cannot delete their own account"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            },
+        }),
+    );
+    for (action, operation_id, summary) in [
+        (
+            "disable",
+            "disableUser",
+            "Disables a user, who can no longer log in or use its API keys, and revokes its sessions",
+        ),
+        ("enable", "enableUser", "Enables a disabled user again"),
+    ] {
+        paths.insert(
+            format!("/api/admin/users/{{user_id}}/{}", action),
+            json!({
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": operation_id,
+                    "summary": summary,
+                    "security": [{ BEARER_AUTH: [] }],
+                    "parameters": [user_id_parameter()],
+                    "responses": {
+                        "200": {
+                            "description": "The user",
+                            "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                        },
+                        "400": error_response("Admins cannot disable their own account"),
+                        "401": { "description": "The bearer token is missing or invalid" },
+                        "403": { "description": "The caller is not an admin" },
+                        "404": error_response("No user with that id exists"),
+                    },
+                }
+            }),
+        );
+    }
+    paths.insert(
+        "/api/admin/users/{user_id}/revoke-sessions".into(),
+        json!({
+            "post": {
+                "tags": ["admin"],
+                "operationId": "revokeUserSessions",
+                "summary": "Revokes every access and refresh token of a user",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "responses": {
+                    "204": { "description": "All the sessions of the user were revoked" },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/users/{user_id}/roles".into(),
+        json!({
+            "put": {
+                "tags": ["admin"],
+                "operationId": "setUserRoles",
+                "summary": "Replaces the roles of a user and revokes its sessions",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "requestBody": json_body("UpdateRolesRequest"),
+                "responses": {
+                    "200": {
+                        "description": "The user with its new roles",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                    },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/users/{user_id}/attributes".into(),
+        json!({
+            "put": {
+                "tags": ["admin"],
+                "operationId": "setUserAttributes",
+                "summary": "Replaces the attributes of a user, read by SQL templates as auth.attributes, and revokes its sessions",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "requestBody": json_body("UpdateAttributesRequest"),
+                "responses": {
+                    "200": {
+                        "description": "The user with its new attributes",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                    },
+                    "400": error_response("An attribute name is not a valid identifier"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/users/{user_id}/unlock".into(),
+        json!({
+            "post": {
+                "tags": ["admin"],
+                "operationId": "unlockUser",
+                "summary": "Clears the failed logins of a user, lifting its lockout",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [user_id_parameter()],
+                "responses": {
+                    "204": { "description": "The user can log in again" },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No user with that id exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/addresses/{address}/unlock".into(),
+        json!({
+            "post": {
+                "tags": ["admin"],
+                "operationId": "unlockAddress",
+                "summary": "Clears the failed logins of a client address, lifting its lockout",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [{
+                    "name": "address",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "204": { "description": "Logins from the address are allowed again" },
+                    "400": error_response("The address is not an IP address"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/admin/invites".into(),
+        json!({
+            "get": {
+                "tags": ["admin"],
+                "operationId": "listInvites",
+                "summary": "Lists the invites that were not used yet, oldest first",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "200": {
+                        "description": "The unused invites, expired ones included",
+                        "content": {
+                            JSON_CONTENT_TYPE: { "schema": { "type": "array", "items": schema_ref("Invite") } },
+                        },
+                    },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                },
+            },
+            "post": {
+                "tags": ["admin"],
+                "operationId": "createInvite",
+                "summary": "Creates an invite to register. The code is only returned in this response.",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": json_body("CreateInviteRequest"),
+                "responses": {
+                    "201": {
+                        "description": "The invite with its code",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("CreatedInvite") } },
+                    },
+                    "400": error_response("The email or the expiry is invalid"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/api/admin/invites/{invite_id}".into(),
+        json!({
+            "delete": {
+                "tags": ["admin"],
+                "operationId": "deleteInvite",
+                "summary": "Revokes an unused invite",
+                "security": [{ BEARER_AUTH: [] }],
+                "parameters": [{
+                    "name": "invite_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "204": { "description": "The invite was revoked" },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller is not an admin" },
+                    "404": error_response("No unused invite with that id exists"),
+                },
+            }
+        }),
+    );
+    paths.insert(
+        "/api/users/me".into(),
+        json!({
+            "get": {
+                "tags": ["users"],
+                "operationId": "getMe",
+                "summary": "Returns the authenticated user",
+                "security": [{ BEARER_AUTH: [] }],
+                "responses": {
+                    "200": {
+                        "description": "The authenticated user",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                    },
+                    "401": { "description": "The bearer token is missing or invalid" },
+                },
+            },
+            "patch": {
+                "tags": ["users"],
+                "operationId": "updateMe",
+                "summary": "Changes the name or the email of the authenticated user",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": json_body("UpdateProfileRequest"),
+                "responses": {
+                    "200": {
+                        "description": "The updated user",
+                        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("User") } },
+                    },
+                    "400": error_response("The name or the email is invalid"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller authenticated with an API key" },
+                    "409": error_response("Another user has that email"),
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/api/users/me/password".into(),
+        json!({
+            "post": {
+                "tags": ["users"],
+                "operationId": "changePassword",
+                "summary": "Changes the password of the authenticated user and revokes all of its logins",
+                "security": [{ BEARER_AUTH: [] }],
+                "requestBody": json_body("ChangePasswordRequest"),
+                "responses": {
+                    "204": { "description": "The password was changed; the user has to log in again" },
+                    "400": error_response("The current password is wrong or the new one breaks the password policy"),
+                    "401": { "description": "The bearer token is missing or invalid" },
+                    "403": { "description": "The caller authenticated with an API key" },
+                    "429": error_response("Too many wrong passwords; see the Retry-After header"),
+                },
+            }
+        }),
+    );
+    paths
+}
+
+fn data_operation(endpoint: &EndpointConfig) -> Value {
+    let mut names = endpoint.request.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut parameters = names
+        .into_iter()
+        .map(|name| request_parameter(name, &endpoint.request[name]))
+        .collect::<Vec<_>>();
+
+    parameters.push(json!({
+        "name": FORMAT_PARAM,
+        "in": "query",
+        "description": "Output format; takes precedence over the Accept header",
+        "schema": { "type": "string", "enum": ["json", "ndjson", "csv", "arrow", "parquet"] },
+    }));
+
+    let row = row_schema(endpoint);
+    let json_schema = if endpoint.stream {
+        json!({ "type": "array", "items": row })
+    } else {
+        parameters.push(json!({
+            "name": PAGE_SIZE_PARAM,
+            "in": "query",
+            "description": "Number of rows per page of a JSON response",
+            "schema": {
+                "type": "integer",
+                "minimum": 1,
+                "maximum": endpoint.pagination.max_page_size,
+                "default": endpoint.pagination.default_page_size,
+            },
+        }));
+        parameters.push(json!({
+            "name": PAGE_TOKEN_PARAM,
+            "in": "query",
+            "description": "The next_page_token of the previous page",
+            "schema": { "type": "string" },
+        }));
+        page_schema(endpoint, row)
+    };
+
+    json!({
+        "tags": ["data"],
+        "operationId": endpoint.name,
+        "summary": format!("Runs the {} query", endpoint.name),
+        "description": allowed_roles_description(&endpoint.allowed_roles),
+        "security": [{ BEARER_AUTH: [] }, { API_KEY_AUTH: [] }],
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "The rows returned by the query",
+                "content": {
+                    JSON_CONTENT_TYPE: { "schema": json_schema },
+                    NDJSON_CONTENT_TYPE: { "schema": row_schema(endpoint) },
+                    CSV_CONTENT_TYPE: { "schema": { "type": "string" } },
+                    ARROW_CONTENT_TYPE: { "schema": { "type": "string", "contentMediaType": ARROW_CONTENT_TYPE } },
+                    PARQUET_CONTENT_TYPE: { "schema": { "type": "string", "contentMediaType": PARQUET_CONTENT_TYPE } },
+                },
+            },
+            "400": {
+                "description": "One or more request parameters are invalid",
+                "content": { JSON_CONTENT_TYPE: { "schema": schema_ref("InvalidRequestParameters") } },
+            },
+            "401": { "description": "The bearer token is missing or invalid" },
+            "403": {
+                "description": "The caller holds none of the roles allowed to call the endpoint, or its API key is not \
+                                scoped to it"
+            },
+        },
+    })
+}
+
+fn allowed_roles_description(allowed_roles: &[String]) -> String {
+    if allowed_roles.is_empty() {
+        "Open to every authenticated user".to_string()
+    } else {
+        format!("Requires one of the roles: {}", allowed_roles.join(", "))
+    }
+}
+
+fn page_schema(endpoint: &EndpointConfig, row: Value) -> Value {
+    let mut properties = json!({
+        "items": { "type": "array", "items": row },
+        "next_page_token": { "type": ["string", "null"] },
+    });
+    if endpoint.pagination.include_total {
+        properties["total_count"] = json!({ "type": "integer", "minimum": 0 });
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": ["items", "next_page_token"],
+    })
+}
+
+/// Only the declared columns are described; the query may return more
+fn row_schema(endpoint: &EndpointConfig) -> Value {
+    let properties = endpoint
+        .response
+        .iter()
+        .map(|(name, column)| (name.clone(), masked_column_schema(column)))
+        .collect::<Map<_, _>>();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": true,
+    })
+}
+
+/// A masked column is a string for the callers it is masked for, unless masking nulls it out
+fn masked_column_schema(column: &ColumnConfig) -> Value {
+    let Some(mask) = &column.mask else {
+        return column_schema(column.column_type);
+    };
+    let mut schema = match mask.policy {
+        MaskPolicy::Null => column_schema(column.column_type),
+        _ => json!({ "anyOf": [column_schema(column.column_type), column_schema(ColumnType::String)] }),
+    };
+    let masked_for = if mask.unmasked_roles.is_empty() {
+        "every caller".to_string()
+    } else {
+        format!("callers holding none of the roles: {}", mask.unmasked_roles.join(", "))
+    };
+    schema["description"] = json!(format!(
+        "Masked ({}) for {}",
+        serde_json::to_value(mask.policy)
+            .unwrap_or_default()
+            .as_str()
+            .unwrap_or_default(),
+        masked_for
+    ));
+    schema
+}
+
+fn column_schema(column_type: ColumnType) -> Value {
+    match column_type {
+        ColumnType::String => json!({ "type": ["string", "null"] }),
+        ColumnType::Int => json!({ "type": ["integer", "null"] }),
+        ColumnType::Float => json!({ "type": ["number", "null"] }),
+        ColumnType::Bool => json!({ "type": ["boolean", "null"] }),
+        ColumnType::Decimal => json!({ "type": ["string", "null"], "format": "decimal" }),
+        ColumnType::Date => json!({ "type": ["string", "null"], "format": "date" }),
+        ColumnType::Timestamp => json!({ "type": ["string", "null"], "format": "date-time" }),
+        ColumnType::Json => json!({}),
+    }
+}
+
+fn request_parameter(name: &str, config: &ParamConfig) -> Value {
+    let mut schema = match config.param_type {
+        ParamType::List => {
+            let mut items = scalar_schema(config.items.unwrap_or(ParamType::String), config);
+            add_bounds(&mut items, config);
+            json!({ "type": "array", "items": items })
+        }
+        param_type => {
+            let mut schema = scalar_schema(param_type, config);
+            add_bounds(&mut schema, config);
+            schema
+        }
+    };
+    if let Some(default) = &config.default {
+        schema["default"] = json!(default);
+    }
+
+    let mut parameter = json!({
+        "name": name,
+        "in": "query",
+        "required": config.required && config.default.is_none(),
+        "schema": schema,
+    });
+    if config.param_type == ParamType::List {
+        parameter["style"] = json!("form");
+        parameter["explode"] = json!(false);
+    }
+    parameter
+}
+
+fn scalar_schema(param_type: ParamType, config: &ParamConfig) -> Value {
+    match param_type {
+        ParamType::String | ParamType::List => json!({ "type": "string" }),
+        ParamType::Int => json!({ "type": "integer" }),
+        ParamType::Float => json!({ "type": "number" }),
+        ParamType::Bool => json!({ "type": "boolean" }),
+        ParamType::Date => json!({ "type": "string", "format": "date" }),
+        ParamType::Timestamp => json!({ "type": "string", "format": "date-time" }),
+        ParamType::Enum => json!({ "type": "string", "enum": config.values }),
+    }
+}
+
+/// `min`/`max` bound the length of strings and the value of everything else
+fn add_bounds(schema: &mut Value, config: &ParamConfig) {
+    if let Some(pattern) = &config.pattern {
+        schema["pattern"] = json!(format!("^(?:{})$", pattern));
+    }
+    let (min_key, max_key) = match schema["type"].as_str() {
+        Some("string") if schema.get("format").is_none() && schema.get("enum").is_none() => ("minLength", "maxLength"),
+        Some("integer") | Some("number") => ("minimum", "maximum"),
+        _ => return,
+    };
+    for (key, bound) in [(min_key, &config.min), (max_key, &config.max)] {
+        if let Some(number) = bound.as_ref().and_then(|raw| raw.parse::<serde_json::Number>().ok()) {
+            schema[key] = Value::Number(number);
+        }
+    }
+}
+
+fn user_id_parameter() -> Value {
+    json!({
+        "name": "user_id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    })
+}
+
+fn json_body(schema: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { JSON_CONTENT_TYPE: { "schema": schema_ref(schema) } },
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn component_schemas() -> Value {
+    json!({
+        "RegisterUser": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "email": { "type": "string", "format": "email" },
+                "password": { "type": "string", "format": "password" },
+                "invite_code": {
+                    "type": "string",
+                    "description": "Required when registration is invite only",
+                },
+            },
+            "required": ["name", "email", "password"],
+        },
+        "LoginUser": {
+            "type": "object",
+            "properties": {
+                "email": { "type": "string", "format": "email" },
+                "password": { "type": "string", "format": "password" },
+            },
+            "required": ["email", "password"],
+        },
+        "LoginUserRequest": {
+            "type": "object",
+            "properties": { "user": schema_ref("LoginUser") },
+            "required": ["user"],
+        },
+        "LoginUserResponse": {
+            "type": "object",
+            "properties": { "user": schema_ref("User") },
+            "required": ["user"],
+        },
+        "RefreshTokenRequest": {
+            "type": "object",
+            "properties": { "refresh_token": { "type": "string" } },
+            "required": ["refresh_token"],
+        },
+        "CreateApiKeyRequest": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "scopes": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Names of the data endpoints the key may call",
+                },
+                "expires_in_days": { "type": ["integer", "null"], "minimum": 1 },
+            },
+            "required": ["name", "scopes"],
+        },
+        "ApiKey": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "name": { "type": "string" },
+                "scopes": { "type": "array", "items": { "type": "string" } },
+                "createdAt": { "type": ["string", "null"], "format": "date-time" },
+                "expiresAt": { "type": ["string", "null"], "format": "date-time" },
+            },
+            "required": ["id", "name", "scopes"],
+        },
+        "CreatedApiKey": {
+            "allOf": [
+                schema_ref("ApiKey"),
+                {
+                    "type": "object",
+                    "properties": { "api_key": { "type": "string" } },
+                    "required": ["api_key"],
+                },
+            ],
+        },
+        "CreateInviteRequest": {
+            "type": "object",
+            "properties": {
+                "email": {
+                    "type": ["string", "null"],
+                    "format": "email",
+                    "description": "Restricts the invite to that address, which is mailed the code",
+                },
+                "roles": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Roles given to the user who registers with the invite",
+                },
+                "expires_in_days": { "type": ["integer", "null"], "minimum": 1 },
+            },
+        },
+        "Invite": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "email": { "type": ["string", "null"], "format": "email" },
+                "roles": { "type": "array", "items": { "type": "string" } },
+                "createdBy": { "type": "string" },
+                "createdAt": { "type": ["string", "null"], "format": "date-time" },
+                "expiresAt": { "type": ["string", "null"], "format": "date-time" },
+            },
+            "required": ["id", "roles", "createdBy"],
+        },
+        "CreatedInvite": {
+            "allOf": [
+                schema_ref("Invite"),
+                {
+                    "type": "object",
+                    "properties": { "invite_code": { "type": "string" } },
+                    "required": ["invite_code"],
+                },
+            ],
+        },
+        "UpdateRolesRequest": {
+            "type": "object",
+            "properties": { "roles": { "type": "array", "items": { "type": "string" } } },
+            "required": ["roles"],
+        },
+        "UpdateProfileRequest": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "email": { "type": "string", "format": "email" },
+            },
+        },
+        "VerifyEmailRequest": {
+            "type": "object",
+            "properties": { "token": { "type": "string" } },
+            "required": ["token"],
+        },
+        "EmailRequest": {
+            "type": "object",
+            "properties": { "email": { "type": "string", "format": "email" } },
+            "required": ["email"],
+        },
+        "ResetPasswordRequest": {
+            "type": "object",
+            "properties": {
+                "token": { "type": "string" },
+                "new_password": { "type": "string", "format": "password" },
+            },
+            "required": ["token", "new_password"],
+        },
+        "ChangePasswordRequest": {
+            "type": "object",
+            "properties": {
+                "current_password": { "type": "string", "format": "password" },
+                "new_password": { "type": "string", "format": "password" },
+            },
+            "required": ["current_password", "new_password"],
+        },
+        "UpdateAttributesRequest": {
+            "type": "object",
+            "properties": {
+                "attributes": { "type": "object", "additionalProperties": { "type": "string" } },
+            },
+            "required": ["attributes"],
+        },
+        "Tokens": {
+            "type": "object",
+            "properties": {
+                "access_token": { "type": "string" },
+                "refresh_token": { "type": "string" },
+            },
+            "required": ["access_token", "refresh_token"],
+        },
+        "User": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "name": { "type": "string" },
+                "email": { "type": "string", "format": "email" },
+                "roles": { "type": "array", "items": { "type": "string" } },
+                "attributes": { "type": "object", "additionalProperties": { "type": "string" } },
+                "createdAt": { "type": ["string", "null"], "format": "date-time" },
+                "updatedAt": { "type": ["string", "null"], "format": "date-time" },
+                "disabledAt": { "type": ["string", "null"], "format": "date-time" },
+                "emailVerifiedAt": { "type": ["string", "null"], "format": "date-time" },
+                "access_token": { "type": ["string", "null"] },
+                "refresh_token": { "type": ["string", "null"] },
+            },
+            "required": ["id", "name", "email"],
+        },
+        "InvalidRequestParameters": {
+            "type": "object",
+            "properties": {
+                "message": { "type": "string" },
+                "violations": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "parameter": { "type": "string" },
+                            "message": { "type": "string" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::AppConfig;
+    use crate::openapi::openapi_document;
+
+    #[test]
+    fn test_document_describes_configured_endpoints() {
+        let config = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        let document = openapi_document(&config);
+
+        let operation = &document["paths"]["/api/nexus/customer_master"]["get"];
+        assert_eq!(operation["operationId"], "customer_master");
+        assert_eq!(operation["security"][0]["bearerAuth"], serde_json::json!([]));
+        let parameters = operation["parameters"].as_array().unwrap();
+        assert!(parameters.iter().any(|p| p["name"] == "customer_id"));
+        assert!(parameters.iter().any(|p| p["name"] == "page_token"));
+        let row =
+            &operation["responses"]["200"]["content"]["application/json"]["schema"]["properties"]["items"]["items"];
+        assert_eq!(row["properties"]["age"]["type"][0], "integer");
+        assert!(document["paths"]["/api/users/me"]["get"].is_object());
+    }
+}
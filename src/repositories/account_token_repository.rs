@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use tracing::info;
+
+use crate::datasource::{DataSource, Statement};
+
+/// Records the email verification and password reset tokens that were issued, so that each is used once
+#[derive(Clone)]
+pub struct AccountTokenRepository {
+    source: Arc<dyn DataSource>,
+}
+
+impl AccountTokenRepository {
+    pub fn new(source: Arc<dyn DataSource>) -> Self {
+        Self { source }
+    }
+
+    pub async fn insert_account_token(
+        &self,
+        token_uuid: &str,
+        user_id: &str,
+        purpose: &str,
+        expires_at: i64,
+    ) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (token_uuid, user_id, purpose, expires_at) VALUES (?,?,?,?)",
+                    self.source.table("nexus_account_tokens")
+                ),
+                vec![token_uuid.into(), user_id.into(), purpose.into(), expires_at.into()],
+            ))
+            .await?;
+        info!("{} token {} issued to user {}", purpose, token_uuid, user_id);
+        Ok(())
+    }
+
+    /// Marks the token as used. Returns false when it was already used, or never issued for that purpose.
+    pub async fn consume_account_token(&self, token_uuid: &str, purpose: &str) -> anyhow::Result<bool> {
+        let changed = self
+            .source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET used_at = CURRENT_TIMESTAMP WHERE token_uuid = ? AND purpose = ? AND used_at IS NULL",
+                    self.source.table("nexus_account_tokens")
+                ),
+                vec![token_uuid.into(), purpose.into()],
+            ))
+            .await?;
+        Ok(changed == 1)
+    }
+
+    /// Uses up every unused token of the user for that purpose
+    pub async fn invalidate_account_tokens(&self, user_id: &str, purpose: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET used_at = CURRENT_TIMESTAMP WHERE user_id = ? AND purpose = ? AND used_at IS NULL",
+                    self.source.table("nexus_account_tokens")
+                ),
+                vec![user_id.into(), purpose.into()],
+            ))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::datasource::{DataSource, Row, Statement};
+
+const SCOPE_SEPARATOR: &str = ",";
+const API_KEY_COLUMNS: &str = "key_id, user_id, name, scopes, key_hash, issued_at, expires_at, revoked_at";
+
+/// An issued API key. Only a hash of its secret is stored.
+#[derive(Debug, Clone)]
+pub struct ApiKeyEntity {
+    pub key_id: String,
+    pub user_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub key_hash: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub revoked: bool,
+}
+
+#[derive(Clone)]
+pub struct ApiKeyRepository {
+    source: Arc<dyn DataSource>,
+}
+
+impl ApiKeyRepository {
+    pub fn new(source: Arc<dyn DataSource>) -> Self {
+        Self { source }
+    }
+
+    pub async fn insert_api_key(&self, key: &ApiKeyEntity) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (key_id, user_id, name, scopes, key_hash, issued_at, expires_at) \
+                     VALUES (?,?,?,?,?,?,?)",
+                    self.source.table("nexus_api_keys")
+                ),
+                vec![
+                    key.key_id.as_str().into(),
+                    key.user_id.as_str().into(),
+                    key.name.as_str().into(),
+                    key.scopes.join(SCOPE_SEPARATOR).into(),
+                    key.key_hash.as_str().into(),
+                    key.issued_at.into(),
+                    key.expires_at.into(),
+                ],
+            ))
+            .await?;
+        info!("API key {} issued to user {}", key.key_id, key.user_id);
+        Ok(())
+    }
+
+    pub async fn get_api_key(&self, key_id: &str) -> anyhow::Result<Option<ApiKeyEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE key_id = ?",
+                    API_KEY_COLUMNS,
+                    self.source.table("nexus_api_keys")
+                ),
+                vec![key_id.into()],
+            ))
+            .await?;
+        rows.first().map(read_api_key).transpose()
+    }
+
+    /// The keys of the user that were not revoked, expired ones included
+    pub async fn list_api_keys(&self, user_id: &str) -> anyhow::Result<Vec<ApiKeyEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE user_id = ? AND revoked_at IS NULL ORDER BY issued_at",
+                    API_KEY_COLUMNS,
+                    self.source.table("nexus_api_keys")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+        rows.iter().map(read_api_key).collect()
+    }
+
+    /// Revokes a key of the user. Returns whether the user had such a key to revoke.
+    pub async fn revoke_api_key(&self, user_id: &str, key_id: &str) -> anyhow::Result<bool> {
+        let changed = self
+            .source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET revoked_at = CURRENT_TIMESTAMP WHERE key_id = ? AND user_id = ? AND revoked_at IS NULL",
+                    self.source.table("nexus_api_keys")
+                ),
+                vec![key_id.into(), user_id.into()],
+            ))
+            .await?;
+        let revoked = changed == 1;
+        if revoked {
+            info!("API key {} of user {} revoked", key_id, user_id);
+        }
+        Ok(revoked)
+    }
+}
+
+fn read_api_key(row: &Row) -> anyhow::Result<ApiKeyEntity> {
+    Ok(ApiKeyEntity {
+        key_id: row.text(0).to_string(),
+        user_id: row.text(1).to_string(),
+        name: row.text(2).to_string(),
+        scopes: row
+            .text(3)
+            .split(SCOPE_SEPARATOR)
+            .filter(|scope| !scope.is_empty())
+            .map(str::to_string)
+            .collect(),
+        key_hash: row.text(4).to_string(),
+        issued_at: row.text(5).parse().context("Invalid issued_at of API key")?,
+        expires_at: row.text(6).parse().context("Invalid expires_at of API key")?,
+        revoked: row.get(7).is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datasource::sqlite::test_source;
+    use crate::repositories::api_key_repository::{ApiKeyEntity, ApiKeyRepository};
+
+    fn api_key(key_id: &str, user_id: &str, scopes: &[&str]) -> ApiKeyEntity {
+        ApiKeyEntity {
+            key_id: key_id.to_string(),
+            user_id: user_id.to_string(),
+            name: format!("key {}", key_id),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            key_hash: format!("hash of {}", key_id),
+            issued_at: 1_700_000_000,
+            expires_at: 1_800_000_000,
+            revoked: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_round_trip_and_are_revoked_by_their_owner() {
+        let keys = ApiKeyRepository::new(test_source());
+        keys.insert_api_key(&api_key("k-1", "u-1", &["customer_master", "orders"]))
+            .await
+            .unwrap();
+        keys.insert_api_key(&api_key("k-2", "u-1", &[])).await.unwrap();
+        keys.insert_api_key(&api_key("k-3", "u-2", &[])).await.unwrap();
+
+        let stored = keys.get_api_key("k-1").await.unwrap().unwrap();
+        assert_eq!(stored.scopes, ["customer_master", "orders"]);
+        assert_eq!((stored.issued_at, stored.expires_at), (1_700_000_000, 1_800_000_000));
+        assert_eq!(stored.key_hash, "hash of k-1");
+        assert!(!stored.revoked);
+        assert!(keys.get_api_key("k-2").await.unwrap().unwrap().scopes.is_empty());
+        assert!(keys.get_api_key("k-4").await.unwrap().is_none());
+
+        assert!(!keys.revoke_api_key("u-2", "k-1").await.unwrap());
+        assert!(keys.revoke_api_key("u-1", "k-1").await.unwrap());
+        assert!(!keys.revoke_api_key("u-1", "k-1").await.unwrap());
+        assert!(keys.get_api_key("k-1").await.unwrap().unwrap().revoked);
+
+        let listed = keys.list_api_keys("u-1").await.unwrap();
+        assert_eq!(
+            listed.iter().map(|key| key.key_id.as_str()).collect::<Vec<_>>(),
+            ["k-2"]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+
+/// Failed logins counted under one key, an account or a client address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttemptRecord {
+    pub failures: u32,
+    /// Unix time of the last failure
+    pub last_failure_at: i64,
+}
+
+/// What became of an attempt submitted to `AttemptStore::reserve`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reservation {
+    /// The attempt may go on, and is counted as a failure until it is released. `previous` is the record it replaced.
+    Counted {
+        previous: Option<AttemptRecord>,
+        counted: AttemptRecord,
+    },
+    /// The attempt is refused for this many seconds, and nothing was counted
+    Wait(u64),
+}
+
+/// Where failed login attempts are counted. The in-memory store counts per instance; a store shared by all the
+/// instances (a database table, Redis) makes the limits apply to the whole deployment.
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    /// Counts an attempt at `now` as a failure before it is made, so that concurrent attempts see each other, unless
+    /// `wait` of the current record is not over. Failures that are older than `forget_before` are forgotten first.
+    /// Checking and counting must be a single atomic step.
+    async fn reserve(
+        &self,
+        key: &str,
+        now: i64,
+        forget_before: i64,
+        wait: &(dyn Fn(AttemptRecord) -> u64 + Sync),
+    ) -> anyhow::Result<Reservation>;
+
+    /// Takes back a counted attempt that succeeded: the record goes back to `previous` unless other attempts were
+    /// counted since, in which case only this one is taken off
+    async fn release(&self, key: &str, previous: Option<AttemptRecord>, counted: AttemptRecord) -> anyhow::Result<()>;
+
+    async fn clear(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Entries beyond which the forgotten ones are dropped
+const PRUNE_SIZE: usize = 10_000;
+
+#[derive(Default)]
+pub struct InMemoryAttemptStore {
+    records: RwLock<HashMap<String, AttemptRecord>>,
+}
+
+impl InMemoryAttemptStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AttemptStore for InMemoryAttemptStore {
+    async fn reserve(
+        &self,
+        key: &str,
+        now: i64,
+        forget_before: i64,
+        wait: &(dyn Fn(AttemptRecord) -> u64 + Sync),
+    ) -> anyhow::Result<Reservation> {
+        let mut records = self.records.write().unwrap();
+        if records.len() >= PRUNE_SIZE {
+            records.retain(|_, record| record.last_failure_at >= forget_before);
+        }
+        let previous = records.get(key).copied();
+        if let Some(record) = &previous {
+            let retry_after = wait(*record);
+            if retry_after > 0 {
+                return Ok(Reservation::Wait(retry_after));
+            }
+        }
+        let failures = match previous {
+            Some(record) if record.last_failure_at >= forget_before => record.failures,
+            _ => 0,
+        };
+        let counted = AttemptRecord {
+            failures: failures + 1,
+            last_failure_at: now,
+        };
+        records.insert(key.to_string(), counted);
+        Ok(Reservation::Counted { previous, counted })
+    }
+
+    async fn release(&self, key: &str, previous: Option<AttemptRecord>, counted: AttemptRecord) -> anyhow::Result<()> {
+        let mut records = self.records.write().unwrap();
+        match (records.get_mut(key), previous) {
+            (Some(record), Some(previous)) if *record == counted => *record = previous,
+            (Some(record), None) if *record == counted => {
+                records.remove(key);
+            }
+            (Some(record), _) => record.failures = record.failures.saturating_sub(1),
+            (None, _) => {}
+        }
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> anyhow::Result<()> {
+        self.records.write().unwrap().remove(key);
+        Ok(())
+    }
+}
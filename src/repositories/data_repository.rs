@@ -1,100 +1,151 @@
 use std::collections::HashMap;
-
-use anyhow::{anyhow, Context};
-use axum_odbc::odbc::buffers::TextRowSet;
-use axum_odbc::odbc::{Cursor, ResultSetMetadata};
-use axum_odbc::ODBCConnectionManager;
-use tera::Tera;
-use tracing::{error, info};
-
-use crate::domain::req_res::DataResponse;
+use std::sync::Arc;
+
+use crate::config::{ColumnType, PaginationConfig};
+use crate::datasource::{DataSource, FetchEvent, FetchReceiver, Row};
+use crate::domain::columns::{ResponseSchema, ResultColumn};
+use crate::domain::identity::AuthContext;
+use crate::domain::pagination::{count_query, next_page_token, paginate, PageRequest};
+use crate::domain::params::RequestParams;
+use crate::domain::req_res::{DataPage, DataRow};
 use crate::errors::ApiError;
 use crate::errors::ApiError::InternalServerErrorWithContext;
+use crate::repositories::sql_template::{CompiledSql, SqlTemplates};
 
 pub const BATCH_SIZE: usize = 1000;
 
+/// What a query produces while it is being fetched: its columns once, then its rows batch by batch
+#[derive(Debug)]
+pub enum RowEvent {
+    Columns(Vec<ResultColumn>),
+    Rows(Vec<DataRow>),
+}
+
+/// The batches of a query as they are fetched, typed and masked as the response of the endpoint declares
+pub struct RowBatchReceiver {
+    receiver: FetchReceiver,
+    response: ResponseSchema,
+    columns: Vec<ResultColumn>,
+}
+
+impl RowBatchReceiver {
+    fn new(receiver: FetchReceiver, response: ResponseSchema) -> Self {
+        Self {
+            receiver,
+            response,
+            columns: Vec::new(),
+        }
+    }
+
+    /// The next event, or `None` once the query is exhausted
+    pub async fn recv(&mut self) -> Option<Result<RowEvent, ApiError>> {
+        let event = match self.receiver.recv().await? {
+            Ok(event) => event,
+            Err(e) => return Some(Err(InternalServerErrorWithContext(format!("{:#}", e)))),
+        };
+        Some(Ok(match event {
+            FetchEvent::Columns(columns) => {
+                self.columns = self.response.resolve(columns);
+                RowEvent::Columns(self.columns.clone())
+            }
+            FetchEvent::Rows(rows) => RowEvent::Rows(rows.iter().map(|row| self.data_row(row)).collect()),
+        }))
+    }
+
+    fn data_row(&self, row: &Row) -> DataRow {
+        let mut data_row = DataRow::with_capacity(self.columns.len());
+        for (coli, column) in self.columns.iter().enumerate() {
+            data_row.insert(column.name.clone(), column.to_json(row.get(coli)));
+        }
+        data_row
+    }
+}
+
 #[derive(Clone)]
 pub struct DataRepository {
-    pool: ODBCConnectionManager,
-    tera: Tera,
+    source: Arc<dyn DataSource>,
+    /// Sources of the endpoints that do not use `source`
+    endpoint_sources: HashMap<String, Arc<dyn DataSource>>,
+    templates: SqlTemplates,
 }
 
 impl DataRepository {
-    pub fn new(pool: ODBCConnectionManager, tera: Tera) -> Self {
-        Self { pool, tera }
+    pub fn new(
+        source: Arc<dyn DataSource>,
+        endpoint_sources: HashMap<String, Arc<dyn DataSource>>,
+        templates: SqlTemplates,
+    ) -> Self {
+        Self {
+            source,
+            endpoint_sources,
+            templates,
+        }
     }
 
-    pub async fn extract_results(&self, params: HashMap<String, String>) -> Result<DataResponse, ApiError> {
-        let conn = self
-            .pool
-            .aquire()
-            .await
-            .context("Unable to get a connection from the ODBCConnectionManager")?;
-        //TOOD - Figure out how to avoid sql injection here.
-        let sql = self.construct_sql(&params)?;
-
-        //FIXME - Convert parameters to a HashMap
-        info!("Executing query: {}", sql);
-        info!("Template parameters: {:?}", &params);
-
-        let rows_result = match conn.execute(&sql, ()) {
-            Err(e) => {
-                error!("Error while executing query: {}", e);
-                return Err(InternalServerErrorWithContext(format!(
-                    "StatementExecutionError: {}",
-                    e
-                )));
-            }
-            Ok(None) => {
-                error!("No results returned");
-                return Err(InternalServerErrorWithContext("NoResultsError".into()));
-            }
-            Ok(Some(mut cursor)) => {
-                let col_names = cursor.column_names()?.map(|s| s.unwrap()).collect::<Vec<String>>(); //The unwrap needs further investigation. Why would the metadata column return an error at a column name level?
-
-                let mut buffers = TextRowSet::for_cursor(BATCH_SIZE, &mut cursor, None)?;
-
-                let mut rows_cursor = cursor.bind_buffer(&mut buffers)?;
-
-                let mut rows = Vec::new();
-
-                while let Some(rowset) = rows_cursor.fetch()? {
-                    for rowi in 0..rowset.num_rows() {
-                        let mut row_map = HashMap::new();
-                        for (coli, col_name) in col_names.iter().enumerate() {
-                            let col_value = rowset.at(coli, rowi);
-                            match col_value {
-                                None => {
-                                    row_map.insert(col_name.to_string(), "".to_string());
-                                }
-                                Some(cval) => {
-                                    row_map
-                                        .insert(col_name.to_string(), std::str::from_utf8(cval).unwrap().to_string());
-                                }
-                            }
-                        }
-                        rows.push(row_map);
-                    }
-                }
-
-                Ok(rows)
-            }
+    pub async fn extract_page(
+        &self,
+        endpoint: &str,
+        params: RequestParams,
+        auth: &AuthContext,
+        response: &ResponseSchema,
+        pagination: &PaginationConfig,
+        page: &PageRequest,
+    ) -> Result<DataPage, ApiError> {
+        let source = self.source(endpoint);
+        let compiled = self.templates.compile(endpoint, &params, auth)?;
+
+        let total_count = if pagination.include_total {
+            Some(count_rows(source.as_ref(), count_query(&compiled)).await?)
+        } else {
+            None
         };
 
-        rows_result
-    }
+        let mut receiver = RowBatchReceiver::new(
+            source
+                .fetch(paginate(compiled, pagination, page).into(), BATCH_SIZE)
+                .await?,
+            response.clone(),
+        );
+        let mut items = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            if let RowEvent::Rows(batch) = event? {
+                items.extend(batch);
+            }
+        }
+        let next_page_token = next_page_token(&mut items, pagination, page)?;
 
-    fn construct_sql(&self, params: &HashMap<String, String>) -> Result<String, ApiError> {
-        let mut context = tera::Context::new();
+        Ok(DataPage {
+            items,
+            next_page_token,
+            total_count,
+        })
+    }
 
-        for (key, value) in params {
-            context.insert(key, &value);
-        }
-        let result = self.tera.render("sql", &context).map_err(|e| {
-            error!("Error while rendering template: {}", e);
-            anyhow!(format!("SqlTemplatingError {}", e.to_string()))
-        })?;
+    /// Hands over every fetched batch as soon as it is read. The channel is bounded, so fetching pauses while the
+    /// client is slower than the database and stops once it disconnects.
+    pub async fn stream_results(
+        &self,
+        endpoint: &str,
+        params: RequestParams,
+        auth: &AuthContext,
+        response: ResponseSchema,
+    ) -> Result<RowBatchReceiver, ApiError> {
+        let source = self.source(endpoint);
+        let compiled = self.templates.compile(endpoint, &params, auth)?;
+        Ok(RowBatchReceiver::new(
+            source.fetch(compiled.into(), BATCH_SIZE).await?,
+            response,
+        ))
+    }
 
-        Ok(result)
+    fn source(&self, endpoint: &str) -> &Arc<dyn DataSource> {
+        self.endpoint_sources.get(endpoint).unwrap_or(&self.source)
     }
 }
+
+async fn count_rows(source: &dyn DataSource, compiled: CompiledSql) -> Result<u64, ApiError> {
+    let rows = source.query(compiled.into()).await?;
+    rows.first()
+        .and_then(|row| ColumnType::Int.to_json(row.get(0)).as_u64())
+        .ok_or_else(|| InternalServerErrorWithContext("Unable to count the rows of the query".into()))
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::datasource::{DataSource, Row, Statement};
+
+const ROLE_SEPARATOR: &str = ",";
+const INVITE_COLUMNS: &str = "invite_id, code_hash, created_by, email, roles, issued_at, expires_at, used_by";
+
+/// An invitation to register. Only a hash of its code is stored.
+#[derive(Debug, Clone)]
+pub struct InviteEntity {
+    pub invite_id: String,
+    pub code_hash: String,
+    pub created_by: String,
+    /// The only address that may register with the invite, when set
+    pub email: Option<String>,
+    /// Roles given to the user who registers with the invite
+    pub roles: Vec<String>,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    /// Email of the user who registered with the invite
+    pub used_by: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct InviteRepository {
+    source: Arc<dyn DataSource>,
+}
+
+impl InviteRepository {
+    pub fn new(source: Arc<dyn DataSource>) -> Self {
+        Self { source }
+    }
+
+    pub async fn insert_invite(&self, invite: &InviteEntity) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (invite_id, code_hash, created_by, email, roles, issued_at, expires_at) \
+                     VALUES (?,?,?,?,?,?,?)",
+                    self.source.table("nexus_invites")
+                ),
+                vec![
+                    invite.invite_id.as_str().into(),
+                    invite.code_hash.as_str().into(),
+                    invite.created_by.as_str().into(),
+                    invite.email.as_deref().into(),
+                    invite.roles.join(ROLE_SEPARATOR).into(),
+                    invite.issued_at.into(),
+                    invite.expires_at.into(),
+                ],
+            ))
+            .await?;
+        info!("Invite {} created by {}", invite.invite_id, invite.created_by);
+        Ok(())
+    }
+
+    pub async fn get_invite_by_code_hash(&self, code_hash: &str) -> anyhow::Result<Option<InviteEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE code_hash = ?",
+                    INVITE_COLUMNS,
+                    self.source.table("nexus_invites")
+                ),
+                vec![code_hash.into()],
+            ))
+            .await?;
+        rows.first().map(read_invite).transpose()
+    }
+
+    /// The invites that were not used yet, expired ones included
+    pub async fn list_invites(&self) -> anyhow::Result<Vec<InviteEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE used_at IS NULL ORDER BY issued_at",
+                    INVITE_COLUMNS,
+                    self.source.table("nexus_invites")
+                ),
+                vec![],
+            ))
+            .await?;
+        rows.iter().map(read_invite).collect()
+    }
+
+    /// Marks the invite as used by `email`. Returns false when it was used already, or has expired.
+    pub async fn consume_invite(&self, invite_id: &str, email: &str, now: i64) -> anyhow::Result<bool> {
+        let changed = self
+            .source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET used_by = ?, used_at = CURRENT_TIMESTAMP \
+                     WHERE invite_id = ? AND used_at IS NULL AND expires_at > ?",
+                    self.source.table("nexus_invites")
+                ),
+                vec![email.into(), invite_id.into(), now.into()],
+            ))
+            .await?;
+        Ok(changed == 1)
+    }
+
+    /// Deletes an invite that was not used. Returns whether there was one.
+    pub async fn delete_invite(&self, invite_id: &str) -> anyhow::Result<bool> {
+        let changed = self
+            .source
+            .execute(Statement::new(
+                format!(
+                    "DELETE FROM {} WHERE invite_id = ? AND used_at IS NULL",
+                    self.source.table("nexus_invites")
+                ),
+                vec![invite_id.into()],
+            ))
+            .await?;
+        let deleted = changed == 1;
+        if deleted {
+            info!("Invite {} deleted", invite_id);
+        }
+        Ok(deleted)
+    }
+}
+
+fn read_invite(row: &Row) -> anyhow::Result<InviteEntity> {
+    Ok(InviteEntity {
+        invite_id: row.text(0).to_string(),
+        code_hash: row.text(1).to_string(),
+        created_by: row.text(2).to_string(),
+        email: row.get(3).filter(|email| !email.is_empty()).map(str::to_string),
+        roles: row
+            .text(4)
+            .split(ROLE_SEPARATOR)
+            .filter(|role| !role.is_empty())
+            .map(str::to_string)
+            .collect(),
+        issued_at: row.text(5).parse().context("Invalid issued_at of invite")?,
+        expires_at: row.text(6).parse().context("Invalid expires_at of invite")?,
+        used_by: row.get(7).filter(|email| !email.is_empty()).map(str::to_string),
+    })
+}
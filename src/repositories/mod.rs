@@ -1,2 +1,8 @@
+pub mod account_token_repository;
+pub mod api_key_repository;
+pub mod attempt_store;
 pub mod data_repository;
+pub mod invite_repository;
+pub mod sql_template;
+pub mod token_repository;
 pub mod user_repository;
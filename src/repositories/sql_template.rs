@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use regex::{Captures, Regex};
+use tera::{Context, Tera};
+use tracing::error;
+
+use crate::config::{EndpointConfig, TemplateMode};
+use crate::domain::identity::{AuthContext, AUTH_VARIABLE};
+use crate::domain::params::{ParamValue, ParamViolation, RequestParams};
+use crate::errors::{ApiError, ApiResult};
+
+const MARKER_PREFIX: &str = "__nexus_bind(";
+const MARKER_SUFFIX: &str = ")__";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledSql {
+    pub sql: String,
+    pub params: Vec<ParamValue>,
+}
+
+#[derive(Clone)]
+struct EndpointTemplate {
+    mode: TemplateMode,
+    /// Declared request parameters, which are all in the context of the template even when not sent
+    params: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct SqlTemplates {
+    tera: Tera,
+    endpoints: HashMap<String, EndpointTemplate>,
+}
+
+impl SqlTemplates {
+    pub fn new(endpoints: &[EndpointConfig]) -> ApiResult<Self> {
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![]); //Values are bound as parameters in `bind` mode, so escaping would only corrupt them
+
+        let mut templates = HashMap::new();
+        for endpoint in endpoints {
+            let params = endpoint.request.keys().cloned().collect::<Vec<_>>();
+            let sql = match endpoint.template_mode {
+                TemplateMode::Bind => bind_variables(&endpoint.sql, &params).map_err(|e| {
+                    error!("Error while parsing template for endpoint {}: {}", endpoint.name, e);
+                    anyhow!(format!("SqlTemplatingError in endpoint {}: {}", endpoint.name, e))
+                })?,
+                TemplateMode::Render => endpoint.sql.clone(),
+            };
+            tera.add_raw_template(&endpoint.name, &sql).map_err(|e| {
+                error!("Error while parsing template for endpoint {}: {}", endpoint.name, e);
+                anyhow!(format!("SqlTemplatingError {}", e))
+            })?;
+            templates.insert(
+                endpoint.name.clone(),
+                EndpointTemplate {
+                    mode: endpoint.template_mode,
+                    params,
+                },
+            );
+        }
+
+        Ok(Self {
+            tera,
+            endpoints: templates,
+        })
+    }
+
+    /// Renders the SQL of an endpoint. Tags such as `{% if %}` and `{% for %}` see the real values in both modes. In
+    /// `bind` mode every `{{ variable }}` then becomes a `?` marker and its value is returned in marker order, ready to
+    /// be bound as a statement parameter. The caller is exposed as `auth`, which is inserted last so that no request
+    /// parameter can stand in for it.
+    pub fn compile(&self, endpoint: &str, params: &RequestParams, auth: &AuthContext) -> ApiResult<CompiledSql> {
+        let template = self
+            .endpoints
+            .get(endpoint)
+            .ok_or_else(|| ApiError::NotFound(format!("No SQL template registered for endpoint {}", endpoint)))?;
+
+        let mut context = Context::new();
+        for name in &template.params {
+            context.insert(name, params.get(name).unwrap_or(&ParamValue::Null));
+        }
+        context.insert(AUTH_VARIABLE, auth);
+        let rendered = self.render(endpoint, &context)?;
+
+        match template.mode {
+            TemplateMode::Render => Ok(CompiledSql {
+                sql: rendered,
+                params: vec![],
+            }),
+            TemplateMode::Bind => bind_markers(&rendered, |variable| bound_value(variable, params, auth)),
+        }
+    }
+
+    fn render(&self, endpoint: &str, context: &Context) -> ApiResult<String> {
+        self.tera.render(endpoint, context).map_err(|e| {
+            error!("Error while rendering template: {}", e);
+            anyhow!(format!("SqlTemplatingError {}", e)).into()
+        })
+    }
+}
+
+/// Replaces every `{{ variable }}` of a `bind` mode template with a marker naming the variable. Only request parameters
+/// and fields of `auth` can be bound, and only as they are: a filter or an expression would see the marker instead of
+/// the value, so such templates are refused.
+fn bind_variables(sql: &str, params: &[String]) -> anyhow::Result<String> {
+    let variable_block = Regex::new(r"(?s)\{\{(-?)(.*?)(-?)\}\}").expect("valid regex");
+    let mut invalid = None;
+    let sql = variable_block.replace_all(sql, |captures: &Captures| {
+        let variable = captures[2].trim();
+        if !is_bindable(variable, params) {
+            invalid.get_or_insert_with(|| variable.to_string());
+        }
+        format!(
+            "{{{{{} \"{}{}{}\" {}}}}}",
+            &captures[1], MARKER_PREFIX, variable, MARKER_SUFFIX, &captures[3]
+        )
+    });
+    match invalid {
+        Some(variable) => Err(anyhow!(
+            "{{{{ {} }}}} cannot be bound: only request parameters and auth.user_id, auth.roles or \
+             auth.attributes.<name> can be output in bind mode, use {{% if %}} for anything else",
+            variable
+        )),
+        None => Ok(sql.into_owned()),
+    }
+}
+
+fn is_bindable(variable: &str, params: &[String]) -> bool {
+    match variable.split('.').collect::<Vec<_>>().as_slice() {
+        [AUTH_VARIABLE, "user_id" | "roles"] => true,
+        [AUTH_VARIABLE, "attributes", name] => !name.is_empty(),
+        [name] => params.iter().any(|param| param == name),
+        _ => false,
+    }
+}
+
+/// The value a marker stands for. A parameter that was not sent, or an attribute the caller does not have, fails the
+/// request instead of running the query without it.
+fn bound_value(variable: &str, params: &RequestParams, auth: &AuthContext) -> ApiResult<ParamValue> {
+    match variable.split('.').collect::<Vec<_>>().as_slice() {
+        [AUTH_VARIABLE, "user_id"] => Ok(ParamValue::Str(auth.user_id.clone())),
+        [AUTH_VARIABLE, "roles"] => Ok(ParamValue::List(
+            auth.roles.iter().cloned().map(ParamValue::Str).collect(),
+        )),
+        [AUTH_VARIABLE, "attributes", name] => auth
+            .attributes
+            .get(*name)
+            .map(|value| ParamValue::Str(value.clone()))
+            .ok_or(ApiError::Forbidden),
+        _ => params.get(variable).cloned().ok_or_else(|| {
+            ApiError::InvalidRequestParameters(vec![ParamViolation::new(variable, "is required by this query")])
+        }),
+    }
+}
+
+/// Replaces the markers rendered in place of template variables with `?`. A marker wrapped in single quotes
+/// (`'{{customer_id}}'`) is bound as a whole value, so the quotes are dropped along with it. A marker inside a longer
+/// literal (`like '%{{name}}%'`) is concatenated to the rest of it (`like '%' || ? || '%'`). A list expands to one
+/// marker per item (`id in ({{ids}})`), and an empty list to `NULL` so that the statement stays valid.
+fn bind_markers(rendered: &str, mut value_of: impl FnMut(&str) -> ApiResult<ParamValue>) -> ApiResult<CompiledSql> {
+    let mut sql = String::with_capacity(rendered.len());
+    let mut params = Vec::new();
+    let mut rest = rendered;
+    let mut in_literal = false;
+
+    while let Some(start) = rest.find(MARKER_PREFIX) {
+        let after = &rest[start + MARKER_PREFIX.len()..];
+        let Some(end) = after.find(MARKER_SUFFIX) else {
+            break;
+        };
+        let variable = &after[..end];
+        let head = &rest[..start];
+        let mut tail = &after[end + MARKER_SUFFIX.len()..];
+        in_literal ^= head.matches('\'').count() % 2 == 1;
+
+        let value = value_of(variable)?;
+        if !in_literal {
+            sql.push_str(head);
+            match value {
+                ParamValue::List(items) if items.is_empty() => sql.push_str("NULL"),
+                ParamValue::List(items) => {
+                    sql.push_str(&vec!["?"; items.len()].join(", "));
+                    params.extend(items);
+                }
+                value => {
+                    sql.push('?');
+                    params.push(value);
+                }
+            }
+        } else {
+            if matches!(value, ParamValue::List(_)) {
+                return Err(ApiError::InternalServerErrorWithContext(format!(
+                    "The list {} cannot be bound inside a string literal",
+                    variable
+                )));
+            }
+            match head.strip_suffix('\'') {
+                Some(head) => sql.push_str(head),
+                None => {
+                    sql.push_str(head);
+                    sql.push_str("' || ");
+                }
+            }
+            sql.push('?');
+            params.push(value);
+            match tail.strip_prefix('\'') {
+                Some(after_quote) => {
+                    tail = after_quote;
+                    in_literal = false;
+                }
+                None => sql.push_str(" || '"),
+            }
+        }
+        rest = tail;
+    }
+    sql.push_str(rest);
+
+    Ok(CompiledSql { sql, params })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::config::{EndpointConfig, ParamConfig, ParamType, TemplateMode};
+    use crate::domain::identity::AuthContext;
+    use crate::domain::params::ParamValue;
+    use crate::errors::{ApiError, ApiResult};
+    use crate::repositories::sql_template::SqlTemplates;
+
+    fn templates(sql: &str, template_mode: TemplateMode) -> SqlTemplates {
+        try_templates(sql, template_mode).unwrap()
+    }
+
+    fn try_templates(sql: &str, template_mode: TemplateMode) -> ApiResult<SqlTemplates> {
+        let request = ["customer_id", "ages", "status", "name"]
+            .into_iter()
+            .map(|name| {
+                let config = ParamConfig {
+                    param_type: ParamType::String,
+                    required: false,
+                    default: None,
+                    pattern: None,
+                    min: None,
+                    max: None,
+                    values: vec![],
+                    items: None,
+                };
+                (name.to_string(), config)
+            })
+            .collect();
+        let endpoint = EndpointConfig {
+            name: "customer_master".to_string(),
+            endpoint: "/api/nexus/customer_master".to_string(),
+            request,
+            response: HashMap::new(),
+            sql: sql.to_string(),
+            template_mode,
+            stream: false,
+            pagination: Default::default(),
+            allowed_roles: vec![],
+            datasource: None,
+        };
+        SqlTemplates::new(&[endpoint])
+    }
+
+    #[test]
+    fn test_bind_mode_never_renders_values() {
+        let templates = templates(
+            "select * from customer_master where id = '{{customer_id}}' and age in ({{ ages }})",
+            TemplateMode::Bind,
+        );
+        let params = HashMap::from([
+            ("customer_id".to_string(), ParamValue::Str("1' or '1'='1".to_string())),
+            (
+                "ages".to_string(),
+                ParamValue::List(vec![ParamValue::Int(30), ParamValue::Int(40)]),
+            ),
+        ]);
+
+        let compiled = templates
+            .compile("customer_master", &params, &AuthContext::default())
+            .unwrap();
+        assert_eq!(
+            compiled.sql,
+            "select * from customer_master where id = ? and age in (?, ?)"
+        );
+        assert_eq!(
+            compiled.params,
+            vec![
+                ParamValue::Str("1' or '1'='1".to_string()),
+                ParamValue::Int(30),
+                ParamValue::Int(40)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bind_mode_binds_the_authenticated_user() {
+        let templates = templates(
+            "select * from orders where owner = '{{ auth.user_id }}' and region = '{{ auth.attributes.region }}' \
+             and team in ({{ auth.roles }})",
+            TemplateMode::Bind,
+        );
+        let params = HashMap::from([("auth".to_string(), ParamValue::Str("admin".to_string()))]);
+        let auth = AuthContext {
+            user_id: "u-1".to_string(),
+            roles: vec!["analyst".to_string()],
+            attributes: [("region".to_string(), "EMEA".to_string())].into(),
+        };
+
+        let compiled = templates.compile("customer_master", &params, &auth).unwrap();
+        assert_eq!(
+            compiled.sql,
+            "select * from orders where owner = ? and region = ? and team in (?)"
+        );
+        assert_eq!(
+            compiled.params,
+            vec![
+                ParamValue::Str("u-1".to_string()),
+                ParamValue::Str("EMEA".to_string()),
+                ParamValue::Str("analyst".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bind_mode_tags_see_the_real_values() {
+        let templates = templates(
+            "select * from customer_master where 1 = 1\
+             {% if status == \"active\" %} and active{% endif %}\
+             {% if customer_id %} and id = {{ customer_id }}{% endif %}",
+            TemplateMode::Bind,
+        );
+        let active = HashMap::from([("status".to_string(), ParamValue::Str("active".to_string()))]);
+
+        let compiled = templates
+            .compile("customer_master", &active, &AuthContext::default())
+            .unwrap();
+        assert_eq!(compiled.sql, "select * from customer_master where 1 = 1 and active");
+        assert!(compiled.params.is_empty());
+
+        let by_id = HashMap::from([("customer_id".to_string(), ParamValue::Str("c-1".to_string()))]);
+        let compiled = templates
+            .compile("customer_master", &by_id, &AuthContext::default())
+            .unwrap();
+        assert_eq!(compiled.sql, "select * from customer_master where 1 = 1 and id = ?");
+        assert_eq!(compiled.params, vec![ParamValue::Str("c-1".to_string())]);
+    }
+
+    #[test]
+    fn test_bind_mode_conditions_on_the_roles() {
+        let templates = templates(
+            "select * from orders where \
+             {% if \"admin\" in auth.roles %}1 = 1{% else %}region = {{ auth.attributes.region }}{% endif %}",
+            TemplateMode::Bind,
+        );
+        let analyst = AuthContext {
+            user_id: "u-1".to_string(),
+            roles: vec!["analyst".to_string()],
+            attributes: [("region".to_string(), "EMEA".to_string())].into(),
+        };
+        let admin = AuthContext {
+            roles: vec!["analyst".to_string(), "admin".to_string()],
+            ..analyst.clone()
+        };
+
+        let compiled = templates.compile("customer_master", &HashMap::new(), &analyst).unwrap();
+        assert_eq!(compiled.sql, "select * from orders where region = ?");
+        assert_eq!(compiled.params, vec![ParamValue::Str("EMEA".to_string())]);
+
+        let compiled = templates.compile("customer_master", &HashMap::new(), &admin).unwrap();
+        assert_eq!(compiled.sql, "select * from orders where 1 = 1");
+        assert!(compiled.params.is_empty());
+    }
+
+    #[test]
+    fn test_bind_mode_concatenates_values_inside_literals() {
+        let templates = templates(
+            "select * from customer_master where name like '%{{name}}%' or id like '{{ customer_id }}%'",
+            TemplateMode::Bind,
+        );
+        let params = HashMap::from([
+            ("name".to_string(), ParamValue::Str("o'neil".to_string())),
+            ("customer_id".to_string(), ParamValue::Str("c-".to_string())),
+        ]);
+
+        let compiled = templates
+            .compile("customer_master", &params, &AuthContext::default())
+            .unwrap();
+        assert_eq!(
+            compiled.sql,
+            "select * from customer_master where name like '%' || ? || '%' or id like ? || '%'"
+        );
+        assert_eq!(
+            compiled.params,
+            vec![ParamValue::Str("o'neil".to_string()), ParamValue::Str("c-".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_bind_mode_refuses_what_it_cannot_bind() {
+        for sql in [
+            "select * from customer_master where name = {{ name | upper }}",
+            "select * from customer_master where id = {{ undeclared }}",
+            "select * from customer_master where age = {{ customer_id + 1 }}",
+        ] {
+            assert!(try_templates(sql, TemplateMode::Bind).is_err(), "{}", sql);
+        }
+
+        let templates = templates(
+            "select * from customer_master where id = {{ customer_id }}",
+            TemplateMode::Bind,
+        );
+        let missing = templates.compile("customer_master", &HashMap::new(), &AuthContext::default());
+        assert!(matches!(missing, Err(ApiError::InvalidRequestParameters(_))));
+    }
+}
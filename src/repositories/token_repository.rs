@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::datasource::{DataSource, Statement};
+use crate::domain::columns::parse_bool;
+
+/// An issued refresh token. The token itself is never stored, only its id and the family of rotations it belongs to.
+#[derive(Debug, Clone)]
+pub struct RefreshTokenEntity {
+    pub token_uuid: String,
+    pub family_id: String,
+    pub user_id: String,
+    pub expires_at: i64,
+    pub used: bool,
+    pub revoked: bool,
+}
+
+/// An issued access token, as recorded in `token_details`
+#[derive(Debug, Clone)]
+pub struct TokenDetailsEntity {
+    pub token_uuid: String,
+    pub user_id: String,
+    pub family_id: String,
+    pub expires_in: i64,
+    pub max_age: i64,
+    pub revoked: bool,
+}
+
+#[derive(Clone)]
+pub struct TokenRepository {
+    source: Arc<dyn DataSource>,
+}
+
+impl TokenRepository {
+    pub fn new(source: Arc<dyn DataSource>) -> Self {
+        Self { source }
+    }
+
+    pub async fn insert_refresh_token(&self, token: &RefreshTokenEntity) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (token_uuid, family_id, user_id, expires_at) VALUES (?,?,?,?)",
+                    self.source.table("nexus_refresh_tokens")
+                ),
+                vec![
+                    token.token_uuid.as_str().into(),
+                    token.family_id.as_str().into(),
+                    token.user_id.as_str().into(),
+                    token.expires_at.into(),
+                ],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_refresh_token(&self, token_uuid: &str) -> anyhow::Result<Option<RefreshTokenEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT token_uuid, family_id, user_id, expires_at, used_at, revoked_at FROM {} WHERE token_uuid = ?",
+                    self.source.table("nexus_refresh_tokens")
+                ),
+                vec![token_uuid.into()],
+            ))
+            .await?;
+
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        Ok(Some(RefreshTokenEntity {
+            token_uuid: row.text(0).to_string(),
+            family_id: row.text(1).to_string(),
+            user_id: row.text(2).to_string(),
+            expires_at: row.text(3).parse().context("Invalid expires_at of refresh token")?,
+            used: row.get(4).is_some(),
+            revoked: row.get(5).is_some(),
+        }))
+    }
+
+    /// Marks the token as used, unless it already was or its family was revoked. Returns whether this call did, so
+    /// that two concurrent refreshes with the same token cannot both succeed.
+    pub async fn consume_refresh_token(&self, token_uuid: &str) -> anyhow::Result<bool> {
+        let changed = self
+            .source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET used_at = CURRENT_TIMESTAMP \
+                     WHERE token_uuid = ? AND used_at IS NULL AND revoked_at IS NULL",
+                    self.source.table("nexus_refresh_tokens")
+                ),
+                vec![token_uuid.into()],
+            ))
+            .await?;
+        Ok(changed == 1)
+    }
+
+    pub async fn revoke_token_family(&self, family_id: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET revoked_at = CURRENT_TIMESTAMP WHERE family_id = ? AND revoked_at IS NULL",
+                    self.source.table("nexus_refresh_tokens")
+                ),
+                vec![family_id.into()],
+            ))
+            .await?;
+        info!("Revoked refresh token family {}", family_id);
+        Ok(())
+    }
+
+    pub async fn insert_token_details(&self, token: &TokenDetailsEntity) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (token_uuid, user_id, family_id, expires_in, max_age) VALUES (?,?,?,?,?)",
+                    self.source.table("token_details")
+                ),
+                vec![
+                    token.token_uuid.as_str().into(),
+                    token.user_id.as_str().into(),
+                    token.family_id.as_str().into(),
+                    token.expires_in.into(),
+                    token.max_age.into(),
+                ],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_token_details(&self, token_uuid: &str) -> anyhow::Result<Option<TokenDetailsEntity>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT token_uuid, user_id, family_id, expires_in, max_age, revoked FROM {} WHERE token_uuid = ?",
+                    self.source.table("token_details")
+                ),
+                vec![token_uuid.into()],
+            ))
+            .await?;
+
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        Ok(Some(TokenDetailsEntity {
+            token_uuid: row.text(0).to_string(),
+            user_id: row.text(1).to_string(),
+            family_id: row.text(2).to_string(),
+            expires_in: row.text(3).parse().context("Invalid expires_in of token")?,
+            max_age: row.text(4).parse().context("Invalid max_age of token")?,
+            revoked: parse_bool(row.text(5)).unwrap_or(false),
+        }))
+    }
+
+    /// Revokes the access tokens and the refresh tokens of one login
+    pub async fn revoke_session(&self, family_id: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET revoked = TRUE, updated_at = CURRENT_TIMESTAMP WHERE family_id = ? AND revoked = FALSE",
+                    self.source.table("token_details")
+                ),
+                vec![family_id.into()],
+            ))
+            .await?;
+        self.revoke_token_family(family_id).await
+    }
+
+    /// Revokes every access token and refresh token issued to the user
+    pub async fn revoke_user_tokens(&self, user_id: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET revoked = TRUE, updated_at = CURRENT_TIMESTAMP WHERE user_id = ? AND revoked = FALSE",
+                    self.source.table("token_details")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ? AND revoked_at IS NULL",
+                    self.source.table("nexus_refresh_tokens")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+        info!("Revoked all tokens of user {}", user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datasource::sqlite::test_source;
+    use crate::repositories::token_repository::{RefreshTokenEntity, TokenDetailsEntity, TokenRepository};
+
+    fn refresh_token(token_uuid: &str, family_id: &str) -> RefreshTokenEntity {
+        RefreshTokenEntity {
+            token_uuid: token_uuid.to_string(),
+            family_id: family_id.to_string(),
+            user_id: "u-1".to_string(),
+            expires_at: 1_700_000_000,
+            used: false,
+            revoked: false,
+        }
+    }
+
+    fn token_details(token_uuid: &str, family_id: &str) -> TokenDetailsEntity {
+        TokenDetailsEntity {
+            token_uuid: token_uuid.to_string(),
+            user_id: "u-1".to_string(),
+            family_id: family_id.to_string(),
+            expires_in: 1_700_000_000,
+            max_age: 15,
+            revoked: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens_are_used_once() {
+        let tokens = TokenRepository::new(test_source());
+        tokens.insert_refresh_token(&refresh_token("r-1", "f-1")).await.unwrap();
+        tokens.insert_refresh_token(&refresh_token("r-2", "f-2")).await.unwrap();
+
+        let stored = tokens.get_refresh_token("r-1").await.unwrap().unwrap();
+        assert_eq!((stored.family_id.as_str(), stored.user_id.as_str()), ("f-1", "u-1"));
+        assert_eq!(stored.expires_at, 1_700_000_000);
+        assert!(!stored.used && !stored.revoked);
+        assert!(tokens.get_refresh_token("r-3").await.unwrap().is_none());
+
+        assert!(tokens.consume_refresh_token("r-1").await.unwrap());
+        assert!(!tokens.consume_refresh_token("r-1").await.unwrap());
+        assert!(tokens.get_refresh_token("r-1").await.unwrap().unwrap().used);
+
+        tokens.revoke_token_family("f-2").await.unwrap();
+        assert!(tokens.get_refresh_token("r-2").await.unwrap().unwrap().revoked);
+        assert!(!tokens.consume_refresh_token("r-2").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_access_tokens_are_revoked_by_login_and_by_user() {
+        let tokens = TokenRepository::new(test_source());
+        for (token_uuid, family_id) in [("a-1", "f-1"), ("a-2", "f-2")] {
+            tokens
+                .insert_token_details(&token_details(token_uuid, family_id))
+                .await
+                .unwrap();
+            tokens
+                .insert_refresh_token(&refresh_token(token_uuid, family_id))
+                .await
+                .unwrap();
+        }
+
+        let stored = tokens.get_token_details("a-1").await.unwrap().unwrap();
+        assert_eq!((stored.family_id.as_str(), stored.max_age), ("f-1", 15));
+        assert!(!stored.revoked);
+
+        tokens.revoke_session("f-1").await.unwrap();
+        assert!(tokens.get_token_details("a-1").await.unwrap().unwrap().revoked);
+        assert!(tokens.get_refresh_token("a-1").await.unwrap().unwrap().revoked);
+        assert!(!tokens.get_token_details("a-2").await.unwrap().unwrap().revoked);
+
+        tokens.revoke_user_tokens("u-1").await.unwrap();
+        assert!(tokens.get_token_details("a-2").await.unwrap().unwrap().revoked);
+        assert!(tokens.get_refresh_token("a-2").await.unwrap().unwrap().revoked);
+    }
+}
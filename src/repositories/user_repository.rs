@@ -1,39 +1,46 @@
-use anyhow::{bail, Context};
-use axum_odbc::odbc::buffers::TextRowSet;
-use axum_odbc::odbc::{Cursor, IntoParameter};
-use axum_odbc::ODBCConnectionManager;
-use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use tracing::info;
 use uuid::Uuid;
 
+use crate::datasource::{DataSource, Row, Statement};
+use crate::domain::columns::parse_bool;
 use crate::domain::RegisterUserDto;
 
+const USER_COLUMNS: &str = "id, name, email, password, created_at, updated_at, disabled_at, email_verified_at";
+/// Escapes the wildcards of a search in LIKE patterns. A backslash would itself need escaping in the literals of some
+/// databases, Snowflake among them.
+const LIKE_ESCAPE: char = '!';
+
 #[derive(Clone)]
 pub struct UserRepository {
-    pool: ODBCConnectionManager,
+    source: Arc<dyn DataSource>,
 }
 
 impl UserRepository {
-    pub fn new(db: ODBCConnectionManager) -> Self {
-        Self { pool: db }
+    pub fn new(source: Arc<dyn DataSource>) -> Self {
+        Self { source }
     }
     pub async fn create_user(&self, register_user: RegisterUserDto, hashed_password: String) -> anyhow::Result<String> {
-        let conn = self
-            .pool
-            .aquire()
-            .await
-            .context("Unable to get a connection from the ODBCConnectionManager")?;
-
         let id = Uuid::new_v4().to_string();
-        let mut prepared =
-            conn.prepare("INSERT INTO nexus_db.public.nexus_users  (id, name, email, password) VALUES (?,?,?,?)")?;
-        let params = (
-            &id.into_parameter(),
-            &register_user.name.clone().into_parameter(),
-            &register_user.email.into_parameter(),
-            &hashed_password.into_parameter(),
-        );
-        prepared.execute(params)?;
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "INSERT INTO {} (id, name, email, password, created_at, updated_at) \
+                     VALUES (?,?,?,?,CURRENT_TIMESTAMP,CURRENT_TIMESTAMP)",
+                    self.source.table("nexus_users")
+                ),
+                vec![
+                    id.into(),
+                    register_user.name.clone().into(),
+                    register_user.email.into(),
+                    hashed_password.into(),
+                ],
+            ))
+            .await?;
 
         info!("User {} successfully inserted", &register_user.name);
 
@@ -44,70 +51,34 @@ impl UserRepository {
     }
 
     pub async fn does_user_exist(&self, email: &str) -> anyhow::Result<bool> {
-        let conn = self
-            .pool
-            .aquire()
-            .await
-            .context("Unable to get a connection from the ODBCConnectionManager")?;
-
-        let mut cursor = conn
-            .execute(
-                "SELECT EXISTS(SELECT 1 FROM nexus_db.public.nexus_users WHERE email = ?)",
-                &email.into_parameter(),
-            )?
-            .expect("select statement must create a cursor");
-        if let Some(mut _row) = cursor.next_row()? {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT EXISTS(SELECT 1 FROM {} WHERE email = ?)",
+                    self.source.table("nexus_users")
+                ),
+                vec![email.into()],
+            ))
+            .await?;
+        Ok(rows.first().and_then(|row| parse_bool(row.text(0))).unwrap_or(false))
     }
 
     pub async fn get_user_by_email(&self, email: &str) -> anyhow::Result<UserEntity> {
-        let conn = self
-            .pool
-            .aquire()
-            .await
-            .context("Unable to get a connection from the ODBCConnectionManager")?;
-
-        let mut cursor = conn
-            .execute(
-                "SELECT id, name, email, password FROM nexus_db.public.nexus_users WHERE email = ?",
-                &email.into_parameter(),
-            )?
-            .expect("select statement must create a cursor");
-        let mut buffers = TextRowSet::for_cursor(1, &mut cursor, Some(4096))?;
-        let mut row_set_cursor = cursor.bind_buffer(&mut buffers)?;
-
-        if let Some(batch) = row_set_cursor.fetch()? {
-            let user = UserEntity {
-                id: batch
-                    .at_as_str(0, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string(),
-                name: batch
-                    .at_as_str(1, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string()
-                    .to_string(),
-                email: batch
-                    .at_as_str(2, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string()
-                    .to_string(),
-                password: batch
-                    .at_as_str(3, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string()
-                    .to_string(),
-                created_at: None,
-                updated_at: None,
-            };
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE email = ?",
+                    USER_COLUMNS,
+                    self.source.table("nexus_users")
+                ),
+                vec![email.into()],
+            ))
+            .await?;
 
+        if let Some(row) = rows.first() {
+            let user = read_user(row);
             info!("user = {:?}", user);
             Ok(user)
         } else {
@@ -116,49 +87,215 @@ impl UserRepository {
     }
 
     pub async fn get_user_by_id(&self, user_id: &str) -> anyhow::Result<UserEntity> {
-        let conn = self
-            .pool
-            .aquire()
-            .await
-            .context("Unable to get a connection from the ODBCConnectionManager")?;
-
-        let mut cursor = conn
-            .execute(
-                "SELECT id, name, email FROM nexus_db.public.nexus_users WHERE id = ?",
-                &user_id.into_parameter(),
-            )?
-            .expect("select statement must create a cursor");
-        let mut buffers = TextRowSet::for_cursor(1, &mut cursor, Some(4096))?;
-        let mut row_set_cursor = cursor.bind_buffer(&mut buffers)?;
-
-        if let Some(batch) = row_set_cursor.fetch()? {
-            let user = UserEntity {
-                id: batch
-                    .at_as_str(0, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string(),
-                name: batch
-                    .at_as_str(1, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string()
-                    .to_string(),
-                email: batch
-                    .at_as_str(2, 0)
-                    .unwrap()
-                    .expect("Can't convert the userid to string")
-                    .to_string()
-                    .to_string(),
-                password: "".to_string(),
-                created_at: None,
-                updated_at: None,
-            };
-            Ok(user)
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT {} FROM {} WHERE id = ?",
+                    USER_COLUMNS,
+                    self.source.table("nexus_users")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+
+        if let Some(row) = rows.first() {
+            Ok(read_user(row))
         } else {
             bail!(format!("Unable to fetch user with that id : {}", user_id))
         }
     }
+
+    /// A page of the users, oldest first. `search` matches a part of the name or of the email, ignoring case; `%` and
+    /// `_` in it are matched as they are.
+    pub async fn list_users(&self, search: Option<&str>, limit: u32, offset: u32) -> anyhow::Result<Vec<UserEntity>> {
+        let page = format!("ORDER BY created_at, id LIMIT {} OFFSET {}", limit, offset);
+        let statement = match search {
+            Some(search) => {
+                let pattern = format!("%{}%", escape_like(&search.to_lowercase()));
+                Statement::new(
+                    format!(
+                        "SELECT {columns} FROM {table} WHERE LOWER(name) LIKE ? ESCAPE '{escape}' \
+                         OR LOWER(email) LIKE ? ESCAPE '{escape}' {page}",
+                        columns = USER_COLUMNS,
+                        table = self.source.table("nexus_users"),
+                        escape = LIKE_ESCAPE,
+                        page = page
+                    ),
+                    vec![pattern.clone().into(), pattern.into()],
+                )
+            }
+            None => Statement::new(
+                format!(
+                    "SELECT {} FROM {} {}",
+                    USER_COLUMNS,
+                    self.source.table("nexus_users"),
+                    page
+                ),
+                vec![],
+            ),
+        };
+        let rows = self.source.query(statement).await?;
+        Ok(rows.iter().map(read_user).collect())
+    }
+
+    pub async fn update_profile(&self, user_id: &str, name: &str, email: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET name = ?, email = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                    self.source.table("nexus_users")
+                ),
+                vec![name.into(), email.into(), user_id.into()],
+            ))
+            .await?;
+        info!("Profile of user {} updated", user_id);
+        Ok(())
+    }
+
+    /// Disables the account of the user, or enables it again
+    pub async fn set_disabled(&self, user_id: &str, disabled: bool) -> anyhow::Result<()> {
+        let table = self.source.table("nexus_users");
+        let sql = if disabled {
+            format!(
+                "UPDATE {} SET disabled_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP \
+                 WHERE id = ? AND disabled_at IS NULL",
+                table
+            )
+        } else {
+            format!(
+                "UPDATE {} SET disabled_at = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                table
+            )
+        };
+        self.source.execute(Statement::new(sql, vec![user_id.into()])).await?;
+        info!("User {} {}", user_id, if disabled { "disabled" } else { "enabled" });
+        Ok(())
+    }
+
+    /// Marks the email address of the user as verified, or as unverified after it changed
+    pub async fn set_email_verified(&self, user_id: &str, verified: bool) -> anyhow::Result<()> {
+        let table = self.source.table("nexus_users");
+        let sql = if verified {
+            format!(
+                "UPDATE {} SET email_verified_at = CURRENT_TIMESTAMP WHERE id = ? AND email_verified_at IS NULL",
+                table
+            )
+        } else {
+            format!("UPDATE {} SET email_verified_at = NULL WHERE id = ?", table)
+        };
+        self.source.execute(Statement::new(sql, vec![user_id.into()])).await?;
+        Ok(())
+    }
+
+    /// Deletes the user along with its roles, attributes, API keys and account tokens
+    pub async fn delete_user(&self, user_id: &str) -> anyhow::Result<()> {
+        let mut statements = [
+            "nexus_user_roles",
+            "nexus_user_attributes",
+            "nexus_api_keys",
+            "nexus_account_tokens",
+        ]
+        .into_iter()
+        .map(|table| {
+            Statement::new(
+                format!("DELETE FROM {} WHERE user_id = ?", self.source.table(table)),
+                vec![user_id.into()],
+            )
+        })
+        .collect::<Vec<_>>();
+        statements.push(Statement::new(
+            format!("DELETE FROM {} WHERE id = ?", self.source.table("nexus_users")),
+            vec![user_id.into()],
+        ));
+        self.source.execute_batch(statements).await?;
+        info!("User {} deleted", user_id);
+        Ok(())
+    }
+
+    pub async fn update_password(&self, user_id: &str, hashed_password: &str) -> anyhow::Result<()> {
+        self.source
+            .execute(Statement::new(
+                format!(
+                    "UPDATE {} SET password = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+                    self.source.table("nexus_users")
+                ),
+                vec![hashed_password.into(), user_id.into()],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_user_roles(&self, user_id: &str) -> anyhow::Result<Vec<String>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT role FROM {} WHERE user_id = ? ORDER BY role",
+                    self.source.table("nexus_user_roles")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+        Ok(rows.iter().filter_map(|row| row.get(0).map(str::to_string)).collect())
+    }
+
+    /// Replaces the roles of the user
+    pub async fn set_user_roles(&self, user_id: &str, roles: &[String]) -> anyhow::Result<()> {
+        let table = self.source.table("nexus_user_roles");
+        let mut statements = vec![Statement::new(
+            format!("DELETE FROM {} WHERE user_id = ?", table),
+            vec![user_id.into()],
+        )];
+        statements.extend(roles.iter().map(|role| {
+            Statement::new(
+                format!("INSERT INTO {} (user_id, role) VALUES (?,?)", table),
+                vec![user_id.into(), role.as_str().into()],
+            )
+        }));
+        self.source.execute_batch(statements).await?;
+        info!("Roles of user {} set to {:?}", user_id, roles);
+        Ok(())
+    }
+
+    pub async fn get_user_attributes(&self, user_id: &str) -> anyhow::Result<BTreeMap<String, String>> {
+        let rows = self
+            .source
+            .query(Statement::new(
+                format!(
+                    "SELECT name, value FROM {} WHERE user_id = ?",
+                    self.source.table("nexus_user_attributes")
+                ),
+                vec![user_id.into()],
+            ))
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| row.get(0).map(|name| (name.to_string(), row.text(1).to_string())))
+            .collect())
+    }
+
+    /// Replaces the attributes of the user
+    pub async fn set_user_attributes(
+        &self,
+        user_id: &str,
+        attributes: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let table = self.source.table("nexus_user_attributes");
+        let mut statements = vec![Statement::new(
+            format!("DELETE FROM {} WHERE user_id = ?", table),
+            vec![user_id.into()],
+        )];
+        statements.extend(attributes.iter().map(|(name, value)| {
+            Statement::new(
+                format!("INSERT INTO {} (user_id, name, value) VALUES (?,?,?)", table),
+                vec![user_id.into(), name.as_str().into(), value.as_str().into()],
+            )
+        }));
+        self.source.execute_batch(statements).await?;
+        info!("Attributes of user {} set to {:?}", user_id, attributes);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -169,4 +306,116 @@ pub struct UserEntity {
     pub password: String,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+}
+
+impl UserEntity {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+}
+
+fn read_user(row: &Row) -> UserEntity {
+    UserEntity {
+        id: row.text(0).to_string(),
+        name: row.text(1).to_string(),
+        email: row.text(2).to_string(),
+        password: row.text(3).to_string(),
+        created_at: parse_timestamp(row.text(4)),
+        updated_at: parse_timestamp(row.text(5)),
+        disabled_at: parse_timestamp(row.text(6)),
+        email_verified_at: parse_timestamp(row.text(7)),
+    }
+}
+
+/// Reads a `TIMESTAMP` column as text, which drivers render as `2023-12-01 10:00:00.000` in UTC, or in RFC 3339
+fn parse_timestamp(text: &str) -> Option<DateTime<Utc>> {
+    if text.is_empty() {
+        return None;
+    }
+    DateTime::parse_from_rfc3339(text)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_e| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f").map(|naive| naive.and_utc()))
+        .ok()
+}
+
+fn escape_like(search: &str) -> String {
+    let mut escaped = String::with_capacity(search.len());
+    for c in search.chars() {
+        if c == LIKE_ESCAPE || c == '%' || c == '_' {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::datasource::sqlite::test_source;
+    use crate::domain::RegisterUserDto;
+    use crate::repositories::user_repository::{parse_timestamp, UserRepository};
+
+    #[test]
+    fn test_timestamps_are_read_as_utc() {
+        let expected = Utc.with_ymd_and_hms(2023, 12, 1, 10, 30, 0).unwrap();
+        assert_eq!(parse_timestamp("2023-12-01 10:30:00.000"), Some(expected));
+        assert_eq!(parse_timestamp("2023-12-01 10:30:00"), Some(expected));
+        assert_eq!(parse_timestamp("2023-12-01T11:30:00+01:00"), Some(expected));
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_roles_are_replaced_all_or_nothing() {
+        let users = UserRepository::new(test_source());
+        let reader = ["reader".to_string()];
+        users.set_user_roles("u1", &reader).await.unwrap();
+
+        let duplicated = ["admin".to_string(), "admin".to_string()];
+        assert!(users.set_user_roles("u1", &duplicated).await.is_err());
+        assert_eq!(users.get_user_roles("u1").await.unwrap(), reader);
+
+        users.set_user_roles("u1", &duplicated[..1]).await.unwrap();
+        assert_eq!(users.get_user_roles("u1").await.unwrap(), ["admin"]);
+    }
+
+    #[tokio::test]
+    async fn test_users_round_trip() {
+        let users = UserRepository::new(test_source());
+        let register_user = RegisterUserDto {
+            name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            password: "not stored".to_string(),
+            invite_code: None,
+        };
+        users.create_user(register_user, "hash".to_string()).await.unwrap();
+
+        assert!(users.does_user_exist("jane@example.com").await.unwrap());
+        assert!(!users.does_user_exist("john@example.com").await.unwrap());
+        let user = users.get_user_by_email("jane@example.com").await.unwrap();
+        assert_eq!((user.name.as_str(), user.password.as_str()), ("Jane", "hash"));
+        assert!(user.created_at.is_some());
+        assert!(user.disabled_at.is_none() && user.email_verified_at.is_none());
+        assert_eq!(users.get_user_by_id(&user.id).await.unwrap().email, "jane@example.com");
+        assert!(users.get_user_by_id("u-unknown").await.is_err());
+
+        users.update_password(&user.id, "new hash").await.unwrap();
+        users.set_email_verified(&user.id, true).await.unwrap();
+        users.set_disabled(&user.id, true).await.unwrap();
+        let user = users.get_user_by_id(&user.id).await.unwrap();
+        assert_eq!(user.password, "new hash");
+        assert!(user.email_verified_at.is_some() && user.disabled_at.is_some());
+
+        let attributes = BTreeMap::from([
+            ("region".to_string(), "EMEA".to_string()),
+            ("tier".to_string(), String::new()),
+        ]);
+        users.set_user_attributes(&user.id, &attributes).await.unwrap();
+        assert_eq!(users.get_user_attributes(&user.id).await.unwrap(), attributes);
+    }
 }
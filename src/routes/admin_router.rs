@@ -0,0 +1,200 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post, put};
+use axum::{middleware, Extension, Json, Router};
+use tracing::info;
+
+use crate::auth::{require_admin, validate_jwt_token, ValidatedTokenDetails};
+use crate::domain::req_res::{CreateInviteRequest, ListUsersQuery, UpdateAttributesRequest, UpdateRolesRequest};
+use crate::domain::{CreatedInviteDto, InviteDto, UserDto};
+use crate::errors::{ApiError, ApiResult};
+use crate::service_register::ServiceRegister;
+use crate::services::invite_service::InviteService;
+use crate::services::user_service::UserService;
+use crate::AppState;
+
+pub struct AdminRouter;
+
+impl AdminRouter {
+    pub fn new_router(app_state: AppState, service_register: ServiceRegister) -> Router {
+        Router::new()
+            .route("/users", get(AdminRouter::list_users_handler))
+            .route(
+                "/users/:user_id",
+                get(AdminRouter::get_user_handler).delete(AdminRouter::delete_user_handler),
+            )
+            .route("/users/:user_id/disable", post(AdminRouter::disable_user_handler))
+            .route("/users/:user_id/enable", post(AdminRouter::enable_user_handler))
+            .route(
+                "/users/:user_id/revoke-sessions",
+                post(AdminRouter::revoke_sessions_handler),
+            )
+            .route("/users/:user_id/roles", put(AdminRouter::set_roles_handler))
+            .route("/users/:user_id/attributes", put(AdminRouter::set_attributes_handler))
+            .route("/users/:user_id/unlock", post(AdminRouter::unlock_user_handler))
+            .route("/addresses/:address/unlock", post(AdminRouter::unlock_address_handler))
+            .route(
+                "/invites",
+                get(AdminRouter::list_invites_handler).post(AdminRouter::create_invite_handler),
+            )
+            .route("/invites/:invite_id", delete(AdminRouter::delete_invite_handler))
+            .route_layer(middleware::from_fn(require_admin))
+            .route_layer(middleware::from_fn_with_state(app_state, validate_jwt_token))
+            .layer(Extension(service_register.user_service))
+            .layer(Extension(service_register.invite_service))
+    }
+
+    pub async fn list_users_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Query(query): Query<ListUsersQuery>,
+    ) -> ApiResult<Json<Vec<UserDto>>> {
+        let users = user_service.list_users_handler(query).await?;
+        Ok(Json(users))
+    }
+
+    pub async fn get_user_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<Json<UserDto>> {
+        let user = user_service.get_user(&user_id).await?;
+        Ok(Json(user))
+    }
+
+    pub async fn disable_user_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<Json<UserDto>> {
+        info!("Admin {} disabling user {}", validated_token.user_id, user_id);
+        let user = user_service
+            .set_disabled_handler(&validated_token.user_id, &user_id, true)
+            .await?;
+        Ok(Json(user))
+    }
+
+    pub async fn enable_user_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<Json<UserDto>> {
+        info!("Admin {} enabling user {}", validated_token.user_id, user_id);
+        let user = user_service
+            .set_disabled_handler(&validated_token.user_id, &user_id, false)
+            .await?;
+        Ok(Json(user))
+    }
+
+    pub async fn delete_user_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        info!("Admin {} deleting user {}", validated_token.user_id, user_id);
+        user_service
+            .delete_user_handler(&validated_token.user_id, &user_id)
+            .await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn revoke_sessions_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        info!(
+            "Admin {} revoking all sessions of user {}",
+            validated_token.user_id, user_id
+        );
+        user_service.revoke_sessions_handler(&user_id).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn set_roles_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+        Json(request): Json<UpdateRolesRequest>,
+    ) -> ApiResult<Json<UserDto>> {
+        info!(
+            "Admin {} setting the roles of user {} to {:?}",
+            validated_token.user_id, user_id, request.roles
+        );
+        let user = user_service.set_roles_handler(&user_id, request.roles).await?;
+        Ok(Json(user))
+    }
+
+    pub async fn set_attributes_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+        Json(request): Json<UpdateAttributesRequest>,
+    ) -> ApiResult<Json<UserDto>> {
+        info!(
+            "Admin {} setting the attributes of user {} to {:?}",
+            validated_token.user_id, user_id, request.attributes
+        );
+        let user = user_service
+            .set_attributes_handler(&user_id, request.attributes)
+            .await?;
+        Ok(Json(user))
+    }
+
+    pub async fn unlock_user_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(user_id): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        info!(
+            "Admin {} unlocking the login of user {}",
+            validated_token.user_id, user_id
+        );
+        user_service.unlock_user_handler(&user_id).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn unlock_address_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(address): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|_e| ApiError::BadRequest(format!("{} is not an IP address", address)))?;
+        info!("Admin {} unlocking logins from {}", validated_token.user_id, address);
+        user_service.login_throttle.unlock_address(address).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn create_invite_handler(
+        Extension(invite_service): Extension<Arc<InviteService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Json(request): Json<CreateInviteRequest>,
+    ) -> ApiResult<(StatusCode, Json<CreatedInviteDto>)> {
+        info!(
+            "Admin {} creating an invite for {:?} with roles {:?}",
+            validated_token.user_id, request.email, request.roles
+        );
+        let invite = invite_service.create_invite(&validated_token.user_id, request).await?;
+        Ok((StatusCode::CREATED, Json(invite)))
+    }
+
+    pub async fn list_invites_handler(
+        Extension(invite_service): Extension<Arc<InviteService>>,
+    ) -> ApiResult<Json<Vec<InviteDto>>> {
+        let invites = invite_service.list_invites().await?;
+        Ok(Json(invites))
+    }
+
+    pub async fn delete_invite_handler(
+        Extension(invite_service): Extension<Arc<InviteService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(invite_id): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        info!("Admin {} deleting invite {}", validated_token.user_id, invite_id);
+        invite_service.delete_invite(&invite_id).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
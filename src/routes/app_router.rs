@@ -3,7 +3,9 @@ use axum::routing::get;
 use axum::{Json, Router};
 use serde_json::json;
 
+use crate::routes::admin_router::AdminRouter;
 use crate::routes::data_router::DataRouter;
+use crate::routes::docs_router::DocsRouter;
 use crate::routes::user_router::UserRouter;
 use crate::service_register::ServiceRegister;
 use crate::AppState;
@@ -14,10 +16,15 @@ impl AppRouter {
     pub fn new(app_state: AppState, service_register: ServiceRegister) -> Router {
         Router::new()
             .route("/healthz", get(healthz_handler)) //Extract this out.
+            .merge(DocsRouter::new_router(app_state.clone()))
             .nest(
                 "/api/users",
                 UserRouter::new_router(app_state.clone(), service_register.clone()),
             )
+            .nest(
+                "/api/admin",
+                AdminRouter::new_router(app_state.clone(), service_register.clone()),
+            )
             .nest("/", DataRouter::new_router(app_state, service_register))
     }
 }
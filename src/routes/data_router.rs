@@ -2,13 +2,19 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use axum::extract::Query;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::{middleware, Extension, Json, Router};
 use tracing::info;
 
-use crate::auth::{validate_jwt_token, ValidatedTokenDetails};
-use crate::domain::req_res::DataResponse;
-use crate::errors::ApiResult;
+use crate::auth::{require_roles, require_scope, validate_jwt_token, ValidatedTokenDetails};
+use crate::config::EndpointConfig;
+use crate::domain::identity::AuthContext;
+use crate::domain::req_res::DataPage;
+use crate::errors::{ApiError, ApiResult};
+use crate::formats::{stream_body, OutputFormat, FORMAT_PARAM};
 use crate::service_register::ServiceRegister;
 use crate::services::data_service::DataService;
 use crate::AppState;
@@ -17,23 +23,45 @@ pub struct DataRouter;
 
 impl DataRouter {
     pub fn new_router(app_state: AppState, service_register: ServiceRegister) -> Router {
-        Router::new()
-            //.route(format!("{}/config", app_state.config.endpoint).as_str(), get(config))
-            .route(
-                app_state.config.api.endpoint.as_str(),
+        let mut router = Router::new();
+        for endpoint in &app_state.config.endpoints {
+            info!("Registering endpoint {} at {}", endpoint.name, endpoint.endpoint);
+            router = router.route(
+                endpoint.endpoint.as_str(),
                 get(DataRouter::extract_results_handler)
-                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
-            )
-            .layer(Extension(service_register.data_service))
+                    .route_layer(middleware::from_fn_with_state(
+                        Arc::new(endpoint.name.clone()),
+                        require_scope,
+                    ))
+                    .route_layer(middleware::from_fn_with_state(
+                        Arc::new(endpoint.allowed_roles.clone()),
+                        require_roles,
+                    ))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token))
+                    .layer(Extension(Arc::new(endpoint.clone()))),
+            );
+        }
+        router.layer(Extension(service_register.data_service))
     }
-    // .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token))
-    //                     .with_state(app_state),
+
     pub async fn extract_results_handler(
         Extension(data_service): Extension<Arc<DataService>>,
+        Extension(endpoint): Extension<Arc<EndpointConfig>>,
         Extension(validated_token): Extension<ValidatedTokenDetails>,
-        Query(params): Query<HashMap<String, String>>,
-    ) -> ApiResult<Json<DataResponse>> {
-        info!("Getting me for user: {:?}", validated_token.user_id);
-        Ok(Json(data_service.extract_results(params).await?))
+        headers: HeaderMap,
+        Query(mut params): Query<HashMap<String, String>>,
+    ) -> ApiResult<Response> {
+        info!("Extracting {} for user: {:?}", endpoint.name, validated_token.user_id);
+        let format = OutputFormat::negotiate(params.remove(FORMAT_PARAM).as_deref(), &headers)
+            .map_err(|violation| ApiError::InvalidRequestParameters(vec![violation]))?;
+        let auth = AuthContext::from(&validated_token);
+        if format.is_streamed(endpoint.stream) {
+            let batches = data_service.stream_results(&endpoint.name, params, &auth).await?;
+            let body = stream_body(format, batches).await?;
+            Ok(([(CONTENT_TYPE, format.content_type())], body).into_response())
+        } else {
+            let page: DataPage = data_service.extract_page(&endpoint.name, params, &auth).await?;
+            Ok(Json(page).into_response())
+        }
     }
 }
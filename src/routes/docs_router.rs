@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde_json::Value;
+
+use crate::openapi::openapi_document;
+use crate::AppState;
+
+const OPENAPI_PATH: &str = "/openapi.json";
+/// The Swagger UI assets are built into the binary, so the page works offline and runs no third-party code
+const SWAGGER_UI_BUNDLE: &str = include_str!("docs/swagger-ui/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &str = include_str!("docs/swagger-ui/swagger-ui.css");
+const ASSET_CACHE_CONTROL: &str = "public, max-age=86400";
+
+pub struct DocsRouter;
+
+impl DocsRouter {
+    pub fn new_router(app_state: AppState) -> Router {
+        let document = Arc::new(openapi_document(&app_state.config));
+        Router::new()
+            .route(OPENAPI_PATH, get(DocsRouter::openapi_handler))
+            .route("/docs", get(DocsRouter::swagger_ui_handler))
+            .route("/docs/swagger-ui-bundle.js", get(DocsRouter::swagger_ui_bundle_handler))
+            .route("/docs/swagger-ui.css", get(DocsRouter::swagger_ui_css_handler))
+            .layer(Extension(document))
+    }
+
+    pub async fn openapi_handler(Extension(document): Extension<Arc<Value>>) -> Json<Value> {
+        Json(document.as_ref().clone())
+    }
+
+    pub async fn swagger_ui_handler() -> Html<&'static str> {
+        Html(include_str!("docs/swagger_ui.html"))
+    }
+
+    pub async fn swagger_ui_bundle_handler() -> impl IntoResponse {
+        (
+            [(CONTENT_TYPE, "text/javascript"), (CACHE_CONTROL, ASSET_CACHE_CONTROL)],
+            SWAGGER_UI_BUNDLE,
+        )
+    }
+
+    pub async fn swagger_ui_css_handler() -> impl IntoResponse {
+        (
+            [(CONTENT_TYPE, "text/css"), (CACHE_CONTROL, ASSET_CACHE_CONTROL)],
+            SWAGGER_UI_CSS,
+        )
+    }
+}
@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
 use anyhow::Context;
@@ -19,8 +20,10 @@ use crate::routes::app_router::AppRouter;
 use crate::service_register::ServiceRegister;
 use crate::AppState;
 
+pub mod admin_router;
 mod app_router;
 pub mod data_router;
+pub mod docs_router;
 pub mod user_router;
 
 pub struct AppController;
@@ -31,7 +34,7 @@ impl AppController {
         let cors = CorsLayer::new()
             //.allow_origin(Any)
             .allow_credentials(true)
-            .allow_methods([Method::GET, Method::POST])
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
             .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
 
         let trace_layer = ServiceBuilder::new()
@@ -50,7 +53,7 @@ impl AppController {
         let listener = TcpListener::bind(format!("{}:{}", config.api.host, config.api.port))
             .await
             .context("Unable to bind to the specified host and port")?;
-        axum::serve(listener, router)
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .context(format!("Unable to start server at port {}", config.api.port))
     }
@@ -1,14 +1,21 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use axum::routing::{get, post};
+use axum::extract::{ConnectInfo, Path};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{delete, get, patch, post};
 use axum::{middleware, Extension, Json, Router};
 use tracing::info;
 
-use crate::auth::{validate_jwt_token, ValidatedTokenDetails};
-use crate::domain::req_res::{LoginUserRequest, LoginUserResponse};
-use crate::domain::{RegisterUserDto, UserDto};
+use crate::auth::{require_access_token, validate_jwt_token, ValidatedTokenDetails};
+use crate::domain::req_res::{
+    ChangePasswordRequest, CreateApiKeyRequest, EmailRequest, LoginUserRequest, LoginUserResponse, RefreshTokenRequest,
+    ResetPasswordRequest, UpdateProfileRequest, VerifyEmailRequest,
+};
+use crate::domain::{ApiKeyDto, CreatedApiKeyDto, RegisterUserDto, TokensDto, UserDto};
 use crate::errors::ApiResult;
 use crate::service_register::ServiceRegister;
+use crate::services::api_key_service::ApiKeyService;
 use crate::services::user_service::UserService;
 use crate::AppState;
 
@@ -19,13 +26,44 @@ impl UserRouter {
         Router::new()
             .route("/register", post(UserRouter::create_user_handler))
             .route("/login", post(UserRouter::login_user_handler))
+            .route("/refresh", post(UserRouter::refresh_token_handler))
+            .route("/verify-email", post(UserRouter::verify_email_handler))
+            .route("/resend-verification", post(UserRouter::resend_verification_handler))
+            .route("/forgot-password", post(UserRouter::forgot_password_handler))
+            .route("/reset-password", post(UserRouter::reset_password_handler))
             .route(
                 "/me",
                 get(UserRouter::get_me_handler)
-                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token))
-                    .with_state(app_state),
+                    .merge(patch(UserRouter::update_me_handler).route_layer(middleware::from_fn(require_access_token)))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
             )
+            .route(
+                "/me/password",
+                post(UserRouter::change_password_handler)
+                    .route_layer(middleware::from_fn(require_access_token))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
+            )
+            .route(
+                "/logout",
+                post(UserRouter::logout_handler)
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
+            )
+            .route(
+                "/api-keys",
+                get(UserRouter::list_api_keys_handler)
+                    .post(UserRouter::create_api_key_handler)
+                    .route_layer(middleware::from_fn(require_access_token))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
+            )
+            .route(
+                "/api-keys/:key_id",
+                delete(UserRouter::revoke_api_key_handler)
+                    .route_layer(middleware::from_fn(require_access_token))
+                    .route_layer(middleware::from_fn_with_state(app_state.clone(), validate_jwt_token)),
+            )
+            .with_state(app_state)
             .layer(Extension(service_register.user_service))
+            .layer(Extension(service_register.api_key_service))
     }
 
     pub async fn create_user_handler(
@@ -39,13 +77,72 @@ impl UserRouter {
 
     pub async fn login_user_handler(
         Extension(user_service): Extension<Arc<UserService>>,
+        connect_info: Option<ConnectInfo<SocketAddr>>,
+        headers: HeaderMap,
         Json(request): Json<LoginUserRequest>,
     ) -> ApiResult<Json<LoginUserResponse>> {
         info!("User logging in");
-        let user = user_service.login_user_handler(request.user).await?;
+        let client = user_service
+            .login_throttle
+            .client_address(&headers, connect_info.map(|ConnectInfo(peer)| peer));
+        let user = user_service.login_user_handler(request.user, client).await?;
         Ok(Json(LoginUserResponse { user }))
     }
 
+    pub async fn refresh_token_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Json(request): Json<RefreshTokenRequest>,
+    ) -> ApiResult<Json<TokensDto>> {
+        info!("Refreshing tokens");
+        let tokens = user_service.refresh_token_handler(&request.refresh_token).await?;
+        Ok(Json(tokens))
+    }
+
+    pub async fn verify_email_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Json(request): Json<VerifyEmailRequest>,
+    ) -> ApiResult<StatusCode> {
+        info!("Verifying an email address");
+        user_service.verify_email_handler(&request.token).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn resend_verification_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Json(request): Json<EmailRequest>,
+    ) -> ApiResult<StatusCode> {
+        info!("Resending the verification mail of {}", request.email);
+        user_service.resend_verification_handler(&request.email).await?;
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    pub async fn forgot_password_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Json(request): Json<EmailRequest>,
+    ) -> ApiResult<StatusCode> {
+        info!("Password reset asked for {}", request.email);
+        user_service.forgot_password_handler(&request.email).await?;
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    pub async fn reset_password_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Json(request): Json<ResetPasswordRequest>,
+    ) -> ApiResult<StatusCode> {
+        info!("Resetting a password");
+        user_service.reset_password_handler(request).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn logout_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+    ) -> ApiResult<StatusCode> {
+        info!("Logging out user: {:?}", validated_token.user_id);
+        user_service.logout_handler(&validated_token).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
     pub async fn get_me_handler(
         Extension(user_service): Extension<Arc<UserService>>,
         Extension(validated_token): Extension<ValidatedTokenDetails>,
@@ -54,4 +151,64 @@ impl UserRouter {
         let user = user_service.get_user(&validated_token.user_id).await?;
         Ok(Json(user))
     }
+
+    pub async fn update_me_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Json(request): Json<UpdateProfileRequest>,
+    ) -> ApiResult<Json<UserDto>> {
+        info!("Updating the profile of user: {:?}", validated_token.user_id);
+        let user = user_service
+            .update_profile_handler(&validated_token.user_id, request)
+            .await?;
+        Ok(Json(user))
+    }
+
+    pub async fn change_password_handler(
+        Extension(user_service): Extension<Arc<UserService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Json(request): Json<ChangePasswordRequest>,
+    ) -> ApiResult<StatusCode> {
+        info!("Changing the password of user: {:?}", validated_token.user_id);
+        user_service
+            .change_password_handler(&validated_token.user_id, request)
+            .await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn create_api_key_handler(
+        Extension(api_key_service): Extension<Arc<ApiKeyService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Json(request): Json<CreateApiKeyRequest>,
+    ) -> ApiResult<(StatusCode, Json<CreatedApiKeyDto>)> {
+        info!(
+            "Creating API key {} for user: {:?}",
+            request.name, validated_token.user_id
+        );
+        let api_key = api_key_service
+            .create_api_key(&validated_token.user_id, request)
+            .await?;
+        Ok((StatusCode::CREATED, Json(api_key)))
+    }
+
+    pub async fn list_api_keys_handler(
+        Extension(api_key_service): Extension<Arc<ApiKeyService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+    ) -> ApiResult<Json<Vec<ApiKeyDto>>> {
+        info!("Listing API keys of user: {:?}", validated_token.user_id);
+        let api_keys = api_key_service.list_api_keys(&validated_token.user_id).await?;
+        Ok(Json(api_keys))
+    }
+
+    pub async fn revoke_api_key_handler(
+        Extension(api_key_service): Extension<Arc<ApiKeyService>>,
+        Extension(validated_token): Extension<ValidatedTokenDetails>,
+        Path(key_id): Path<String>,
+    ) -> ApiResult<StatusCode> {
+        info!("Revoking API key {} of user: {:?}", key_id, validated_token.user_id);
+        api_key_service
+            .revoke_api_key(&validated_token.user_id, &key_id)
+            .await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
 }
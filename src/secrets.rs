@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+use tracing::info;
+
+use crate::config::VaultConfig;
+
+/// Reads the string values of a vault secret. Configuration is loaded synchronously, from async `main` as well as from
+/// plain tests, so the request runs on a thread and runtime of its own.
+pub fn read_vault_secret(vault: &VaultConfig) -> anyhow::Result<HashMap<String, String>> {
+    let vault = vault.clone();
+    thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(fetch_secret(&vault))
+    })
+    .join()
+    .map_err(|_| anyhow!("The vault request panicked"))?
+}
+
+async fn fetch_secret(vault: &VaultConfig) -> anyhow::Result<HashMap<String, String>> {
+    let token = match &vault.token_file {
+        Some(file) => {
+            fs::read_to_string(file).with_context(|| format!("Unable to read the vault token from {}", file))?
+        }
+        None => std::env::var(&vault.token_env).with_context(|| {
+            format!(
+                "The environment variable {} holding the vault token is not set",
+                vault.token_env
+            )
+        })?,
+    };
+    let url = format!(
+        "{}/v1/{}",
+        vault.address.trim_end_matches('/'),
+        vault.path.trim_start_matches('/')
+    );
+
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_secs(vault.timeout_secs))
+        .build()?
+        .get(&url)
+        .header("X-Vault-Token", token.trim())
+        .send()
+        .await
+        .with_context(|| format!("Unable to reach the vault at {}", vault.address))?;
+    if !response.status().is_success() {
+        bail!("The vault answered {} for {}", response.status(), vault.path);
+    }
+    let body: Value = response
+        .json()
+        .await
+        .with_context(|| format!("The vault secret {} is not JSON", vault.path))?;
+    info!("Read the vault secret {}", vault.path);
+    secret_values(&body).with_context(|| format!("The vault secret {} has no data", vault.path))
+}
+
+/// The values under `data.data` (KV version 2) or else under `data`
+fn secret_values(body: &Value) -> Option<HashMap<String, String>> {
+    let data = body.get("data")?;
+    let data = data.get("data").filter(|data| data.is_object()).unwrap_or(data);
+    Some(
+        data.as_object()?
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use secrecy::ExposeSecret;
+    use serde_json::{json, Value};
+
+    use crate::config::{Credentials, VaultConfig};
+
+    async fn kv2_secret(headers: HeaderMap) -> Json<Value> {
+        assert_eq!(headers["X-Vault-Token"], "test-token");
+        Json(json!({ "data": { "data": { "username": "NEXUS_READER", "password": "from-vault" } } }))
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_read_from_a_vault_stand_in() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/v1/secret/data/nexus/snowflake", get(kv2_secret));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let token_file = std::env::temp_dir().join(format!("nexus-vault-token-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&token_file, "test-token\n").unwrap();
+        let credentials = Credentials {
+            username: Some("INLINE_USER".to_string()),
+            vault: Some(VaultConfig {
+                address,
+                path: "secret/data/nexus/snowflake".to_string(),
+                token_env: "VAULT_TOKEN".to_string(),
+                token_file: Some(token_file.to_string_lossy().into_owned()),
+                username_key: "username".to_string(),
+                password_key: "password".to_string(),
+                timeout_secs: 5,
+            }),
+            ..Default::default()
+        };
+
+        let (username, password) = tokio::task::spawn_blocking(move || credentials.resolve())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(username.as_deref(), Some("INLINE_USER"));
+        assert_eq!(password.unwrap().expose_secret(), "from-vault");
+        std::fs::remove_file(token_file).unwrap();
+    }
+}
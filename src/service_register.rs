@@ -1,12 +1,26 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use axum_odbc::ODBCConnectionManager;
-use tera::Tera;
+use secrecy::ExposeSecret;
 
 use crate::config::AppConfig;
+use crate::datasource;
+use crate::datasource::executor::BlockingExecutor;
+use crate::mailer;
+use crate::repositories::account_token_repository::AccountTokenRepository;
+use crate::repositories::api_key_repository::ApiKeyRepository;
+use crate::repositories::attempt_store::InMemoryAttemptStore;
 use crate::repositories::data_repository::DataRepository;
+use crate::repositories::invite_repository::InviteRepository;
+use crate::repositories::sql_template::SqlTemplates;
+use crate::repositories::token_repository::TokenRepository;
 use crate::repositories::user_repository::UserRepository;
+use crate::services::account_token_service::AccountTokenService;
+use crate::services::api_key_service::ApiKeyService;
 use crate::services::data_service::DataService;
+use crate::services::invite_service::InviteService;
+use crate::services::login_throttle_service::LoginThrottleService;
+use crate::services::oidc_service::OidcService;
 use crate::services::security_service::SecurityService;
 use crate::services::token_service::TokenService;
 use crate::services::user_service::UserService;
@@ -15,21 +29,77 @@ use crate::services::user_service::UserService;
 pub struct ServiceRegister {
     pub user_service: Arc<UserService>,
     pub data_service: Arc<DataService>,
+    pub token_service: Arc<TokenService>,
+    pub api_key_service: Arc<ApiKeyService>,
+    pub invite_service: Arc<InviteService>,
+    pub oidc_service: Option<Arc<OidcService>>,
 }
 
 impl ServiceRegister {
-    pub fn new(config: AppConfig, pool: ODBCConnectionManager, tera: Tera) -> Self {
-        let users_repository = Arc::new(UserRepository::new(pool.clone())); //db is cloned because we would need it for other repositories
-        let security_service = Arc::new(SecurityService::new(Arc::new(config.clone())));
-        let token_service = Arc::new(TokenService::new(config.clone()));
-        let user_service = Arc::new(UserService::new(users_repository, security_service, token_service));
+    pub fn new(config: AppConfig, templates: SqlTemplates) -> anyhow::Result<Self> {
+        let odbc_executor = Arc::new(BlockingExecutor::new("odbc", config.odbc.threads)?);
+        let source = datasource::from_config("database", &config.database, config.db.as_ref(), &odbc_executor)?;
+        let users_repository = Arc::new(UserRepository::new(source.clone())); //source is cloned because we would need it for other repositories
+        let security_service = Arc::new(SecurityService::new(Arc::new(config.clone()))?);
+        let token_repository = Arc::new(TokenRepository::new(source.clone()));
+        let token_service = Arc::new(TokenService::new(config.clone(), token_repository));
+        let api_key_repository = Arc::new(ApiKeyRepository::new(source.clone()));
+        let api_key_service = Arc::new(ApiKeyService::new(
+            config.clone(),
+            api_key_repository,
+            users_repository.clone(),
+            security_service.clone(),
+        ));
+        let mailer = mailer::from_config(config.mailer.as_ref())?;
+        let invite_repository = Arc::new(InviteRepository::new(source.clone()));
+        let invite_service = Arc::new(InviteService::new(
+            config.clone(),
+            invite_repository,
+            security_service.clone(),
+            mailer.clone(),
+        ));
+        let login_throttle = Arc::new(LoginThrottleService::new(
+            config.api.login_throttling.clone(),
+            Arc::new(InMemoryAttemptStore::new()),
+        ));
+        let account_token_repository = Arc::new(AccountTokenRepository::new(source.clone()));
+        let account_token_service = Arc::new(AccountTokenService::new(config.clone(), account_token_repository)?);
+        let user_service = Arc::new(UserService::new(
+            config.clone(),
+            users_repository,
+            security_service,
+            token_service.clone(),
+            login_throttle,
+            account_token_service,
+            invite_service.clone(),
+            mailer,
+        ));
 
-        let data_repository = Arc::new(DataRepository::new(pool.clone(), tera));
-        let data_service = Arc::new(DataService::new(data_repository.clone()));
+        let oidc_service = config.oidc.clone().map(OidcService::new).transpose()?.map(Arc::new);
 
-        Self {
+        let named_sources = datasource::from_configs(&config.datasources, config.db.as_ref(), &odbc_executor)?;
+        let endpoint_sources = config
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| {
+                let source = named_sources.get(endpoint.datasource.as_ref()?)?;
+                Some((endpoint.name.clone(), source.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+        let data_repository = Arc::new(DataRepository::new(source, endpoint_sources, templates));
+        let data_service = Arc::new(DataService::new(
+            data_repository.clone(),
+            &config.endpoints,
+            config.api.masking_secret.expose_secret(),
+        )?);
+
+        Ok(Self {
             user_service,
             data_service,
-        }
+            token_service,
+            api_key_service,
+            invite_service,
+            oidc_service,
+        })
     }
 }
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::errors::{ApiError, ApiResult};
+use crate::repositories::account_token_repository::AccountTokenRepository;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl AccountTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTokenPurpose::VerifyEmail => "verify_email",
+            AccountTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+}
+
+/// Claims of the tokens mailed to users. The email is the address the token was sent to, so that a token stops
+/// verifying an address once the user changed it.
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountTokenClaims {
+    sub: String,
+    jti: String,
+    purpose: AccountTokenPurpose,
+    email: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// The user and the address a consumed token was issued for
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountToken {
+    pub user_id: String,
+    pub email: String,
+}
+
+/// Issues the signed, single-use and expiring tokens of the email verification and password reset flows
+pub struct AccountTokenService {
+    config: AppConfig,
+    account_token_repository: Arc<AccountTokenRepository>,
+}
+
+impl AccountTokenService {
+    pub fn new(config: AppConfig, account_token_repository: Arc<AccountTokenRepository>) -> anyhow::Result<Self> {
+        let secret = config.api.account_token_secret.expose_secret();
+        if secret.is_empty() || EncodingKey::from_base64_secret(secret).is_err() {
+            bail!("api.account_token_secret must be set to a base64 encoded secret");
+        }
+        Ok(Self {
+            config,
+            account_token_repository,
+        })
+    }
+
+    pub fn max_age_mins(&self, purpose: AccountTokenPurpose) -> u64 {
+        match purpose {
+            AccountTokenPurpose::VerifyEmail => self.config.api.email_verification_max_age_mins,
+            AccountTokenPurpose::ResetPassword => self.config.api.password_reset_max_age_mins,
+        }
+    }
+
+    pub async fn issue(&self, user_id: &str, email: &str, purpose: AccountTokenPurpose) -> ApiResult<String> {
+        let now = Utc::now();
+        let claims = AccountTokenClaims {
+            sub: user_id.to_string(),
+            jti: Uuid::new_v4().to_string(),
+            purpose,
+            email: email.to_string(),
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(self.max_age_mins(purpose) as i64)).timestamp(),
+        };
+        let token = sign(self.config.api.account_token_secret.expose_secret(), &claims)?;
+        self.account_token_repository
+            .insert_account_token(&claims.jti, user_id, purpose.as_str(), claims.exp)
+            .await?;
+        Ok(token)
+    }
+
+    /// Checks the token and uses it up
+    pub async fn consume(&self, token: &str, purpose: AccountTokenPurpose) -> ApiResult<AccountToken> {
+        let claims = verify(self.config.api.account_token_secret.expose_secret(), token, purpose)?;
+        if !self
+            .account_token_repository
+            .consume_account_token(&claims.jti, purpose.as_str())
+            .await?
+        {
+            info!(
+                "Rejecting {} token {} that was already used",
+                purpose.as_str(),
+                claims.jti
+            );
+            return Err(invalid_token());
+        }
+        Ok(AccountToken {
+            user_id: claims.sub,
+            email: claims.email,
+        })
+    }
+
+    /// Voids the unused tokens of the user, once one of them served its purpose
+    pub async fn invalidate(&self, user_id: &str, purpose: AccountTokenPurpose) -> ApiResult<()> {
+        Ok(self
+            .account_token_repository
+            .invalidate_account_tokens(user_id, purpose.as_str())
+            .await?)
+    }
+}
+
+fn sign(secret: &str, claims: &AccountTokenClaims) -> ApiResult<String> {
+    let key = EncodingKey::from_base64_secret(secret).map_err(|_e| {
+        ApiError::InternalServerErrorWithContext("Unable to encode using account_token_secret".to_string())
+    })?;
+    encode(&Header::new(Algorithm::HS256), claims, &key)
+        .map_err(|e| ApiError::InternalServerErrorWithContext(format!("Unable to encode token: {e}")))
+}
+
+fn verify(secret: &str, token: &str, purpose: AccountTokenPurpose) -> ApiResult<AccountTokenClaims> {
+    let key = DecodingKey::from_base64_secret(secret).map_err(|_e| {
+        ApiError::InternalServerErrorWithContext("Unable to decode using account_token_secret".to_string())
+    })?;
+    let claims = decode::<AccountTokenClaims>(token, &key, &Validation::new(Algorithm::HS256))
+        .map_err(|_e| invalid_token())?
+        .claims;
+    if claims.purpose != purpose {
+        return Err(invalid_token());
+    }
+    Ok(claims)
+}
+
+fn invalid_token() -> ApiError {
+    ApiError::BadRequest("The token is invalid, was already used or has expired".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::errors::ApiError;
+    use crate::services::account_token_service::{sign, verify, AccountTokenClaims, AccountTokenPurpose};
+
+    const SECRET: &str = "c3VwZXJfc2VjdXJlX2FjY291bnRfdG9rZW5fU0VDUkVU";
+
+    fn token(purpose: AccountTokenPurpose, expires_in: i64) -> String {
+        let now = Utc::now().timestamp();
+        let claims = AccountTokenClaims {
+            sub: "u-1".to_string(),
+            jti: "t-1".to_string(),
+            purpose,
+            email: "alice@example.com".to_string(),
+            iat: now,
+            exp: now + expires_in,
+        };
+        sign(SECRET, &claims).unwrap()
+    }
+
+    #[test]
+    fn test_tokens_only_serve_their_purpose() {
+        let reset = token(AccountTokenPurpose::ResetPassword, 600);
+        let claims = verify(SECRET, &reset, AccountTokenPurpose::ResetPassword).unwrap();
+        assert_eq!(claims.sub, "u-1");
+        assert_eq!(claims.email, "alice@example.com");
+
+        let rejected = |result: Result<_, ApiError>| matches!(result, Err(ApiError::BadRequest(_)));
+        assert!(rejected(verify(SECRET, &reset, AccountTokenPurpose::VerifyEmail)));
+        assert!(rejected(verify(
+            SECRET,
+            &token(AccountTokenPurpose::ResetPassword, -600),
+            AccountTokenPurpose::ResetPassword
+        )));
+        assert!(rejected(verify(
+            "b3RoZXJfc2VjcmV0",
+            &reset,
+            AccountTokenPurpose::ResetPassword
+        )));
+    }
+}
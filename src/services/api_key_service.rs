@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::{Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::ValidatedTokenDetails;
+use crate::config::AppConfig;
+use crate::domain::req_res::CreateApiKeyRequest;
+use crate::domain::{ApiKeyDto, CreatedApiKeyDto};
+use crate::errors::{ApiError, ApiResult};
+use crate::repositories::api_key_repository::{ApiKeyEntity, ApiKeyRepository};
+use crate::repositories::user_repository::UserRepository;
+use crate::services::security_service::SecurityService;
+
+/// Every API key starts with it, so that keys are told apart from JWTs and are easy to spot in leaked text
+pub const API_KEY_PREFIX: &str = "nxk_";
+const SECRET_BYTES: usize = 32;
+
+#[derive(Clone)]
+pub struct ApiKeyService {
+    config: AppConfig,
+    api_key_repository: Arc<ApiKeyRepository>,
+    user_repository: Arc<UserRepository>,
+    security_service: Arc<SecurityService>,
+    cache: Arc<RwLock<HashMap<String, CachedApiKey>>>,
+}
+
+/// An API key as last read from the store, with the roles and attributes of its owner at that time. Like the
+/// revocation status of access tokens, it is read again after `api.revocation_cache_ttl_secs`.
+#[derive(Debug, Clone)]
+struct CachedApiKey {
+    key: ApiKeyEntity,
+    roles: Vec<String>,
+    attributes: BTreeMap<String, String>,
+    checked_at: Instant,
+}
+
+impl ApiKeyService {
+    pub fn new(
+        config: AppConfig,
+        api_key_repository: Arc<ApiKeyRepository>,
+        user_repository: Arc<UserRepository>,
+        security_service: Arc<SecurityService>,
+    ) -> Self {
+        Self {
+            config,
+            api_key_repository,
+            user_repository,
+            security_service,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn create_api_key(&self, user_id: &str, request: CreateApiKeyRequest) -> ApiResult<CreatedApiKeyDto> {
+        let name = request.name.trim().to_string();
+        if name.is_empty() {
+            return Err(ApiError::BadRequest("An API key needs a name".to_string()));
+        }
+        let mut scopes = request.scopes;
+        scopes.sort();
+        scopes.dedup();
+        let endpoints = self
+            .config
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.name.as_str())
+            .collect::<HashSet<_>>();
+        if scopes.is_empty() {
+            return Err(ApiError::BadRequest(
+                "An API key needs the names of the endpoints it may call as scopes".to_string(),
+            ));
+        }
+        if let Some(unknown) = scopes.iter().find(|scope| !endpoints.contains(scope.as_str())) {
+            return Err(ApiError::BadRequest(format!("Unknown endpoint {} in scopes", unknown)));
+        }
+        let max_age_days = self.config.api.api_key_max_age_days;
+        let expires_in_days = request.expires_in_days.unwrap_or(max_age_days);
+        if expires_in_days == 0 || expires_in_days > max_age_days {
+            return Err(ApiError::BadRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                max_age_days
+            )));
+        }
+
+        let key_id = Uuid::new_v4().simple().to_string();
+        let mut secret = [0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+        let secret = hex::encode(secret);
+
+        let now = Utc::now();
+        let key = ApiKeyEntity {
+            key_id: key_id.clone(),
+            user_id: user_id.to_string(),
+            name,
+            scopes,
+            key_hash: self.security_service.hash_secret(&secret),
+            issued_at: now.timestamp(),
+            expires_at: (now + Duration::days(expires_in_days as i64)).timestamp(),
+            revoked: false,
+        };
+        self.api_key_repository.insert_api_key(&key).await?;
+
+        Ok(CreatedApiKeyDto {
+            key: key.into(),
+            api_key: format_api_key(&key_id, &secret),
+        })
+    }
+
+    pub async fn list_api_keys(&self, user_id: &str) -> ApiResult<Vec<ApiKeyDto>> {
+        let keys = self.api_key_repository.list_api_keys(user_id).await?;
+        Ok(keys.into_iter().map(ApiKeyDto::from).collect())
+    }
+
+    pub async fn revoke_api_key(&self, user_id: &str, key_id: &str) -> ApiResult<()> {
+        if !self.api_key_repository.revoke_api_key(user_id, key_id).await? {
+            return Err(ApiError::NotFound("No API key with that id".to_string()));
+        }
+        self.cache.write().unwrap().remove(key_id);
+        Ok(())
+    }
+
+    /// Checks an API key and describes its caller the way an access token would, with the key scopes added
+    pub async fn authenticate(&self, api_key: &str) -> ApiResult<ValidatedTokenDetails> {
+        let invalid = || ApiError::Unauthorized("API key is invalid, revoked or has expired".to_string());
+        let (key_id, secret) = parse_api_key(api_key).ok_or_else(invalid)?;
+
+        let cached = self.cached(key_id);
+        let cached = match cached {
+            Some(cached) => cached,
+            None => self.load(key_id).await?.ok_or_else(invalid)?,
+        };
+        let key = &cached.key;
+        if !self.security_service.verify_secret(&key.key_hash, secret) {
+            warn!("Wrong secret presented for API key {}", key_id);
+            return Err(invalid());
+        }
+        if key.revoked || key.expires_at <= Utc::now().timestamp() {
+            info!("Rejecting revoked or expired API key {}", key_id);
+            return Err(invalid());
+        }
+
+        Ok(ValidatedTokenDetails {
+            token: String::new(),
+            token_uuid: Uuid::parse_str(&key.key_id).map_err(|_e| invalid())?,
+            user_id: key.user_id.clone(),
+            family_id: None,
+            roles: cached.roles,
+            attributes: cached.attributes,
+            scopes: Some(key.scopes.clone()),
+            expires_in: key.expires_at,
+        })
+    }
+
+    fn cached(&self, key_id: &str) -> Option<CachedApiKey> {
+        let ttl = StdDuration::from_secs(self.config.api.revocation_cache_ttl_secs);
+        self.cache
+            .read()
+            .unwrap()
+            .get(key_id)
+            .filter(|cached| cached.checked_at.elapsed() < ttl)
+            .cloned()
+    }
+
+    async fn load(&self, key_id: &str) -> ApiResult<Option<CachedApiKey>> {
+        let Some(key) = self.api_key_repository.get_api_key(key_id).await? else {
+            return Ok(None);
+        };
+        let owner = self.user_repository.get_user_by_id(&key.user_id).await;
+        if owner.map_or(true, |owner| owner.is_disabled()) {
+            info!("Rejecting API key {} of a disabled or deleted user", key_id);
+            return Ok(None);
+        }
+        let cached = CachedApiKey {
+            roles: self.user_repository.get_user_roles(&key.user_id).await?,
+            attributes: self.user_repository.get_user_attributes(&key.user_id).await?,
+            key,
+            checked_at: Instant::now(),
+        };
+
+        let ttl = StdDuration::from_secs(self.config.api.revocation_cache_ttl_secs);
+        let mut cache = self.cache.write().unwrap();
+        cache.retain(|_, cached| cached.checked_at.elapsed() < ttl);
+        cache.insert(key_id.to_string(), cached.clone());
+        Ok(Some(cached))
+    }
+}
+
+pub fn is_api_key(credential: &str) -> bool {
+    credential.starts_with(API_KEY_PREFIX)
+}
+
+fn format_api_key(key_id: &str, secret: &str) -> String {
+    format!("{}{}_{}", API_KEY_PREFIX, key_id, secret)
+}
+
+/// Splits a key into the id it is stored under and its secret
+fn parse_api_key(api_key: &str) -> Option<(&str, &str)> {
+    api_key
+        .strip_prefix(API_KEY_PREFIX)?
+        .split_once('_')
+        .filter(|(key_id, secret)| !key_id.is_empty() && !secret.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::api_key_service::{format_api_key, is_api_key, parse_api_key};
+
+    #[test]
+    fn test_api_key_round_trip() {
+        let api_key = format_api_key("0f8c2d", "5e1a");
+        assert!(is_api_key(&api_key));
+        assert_eq!(parse_api_key(&api_key), Some(("0f8c2d", "5e1a")));
+        assert_eq!(parse_api_key("nxk_0f8c2d"), None);
+        assert_eq!(parse_api_key("eyJhbGciOiJIUzI1NiJ9.e30.sig"), None);
+    }
+}
@@ -1,21 +1,130 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::domain::req_res::DataResponse;
-use crate::errors::ApiResult;
-use crate::repositories::data_repository::DataRepository;
+use anyhow::{bail, Context};
+
+use crate::config::{EndpointConfig, PaginationConfig, PaginationMode};
+use crate::domain::columns::ResponseSchema;
+use crate::domain::identity::AuthContext;
+use crate::domain::pagination::{PageRequest, PAGE_SIZE_PARAM, PAGE_TOKEN_PARAM};
+use crate::domain::params::{ParamViolation, RequestSchema};
+use crate::domain::req_res::DataPage;
+use crate::errors::{ApiError, ApiResult};
+use crate::repositories::data_repository::{DataRepository, RowBatchReceiver};
 
 #[derive(Clone)]
 pub struct DataService {
     pub data_repository: Arc<DataRepository>,
+    endpoints: HashMap<String, EndpointSchema>,
+}
+
+#[derive(Clone)]
+struct EndpointSchema {
+    request: RequestSchema,
+    response: ResponseSchema,
+    pagination: PaginationConfig,
 }
 
 impl DataService {
-    pub fn new(data_repository: Arc<DataRepository>) -> Self {
-        Self { data_repository }
+    pub fn new(
+        data_repository: Arc<DataRepository>,
+        endpoints: &[EndpointConfig],
+        masking_secret: &str,
+    ) -> anyhow::Result<Self> {
+        let mut schemas = HashMap::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            if endpoint.pagination.mode == PaginationMode::Keyset {
+                for key in endpoint.pagination.keyset_columns() {
+                    let masked = endpoint
+                        .response
+                        .iter()
+                        .any(|(name, column)| name.eq_ignore_ascii_case(key) && column.mask.is_some());
+                    if masked {
+                        bail!(
+                            "The keyset column {} of endpoint {} cannot be masked",
+                            key,
+                            endpoint.name
+                        );
+                    }
+                }
+            }
+            let schema = EndpointSchema {
+                request: RequestSchema::new(&endpoint.request)?,
+                response: ResponseSchema::new(&endpoint.response, masking_secret)
+                    .with_context(|| format!("Invalid response of endpoint {}", endpoint.name))?,
+                pagination: endpoint.pagination.clone(),
+            };
+            schemas.insert(endpoint.name.clone(), schema);
+        }
+        Ok(Self {
+            data_repository,
+            endpoints: schemas,
+        })
+    }
+
+    pub async fn extract_page(
+        &self,
+        endpoint: &str,
+        mut params: HashMap<String, String>,
+        auth: &AuthContext,
+    ) -> ApiResult<DataPage> {
+        let schema = self.schema(endpoint)?;
+        let page = PageRequest::from_params(&mut params, &schema.pagination);
+        let params = schema.request.validate(&params);
+
+        let (page, params) = match (page, params) {
+            (Ok(page), Ok(params)) => (page, params),
+            (page, params) => {
+                let mut violations = page.err().unwrap_or_default();
+                violations.extend(params.err().unwrap_or_default());
+                return Err(ApiError::InvalidRequestParameters(violations));
+            }
+        };
+
+        self.data_repository
+            .extract_page(
+                endpoint,
+                params,
+                auth,
+                &schema.response.for_roles(&auth.roles),
+                &schema.pagination,
+                &page,
+            )
+            .await
+    }
+
+    /// Streams the whole result of the query; pagination does not apply to streamed responses.
+    pub async fn stream_results(
+        &self,
+        endpoint: &str,
+        params: HashMap<String, String>,
+        auth: &AuthContext,
+    ) -> ApiResult<RowBatchReceiver> {
+        let schema = self.schema(endpoint)?;
+        let mut violations = [PAGE_SIZE_PARAM, PAGE_TOKEN_PARAM]
+            .into_iter()
+            .filter(|name| params.contains_key(*name))
+            .map(|name| ParamViolation::new(name, "streamed responses are not paged, they hold every row"))
+            .collect::<Vec<_>>();
+        let params = params
+            .into_iter()
+            .filter(|(name, _)| name != PAGE_SIZE_PARAM && name != PAGE_TOKEN_PARAM)
+            .collect();
+        let params = match schema.request.validate(&params) {
+            Ok(params) if violations.is_empty() => params,
+            result => {
+                violations.extend(result.err().unwrap_or_default());
+                return Err(ApiError::InvalidRequestParameters(violations));
+            }
+        };
+        self.data_repository
+            .stream_results(endpoint, params, auth, schema.response.for_roles(&auth.roles))
+            .await
     }
 
-    pub async fn extract_results(&self, params: HashMap<String, String>) -> ApiResult<DataResponse> {
-        self.data_repository.extract_results(params).await
+    fn schema(&self, endpoint: &str) -> ApiResult<&EndpointSchema> {
+        self.endpoints
+            .get(endpoint)
+            .ok_or_else(|| ApiError::NotFound(format!("Unknown endpoint {}", endpoint)))
     }
 }
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::domain::req_res::CreateInviteRequest;
+use crate::domain::{CreatedInviteDto, InviteDto};
+use crate::errors::{ApiError, ApiResult};
+use crate::mailer::{Email, Mailer};
+use crate::repositories::invite_repository::{InviteEntity, InviteRepository};
+use crate::services::security_service::SecurityService;
+
+/// Every invite code starts with it, so that codes are easy to spot in leaked text
+pub const INVITE_CODE_PREFIX: &str = "nxi_";
+const CODE_BYTES: usize = 24;
+
+/// Invitations to register, created by admins
+pub struct InviteService {
+    config: AppConfig,
+    invite_repository: Arc<InviteRepository>,
+    security_service: Arc<SecurityService>,
+    mailer: Arc<dyn Mailer>,
+}
+
+impl InviteService {
+    pub fn new(
+        config: AppConfig,
+        invite_repository: Arc<InviteRepository>,
+        security_service: Arc<SecurityService>,
+        mailer: Arc<dyn Mailer>,
+    ) -> Self {
+        Self {
+            config,
+            invite_repository,
+            security_service,
+            mailer,
+        }
+    }
+
+    /// Creates an invite. One restricted to an email address is mailed there.
+    pub async fn create_invite(&self, admin_id: &str, request: CreateInviteRequest) -> ApiResult<CreatedInviteDto> {
+        let email = request
+            .email
+            .map(|email| email.trim().to_string())
+            .filter(|email| !email.is_empty());
+        if email.as_ref().is_some_and(|email| !email.contains('@')) {
+            return Err(ApiError::BadRequest(
+                "The invite email is not a valid address".to_string(),
+            ));
+        }
+        let mut roles = request
+            .roles
+            .into_iter()
+            .map(|role| role.trim().to_string())
+            .filter(|role| !role.is_empty())
+            .collect::<Vec<_>>();
+        roles.sort();
+        roles.dedup();
+        let max_age_days = self.config.api.invite_max_age_days;
+        let expires_in_days = request.expires_in_days.unwrap_or(max_age_days);
+        if expires_in_days == 0 || expires_in_days > max_age_days {
+            return Err(ApiError::BadRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                max_age_days
+            )));
+        }
+
+        let code = new_invite_code();
+        let now = Utc::now();
+        let invite = InviteEntity {
+            invite_id: Uuid::new_v4().simple().to_string(),
+            code_hash: self.security_service.hash_secret(&code),
+            created_by: admin_id.to_string(),
+            email,
+            roles,
+            issued_at: now.timestamp(),
+            expires_at: (now + Duration::days(expires_in_days as i64)).timestamp(),
+            used_by: None,
+        };
+        self.invite_repository.insert_invite(&invite).await?;
+        if let Some(email) = &invite.email {
+            self.mail_invite(email, &code, expires_in_days).await;
+        }
+
+        Ok(CreatedInviteDto {
+            invite: invite.into(),
+            invite_code: code,
+        })
+    }
+
+    pub async fn list_invites(&self) -> ApiResult<Vec<InviteDto>> {
+        let invites = self.invite_repository.list_invites().await?;
+        Ok(invites.into_iter().map(InviteDto::from).collect())
+    }
+
+    pub async fn delete_invite(&self, invite_id: &str) -> ApiResult<()> {
+        if !self.invite_repository.delete_invite(invite_id).await? {
+            return Err(ApiError::NotFound("No unused invite with that id".to_string()));
+        }
+        Ok(())
+    }
+
+    /// The invite of the code, if `email` can still register with it. Nothing is used up until `redeem`.
+    pub async fn find_redeemable(&self, code: &str, email: &str) -> ApiResult<InviteEntity> {
+        let code_hash = self.security_service.hash_secret(code.trim());
+        let invite = self
+            .invite_repository
+            .get_invite_by_code_hash(&code_hash)
+            .await?
+            .filter(|invite| invite.used_by.is_none() && invite.expires_at > Utc::now().timestamp())
+            .ok_or_else(invalid_invite)?;
+        if invite
+            .email
+            .as_ref()
+            .is_some_and(|invited| !invited.eq_ignore_ascii_case(email.trim()))
+        {
+            return Err(ApiError::BadRequest(
+                "The invite was issued for another email address".to_string(),
+            ));
+        }
+        Ok(invite)
+    }
+
+    /// Uses up the invite for the registration of `email`. Fails when another registration used it first.
+    pub async fn redeem(&self, invite: &InviteEntity, email: &str) -> ApiResult<()> {
+        let now = Utc::now().timestamp();
+        if !self
+            .invite_repository
+            .consume_invite(&invite.invite_id, email, now)
+            .await?
+        {
+            return Err(invalid_invite());
+        }
+        info!("Invite {} redeemed by {}", invite.invite_id, email);
+        Ok(())
+    }
+
+    async fn mail_invite(&self, email: &str, code: &str, expires_in_days: u32) {
+        let instructions = match self
+            .config
+            .mailer
+            .as_ref()
+            .and_then(|mailer| mailer.link_base_url.as_ref())
+        {
+            Some(base_url) => format!(
+                "open this link:\n\n{}/register?invite_code={}",
+                base_url.trim_end_matches('/'),
+                code
+            ),
+            None => format!("register with this invite code:\n\n{}", code),
+        };
+        let email = Email {
+            to: email.to_string(),
+            subject: "You are invited to nexus".to_string(),
+            body: format!(
+                "Hello,\n\nYou are invited to create a nexus account. To accept, {}\n\nThe invite expires in {} days.\n",
+                instructions, expires_in_days
+            ),
+        };
+        if let Err(e) = self.mailer.send(&email).await {
+            error!("Unable to mail an invite to {}: {}", email.to, e);
+        }
+    }
+}
+
+fn invalid_invite() -> ApiError {
+    ApiError::BadRequest("The invite code is invalid, was already used or has expired".to_string())
+}
+
+fn new_invite_code() -> String {
+    let mut code = [0u8; CODE_BYTES];
+    OsRng.fill_bytes(&mut code);
+    format!("{}{}", INVITE_CODE_PREFIX, hex::encode(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::invite_service::{new_invite_code, CODE_BYTES, INVITE_CODE_PREFIX};
+
+    #[test]
+    fn test_invite_codes_are_prefixed_and_random() {
+        let code = new_invite_code();
+        let random = code.strip_prefix(INVITE_CODE_PREFIX).unwrap();
+        assert_eq!(random.len(), CODE_BYTES * 2);
+        assert!(random.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(code, new_invite_code());
+    }
+}
@@ -0,0 +1,239 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use chrono::Utc;
+use tracing::warn;
+
+use crate::config::LoginThrottlingConfig;
+use crate::errors::{ApiError, ApiResult};
+use crate::repositories::attempt_store::{AttemptRecord, AttemptStore, Reservation};
+
+/// Slows down password guessing. Every failed login is counted against the account and against the client address;
+/// each failure doubles the wait before the next attempt, and too many failures lock the account or the address out.
+pub struct LoginThrottleService {
+    config: LoginThrottlingConfig,
+    store: Arc<dyn AttemptStore>,
+}
+
+impl LoginThrottleService {
+    pub fn new(config: LoginThrottlingConfig, store: Arc<dyn AttemptStore>) -> Self {
+        Self { config, store }
+    }
+
+    /// The address of the client: the first `X-Forwarded-For` entry when nexus runs behind a trusted proxy, the peer
+    /// address otherwise
+    pub fn client_address(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|value| value.trim().parse().ok());
+        match forwarded {
+            Some(address) if self.config.trust_forwarded_for => Some(address),
+            _ => peer.map(|peer| peer.ip()),
+        }
+    }
+
+    /// Lets a login attempt through, or fails with `429` while the account or the address has to wait before trying
+    /// again. The attempt is counted as failed before the password is verified, so that concurrent attempts cannot all
+    /// pass the same check; `succeeded` takes it back.
+    pub async fn begin(&self, email: &str, client: Option<IpAddr>) -> ApiResult<LoginAttempt> {
+        self.begin_at(email, client, Utc::now().timestamp()).await
+    }
+
+    /// A successful login clears the failures of the account. Those of the address are kept, so that an attacker
+    /// cannot reset them by logging into an account of their own; only the attempt itself is taken back.
+    pub async fn succeeded(&self, attempt: LoginAttempt) -> ApiResult<()> {
+        let account = account_key(&attempt.email);
+        for counted in attempt.counted {
+            if counted.key == account {
+                self.store.clear(&counted.key).await?;
+            } else {
+                self.store
+                    .release(&counted.key, counted.previous, counted.record)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The attempt stays counted; this only reports the lockouts it caused
+    pub fn failed(&self, attempt: LoginAttempt) {
+        for counted in attempt.counted {
+            if counted.record.failures == counted.max_failures {
+                warn!(
+                    "{} locked out for {} seconds after {} failed logins",
+                    counted.key, self.config.lockout_secs, counted.record.failures
+                );
+            }
+        }
+    }
+
+    pub async fn unlock_account(&self, email: &str) -> ApiResult<()> {
+        Ok(self.store.clear(&account_key(email)).await?)
+    }
+
+    pub async fn unlock_address(&self, address: IpAddr) -> ApiResult<()> {
+        Ok(self.store.clear(&address_key(address)).await?)
+    }
+
+    async fn begin_at(&self, email: &str, client: Option<IpAddr>, now: i64) -> ApiResult<LoginAttempt> {
+        let mut attempt = LoginAttempt {
+            email: email.to_string(),
+            counted: Vec::new(),
+        };
+        if !self.config.enabled {
+            return Ok(attempt);
+        }
+        let forget_before = now - self.config.failure_window_secs as i64;
+        let mut retry_after = 0;
+        for (key, max_failures) in self.keys(email, client) {
+            let wait = |record: AttemptRecord| self.remaining_wait(&record, max_failures, now);
+            match self.store.reserve(&key, now, forget_before, &wait).await? {
+                Reservation::Counted { previous, counted } => attempt.counted.push(CountedAttempt {
+                    key,
+                    max_failures,
+                    previous,
+                    record: counted,
+                }),
+                Reservation::Wait(secs) => retry_after = retry_after.max(secs),
+            }
+        }
+        if retry_after > 0 {
+            for counted in attempt.counted {
+                self.store
+                    .release(&counted.key, counted.previous, counted.record)
+                    .await?;
+            }
+            return Err(ApiError::TooManyRequests(retry_after));
+        }
+        Ok(attempt)
+    }
+
+    fn keys(&self, email: &str, client: Option<IpAddr>) -> Vec<(String, u32)> {
+        let mut keys = vec![(account_key(email), self.config.max_account_failures)];
+        if let Some(address) = client {
+            keys.push((address_key(address), self.config.max_address_failures));
+        }
+        keys
+    }
+
+    /// Seconds left to wait after the failures of the record: the lockout once there were `max_failures`, a delay
+    /// that doubles with every failure before that
+    fn remaining_wait(&self, record: &AttemptRecord, max_failures: u32, now: i64) -> u64 {
+        if record.failures == 0 || record.last_failure_at < now - self.config.failure_window_secs as i64 {
+            return 0;
+        }
+        let wait = if record.failures >= max_failures {
+            self.config.lockout_secs
+        } else {
+            let factor = 1u64.checked_shl(record.failures - 1).unwrap_or(u64::MAX);
+            self.config
+                .base_delay_secs
+                .saturating_mul(factor)
+                .min(self.config.max_delay_secs)
+        };
+        (record.last_failure_at + wait as i64 - now).max(0) as u64
+    }
+}
+
+/// A login attempt let through by `LoginThrottleService::begin`, counted as failed under each of its keys
+pub struct LoginAttempt {
+    email: String,
+    counted: Vec<CountedAttempt>,
+}
+
+struct CountedAttempt {
+    key: String,
+    max_failures: u32,
+    previous: Option<AttemptRecord>,
+    record: AttemptRecord,
+}
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn address_key(address: IpAddr) -> String {
+    format!("address:{}", address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    use crate::config::LoginThrottlingConfig;
+    use crate::errors::{ApiError, ApiResult};
+    use crate::repositories::attempt_store::InMemoryAttemptStore;
+    use crate::services::login_throttle_service::{LoginAttempt, LoginThrottleService};
+
+    fn throttle() -> LoginThrottleService {
+        LoginThrottleService::new(
+            LoginThrottlingConfig {
+                max_account_failures: 3,
+                base_delay_secs: 2,
+                lockout_secs: 600,
+                ..Default::default()
+            },
+            Arc::new(InMemoryAttemptStore::new()),
+        )
+    }
+
+    fn retry_after(result: ApiResult<LoginAttempt>) -> u64 {
+        match result {
+            Err(ApiError::TooManyRequests(secs)) => secs,
+            Ok(_) => 0,
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failures_back_off_then_lock_out() {
+        let throttle = throttle();
+        let client = Some("10.0.0.7".parse::<IpAddr>().unwrap());
+        let fail = |attempt: ApiResult<LoginAttempt>| throttle.failed(attempt.unwrap());
+
+        let now = 1_700_000_000;
+        fail(throttle.begin_at("Alice@example.com", client, now).await);
+        assert_eq!(retry_after(throttle.begin_at("alice@example.com", None, now).await), 2);
+        fail(throttle.begin_at("alice@example.com", client, now + 2).await);
+        assert_eq!(
+            retry_after(throttle.begin_at("alice@example.com", None, now + 3).await),
+            3
+        );
+        fail(throttle.begin_at("alice@example.com", client, now + 6).await);
+        assert_eq!(
+            retry_after(throttle.begin_at("alice@example.com", None, now + 6).await),
+            600
+        );
+
+        throttle.unlock_account("alice@example.com").await.unwrap();
+        assert_eq!(
+            retry_after(throttle.begin_at("alice@example.com", None, now + 6).await),
+            0
+        );
+        assert_eq!(
+            retry_after(throttle.begin_at("bob@example.com", client, now + 6).await),
+            8
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_attempts_are_counted_before_they_are_verified() {
+        let throttle = throttle();
+        let client = Some("10.0.0.7".parse::<IpAddr>().unwrap());
+        let now = 1_700_000_000;
+
+        let first = throttle.begin_at("alice@example.com", client, now).await.unwrap();
+        assert_eq!(retry_after(throttle.begin_at("alice@example.com", None, now).await), 2);
+        assert_eq!(retry_after(throttle.begin_at("bob@example.com", client, now).await), 2);
+
+        throttle.succeeded(first).await.unwrap();
+        assert_eq!(
+            retry_after(throttle.begin_at("alice@example.com", client, now).await),
+            0
+        );
+    }
+}
@@ -1,4 +1,9 @@
+pub mod account_token_service;
+pub mod api_key_service;
 pub mod data_service;
+pub mod invite_service;
+pub mod login_throttle_service;
+pub mod oidc_service;
 pub(crate) mod security_service;
 pub mod token_service;
 pub mod user_service;
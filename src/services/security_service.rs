@@ -1,25 +1,213 @@
 use crate::config::AppConfig;
+use std::collections::HashSet;
+use std::fs;
 use std::sync::Arc;
 
+use anyhow::Context;
+use argon2::{Variant, Version};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, Semaphore};
+use tokio::task;
+use tracing::info;
+
 use crate::errors::{ApiError, ApiResult};
 
+const SALT_LENGTH: usize = 16;
+
 #[derive(Clone)]
 pub struct SecurityService {
     config: Arc<AppConfig>,
+    breached_passwords: Arc<HashSet<String>>,
+    /// One permit per core: each hash holds `memory_kib` of memory and a core for its whole run
+    hashing_permits: Arc<Semaphore>,
+    /// Hash of a random password with the configured parameters, verified against when the account does not exist
+    unknown_user_hash: Arc<OnceCell<String>>,
 }
 
 impl SecurityService {
-    pub fn new(config: Arc<AppConfig>) -> Self {
-        Self { config }
+    pub fn new(config: Arc<AppConfig>) -> anyhow::Result<Self> {
+        let breached_passwords = match &config.api.password_policy.breached_passwords_file {
+            Some(file) => {
+                let contents = fs::read_to_string(file)
+                    .with_context(|| format!("Unable to read breached passwords from {}", file))?;
+                let passwords = contents
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty())
+                    .collect::<HashSet<_>>();
+                info!("Loaded {} breached passwords from {}", passwords.len(), file);
+                passwords
+            }
+            None => HashSet::new(),
+        };
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        Ok(Self {
+            config,
+            breached_passwords: Arc::new(breached_passwords),
+            hashing_permits: Arc::new(Semaphore::new(cores)),
+            unknown_user_hash: Arc::new(OnceCell::new()),
+        })
+    }
+
+    /// Hashes with Argon2id and a random salt of its own, which the encoded hash carries along with the parameters
+    pub async fn hash_password(&self, password: &str) -> ApiResult<String> {
+        self.hash_password_bytes(password.as_bytes().to_vec()).await
+    }
+
+    async fn hash_password_bytes(&self, password: Vec<u8>) -> ApiResult<String> {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        let config = self.argon2_config();
+        self.run_argon2(move || {
+            argon2::hash_encoded(&password, &salt, &config)
+                .map_err(|_e| ApiError::InternalServerErrorWithContext("Unable to hash password".to_string()))
+        })
+        .await
+    }
+
+    pub async fn verify_password(&self, actual_password_hash: &str, attempted_password: &str) -> ApiResult<bool> {
+        let actual_password_hash = actual_password_hash.to_string();
+        let attempted_password = attempted_password.to_string();
+        self.run_argon2(move || {
+            argon2::verify_encoded(&actual_password_hash, attempted_password.as_bytes())
+                .map_err(|_e| ApiError::InvalidLoginAttempt)
+        })
+        .await
     }
-    pub fn hash_password(&self, password: &str) -> ApiResult<String> {
-        let salt = &self.config.api.password_salt;
-        argon2::hash_encoded(password.as_bytes(), salt.as_bytes(), &argon2::Config::default())
-            .map_err(|_e| ApiError::InternalServerErrorWithContext("Unable to hash password".to_string()))
+
+    /// Verifies the password against a hash made like those of the users, and fails, as a wrong password would
+    pub async fn verify_unknown_password(&self, attempted_password: &str) -> ApiResult<bool> {
+        let hash = self
+            .unknown_user_hash
+            .get_or_try_init(|| {
+                let mut password = [0u8; SALT_LENGTH];
+                OsRng.fill_bytes(&mut password);
+                self.hash_password_bytes(password.to_vec())
+            })
+            .await?;
+        self.verify_password(hash, attempted_password).await?;
+        Ok(false)
+    }
+
+    /// Argon2 takes long enough on purpose to stall the runtime, so it runs on the blocking threads, a core at a time
+    async fn run_argon2<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> ApiResult<T> + Send + 'static,
+    ) -> ApiResult<T> {
+        let _permit = self
+            .hashing_permits
+            .acquire()
+            .await
+            .map_err(|_e| ApiError::InternalServerErrorWithContext("Password hashing is shut down".to_string()))?;
+        task::spawn_blocking(work).await.map_err(anyhow::Error::from)?
+    }
+
+    /// Whether a stored hash was made with other parameters than the configured ones, or with a shared salt
+    pub fn needs_rehash(&self, password_hash: &str) -> bool {
+        let hashing = &self.config.api.password_hashing;
+        let expected_params = format!(
+            "m={},t={},p={}",
+            hashing.memory_kib, hashing.iterations, hashing.parallelism
+        );
+        //$argon2id$v=19$m=65536,t=3,p=1$<salt>$<hash>
+        match password_hash.split('$').collect::<Vec<_>>()[..] {
+            ["", variant, version, params, salt, _hash] => {
+                variant != Variant::Argon2id.as_lowercase_str()
+                    || version != format!("v={}", Version::Version13.as_u32())
+                    || params != expected_params
+                    || salt.len() != base64_len(SALT_LENGTH)
+            }
+            _ => true,
+        }
+    }
+
+    /// Checks a new password against `api.password_policy`
+    pub fn check_password_policy(&self, password: &str) -> ApiResult<()> {
+        let policy = &self.config.api.password_policy;
+        let length = password.chars().count();
+        if length < policy.min_length {
+            return Err(ApiError::BadRequest(format!(
+                "The password must be at least {} characters long",
+                policy.min_length
+            )));
+        }
+        if length > policy.max_length {
+            return Err(ApiError::BadRequest(format!(
+                "The password must be at most {} characters long",
+                policy.max_length
+            )));
+        }
+        if self.breached_passwords.contains(&password.to_lowercase()) {
+            return Err(ApiError::BadRequest(
+                "The password appears in a list of breached passwords".to_string(),
+            ));
+        }
+        Ok(())
     }
 
-    pub fn verify_password(&self, actual_password_hash: &str, attempted_password: &str) -> ApiResult<bool> {
-        argon2::verify_encoded(actual_password_hash, attempted_password.as_bytes())
-            .map_err(|_e| ApiError::InvalidLoginAttempt)
+    fn argon2_config(&self) -> argon2::Config<'static> {
+        let hashing = &self.config.api.password_hashing;
+        argon2::Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: hashing.memory_kib,
+            time_cost: hashing.iterations,
+            lanes: hashing.parallelism,
+            ..argon2::Config::default()
+        }
+    }
+
+    /// Secrets that nexus generates, API keys and invite codes, are long and random, unlike passwords, so a plain
+    /// SHA-256 is enough and keeps the check that runs on every request cheap
+    pub fn hash_secret(&self, secret: &str) -> String {
+        hex::encode(Sha256::digest(secret.as_bytes()))
+    }
+
+    pub fn verify_secret(&self, secret_hash: &str, attempted_secret: &str) -> bool {
+        let attempted_hash = self.hash_secret(attempted_secret);
+        //Compares every byte, so that the time taken does not tell how much of the hash matched
+        secret_hash.len() == attempted_hash.len()
+            && secret_hash
+                .bytes()
+                .zip(attempted_hash.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Length of unpadded base64, as used by the encoded hash
+fn base64_len(bytes: usize) -> usize {
+    (bytes * 4).div_ceil(3)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::AppConfig;
+    use crate::services::security_service::SecurityService;
+
+    #[tokio::test]
+    async fn test_passwords_get_their_own_salt() {
+        let config = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        let security_service = SecurityService::new(Arc::new(config)).unwrap();
+
+        let first = security_service.hash_password("correct horse battery").await.unwrap();
+        let second = security_service.hash_password("correct horse battery").await.unwrap();
+        assert_ne!(first, second);
+        assert!(security_service
+            .verify_password(&first, "correct horse battery")
+            .await
+            .unwrap());
+        assert!(!security_service.needs_rehash(&first));
+
+        //Argon2 defaults with the salt once shared by every user
+        let legacy = "$argon2id$v=19$m=2097152,t=1,p=1$c3VwZXJfc2VjdXJlX3Bhc3N3b3JkX3NhbHQ$\
+                      H8nmw4kmmrmGqGUJ4O5c2Rn5yWpU9ZKbNQoD9b4jT9Y";
+        assert!(security_service.needs_rehash(legacy));
+
+        assert!(security_service.check_password_policy("short").is_err());
+        assert!(security_service.check_password_policy("correct horse battery").is_ok());
     }
 }
@@ -1,14 +1,44 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration as StdDuration, Instant};
+
 use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::config::AppConfig;
 use crate::errors::{ApiError, ApiResult};
+use crate::repositories::token_repository::{RefreshTokenEntity, TokenDetailsEntity, TokenRepository};
+
+/// Cached entries beyond which the expired ones are dropped
+const REVOCATION_CACHE_PRUNE_SIZE: usize = 10_000;
 
 #[derive(Clone)]
 pub struct TokenService {
     config: AppConfig,
+    token_repository: Arc<TokenRepository>,
+    revocation_cache: Arc<RwLock<HashMap<String, CachedTokenStatus>>>,
+}
+
+/// Revocation status of an access token as last read from `token_details`. Revocations made through this instance
+/// update the cache right away; those made through another instance are seen once the entry expires.
+#[derive(Debug, Clone)]
+struct CachedTokenStatus {
+    user_id: String,
+    family_id: String,
+    revoked: bool,
+    checked_at: Instant,
+}
+
+/// Whether a JWT may be used to call the API or only to get new tokens. It is part of the claims, so that the two kinds
+/// cannot be swapped even when they share a secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,6 +48,16 @@ pub struct TokenClaims {
     pub nbf: i64,
     pub sub: String,
     pub token_uuid: String,
+    pub token_type: TokenType,
+    /// The login the token was issued for, shared by all the access and refresh tokens that stem from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_id: Option<String>,
+    /// Access tokens only: the roles of the user when the token was issued
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Access tokens only: the attributes of the user when the token was issued
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,13 +69,34 @@ pub struct TokenDetails {
 }
 
 impl TokenService {
-    pub fn new(config: AppConfig) -> Self {
-        Self { config }
+    pub fn new(config: AppConfig, token_repository: Arc<TokenRepository>) -> Self {
+        Self {
+            config,
+            token_repository,
+            revocation_cache: Arc::new(RwLock::new(HashMap::new())),
+        }
     }
 
-    fn generate_jwt_token(&self, user_id: String, token_secret: &str, max_age: i64) -> ApiResult<TokenDetails> {
+    fn generate_jwt_token(
+        &self,
+        token_type: TokenType,
+        user_id: String,
+        family_id: String,
+        roles: Vec<String>,
+        attributes: BTreeMap<String, String>,
+    ) -> ApiResult<TokenDetails> {
+        let (token_secret, max_age) = match token_type {
+            TokenType::Access => (
+                &self.config.api.access_token_secret,
+                self.config.api.access_token_max_age,
+            ),
+            TokenType::Refresh => (
+                &self.config.api.refresh_token_secret,
+                self.config.api.refresh_token_max_age,
+            ),
+        };
         let now = Utc::now();
-        let expires_in = (now + Duration::minutes(max_age)).timestamp();
+        let expires_in = (now + Duration::minutes(max_age as i64)).timestamp();
         let token_uuid = Uuid::new_v4();
 
         let claims = TokenClaims {
@@ -44,6 +105,10 @@ impl TokenService {
             nbf: now.timestamp(),
             sub: user_id.clone(),
             token_uuid: token_uuid.to_string(),
+            token_type,
+            family_id: Some(family_id),
+            roles,
+            attributes,
         };
 
         let token = encode(
@@ -65,23 +130,224 @@ impl TokenService {
         Ok(token_details)
     }
 
-    pub async fn generate_access_token(&self, user_id: String) -> ApiResult<String> {
-        let token_details = self.generate_jwt_token(
-            user_id,
-            &self.config.api.access_token_secret,
-            self.config.api.access_token_max_age as i64,
-        )?;
+    /// Starts a login: every token issued from it shares the returned family id
+    pub fn new_token_family(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+
+    /// Issues an access token and records it in `token_details`, so that it can be revoked before it expires
+    pub async fn generate_access_token(
+        &self,
+        user_id: String,
+        family_id: String,
+        roles: Vec<String>,
+        attributes: BTreeMap<String, String>,
+    ) -> ApiResult<String> {
+        let token_details =
+            self.generate_jwt_token(TokenType::Access, user_id, family_id.clone(), roles, attributes)?;
+
+        self.token_repository
+            .insert_token_details(&TokenDetailsEntity {
+                token_uuid: token_details.token_uuid.to_string(),
+                user_id: token_details.user_id,
+                family_id,
+                expires_in: token_details.expires_in,
+                max_age: self.config.api.access_token_max_age as i64,
+                revoked: false,
+            })
+            .await?;
 
         Ok(token_details.token)
     }
 
-    /*pub async fn generate_refresh_token(&self, user_id: Uuid) -> ApiResult<String> {
-        let token_details = self.generate_jwt_token(
-            user_id,
-            &self.config.refresh_token_secret,
-            self.config.refresh_token_max_age,
-        )?;
+    /// Issues a refresh token and records it. A rotated token stays in the family of the token it replaces.
+    pub async fn generate_refresh_token(&self, user_id: String, family_id: String) -> ApiResult<String> {
+        let token_details =
+            self.generate_jwt_token(TokenType::Refresh, user_id, family_id.clone(), vec![], BTreeMap::new())?;
+
+        self.token_repository
+            .insert_refresh_token(&RefreshTokenEntity {
+                token_uuid: token_details.token_uuid.to_string(),
+                family_id,
+                user_id: token_details.user_id,
+                expires_at: token_details.expires_in,
+                used: false,
+                revoked: false,
+            })
+            .await?;
 
         Ok(token_details.token)
-    }*/
+    }
+
+    /// Validates a refresh token and marks it as used. Every refresh token can be used once: presenting one that was
+    /// already rotated means it leaked, so its whole family is revoked and the user has to log in again.
+    pub async fn consume_refresh_token(&self, refresh_token: &str) -> ApiResult<RefreshTokenEntity> {
+        let claims = self.decode_refresh_token(refresh_token)?;
+        let invalid = || ApiError::Unauthorized("refresh token is invalid or has expired".to_string());
+
+        let stored = self
+            .token_repository
+            .get_refresh_token(&claims.token_uuid)
+            .await?
+            .ok_or_else(invalid)?;
+        if stored.revoked {
+            return Err(invalid());
+        }
+        if stored.used || !self.token_repository.consume_refresh_token(&stored.token_uuid).await? {
+            warn!(
+                "Refresh token {} of user {} was reused, revoking its family",
+                stored.token_uuid, stored.user_id
+            );
+            self.revoke_session(&stored.family_id).await?;
+            return Err(invalid());
+        }
+        Ok(stored)
+    }
+
+    /// Whether an access token was revoked, or was never issued by this service
+    pub async fn is_revoked(&self, token_uuid: &str) -> ApiResult<bool> {
+        let ttl = StdDuration::from_secs(self.config.api.revocation_cache_ttl_secs);
+        if let Some(status) = self.revocation_cache.read().unwrap().get(token_uuid) {
+            if status.revoked || status.checked_at.elapsed() < ttl {
+                return Ok(status.revoked);
+            }
+        }
+
+        let Some(stored) = self.token_repository.get_token_details(token_uuid).await? else {
+            return Ok(true);
+        };
+        let revoked = stored.revoked;
+        let mut cache = self.revocation_cache.write().unwrap();
+        if cache.len() >= REVOCATION_CACHE_PRUNE_SIZE {
+            cache.retain(|_, status| status.checked_at.elapsed() < ttl);
+        }
+        cache.insert(
+            stored.token_uuid,
+            CachedTokenStatus {
+                user_id: stored.user_id,
+                family_id: stored.family_id,
+                revoked,
+                checked_at: Instant::now(),
+            },
+        );
+        Ok(revoked)
+    }
+
+    /// Logs out of one login: its access tokens and its refresh tokens stop working
+    pub async fn revoke_session(&self, family_id: &str) -> ApiResult<()> {
+        self.token_repository.revoke_session(family_id).await?;
+        self.mark_revoked(|status| status.family_id == family_id);
+        Ok(())
+    }
+
+    /// Logs the user out of every login
+    pub async fn revoke_user_sessions(&self, user_id: &str) -> ApiResult<()> {
+        self.token_repository.revoke_user_tokens(user_id).await?;
+        self.mark_revoked(|status| status.user_id == user_id);
+        Ok(())
+    }
+
+    fn mark_revoked(&self, matches: impl Fn(&CachedTokenStatus) -> bool) {
+        let mut cache = self.revocation_cache.write().unwrap();
+        for status in cache.values_mut().filter(|status| matches(status)) {
+            status.revoked = true;
+        }
+    }
+
+    fn decode_refresh_token(&self, token: &str) -> ApiResult<TokenClaims> {
+        let key = DecodingKey::from_base64_secret(&self.config.api.refresh_token_secret).map_err(|_e| {
+            ApiError::InternalServerErrorWithContext("Unable to decode using refresh_token_secret".to_string())
+        })?;
+        let claims = decode::<TokenClaims>(token, &key, &Validation::new(Algorithm::default()))
+            .map_err(|_e| ApiError::Unauthorized("refresh token is invalid or has expired".to_string()))?
+            .claims;
+        if claims.token_type != TokenType::Refresh || claims.family_id.is_none() {
+            return Err(ApiError::Unauthorized("not a refresh token".to_string()));
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    use crate::config::AppConfig;
+    use crate::datasource::sqlite::test_source;
+    use crate::repositories::token_repository::TokenRepository;
+    use crate::services::token_service::{TokenClaims, TokenService};
+
+    fn token_service(config: AppConfig) -> TokenService {
+        TokenService::new(config, Arc::new(TokenRepository::new(test_source())))
+    }
+
+    /// The `token_uuid` claim, read without checking the signature
+    fn token_uuid(token: &str) -> String {
+        let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1).unwrap()).unwrap();
+        serde_json::from_slice::<TokenClaims>(&payload).unwrap().token_uuid
+    }
+
+    fn test_config() -> AppConfig {
+        AppConfig::get_configuration("tests/test_nexus.yaml").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reusing_a_rotated_refresh_token_revokes_the_login() {
+        let tokens = token_service(test_config());
+        let family = tokens.new_token_family();
+        let access_token = tokens
+            .generate_access_token("u-1".to_string(), family.clone(), vec![], BTreeMap::new())
+            .await
+            .unwrap();
+        let access_uuid = token_uuid(&access_token);
+        let refresh_token = tokens
+            .generate_refresh_token("u-1".to_string(), family.clone())
+            .await
+            .unwrap();
+
+        let consumed = tokens.consume_refresh_token(&refresh_token).await.unwrap();
+        assert_eq!(consumed.family_id, family);
+        assert!(!tokens.is_revoked(&access_uuid).await.unwrap());
+        let rotated = tokens
+            .generate_refresh_token("u-1".to_string(), consumed.family_id)
+            .await
+            .unwrap();
+
+        assert!(tokens.consume_refresh_token(&refresh_token).await.is_err());
+        assert!(tokens.is_revoked(&access_uuid).await.unwrap());
+        assert!(tokens.consume_refresh_token(&rotated).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_logged_out_access_tokens_are_revoked() {
+        let tokens = token_service(test_config());
+        let family = tokens.new_token_family();
+        let access_token = tokens
+            .generate_access_token("u-1".to_string(), family.clone(), vec![], BTreeMap::new())
+            .await
+            .unwrap();
+        let access_uuid = token_uuid(&access_token);
+
+        assert!(!tokens.is_revoked(&access_uuid).await.unwrap());
+        tokens.revoke_session(&family).await.unwrap();
+        assert!(tokens.is_revoked(&access_uuid).await.unwrap());
+        assert!(tokens.is_revoked("never-issued").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_an_access_token_is_not_a_refresh_token() {
+        let mut config = test_config();
+        config.api.refresh_token_secret = config.api.access_token_secret.clone();
+        let tokens = token_service(config);
+        let access_token = tokens
+            .generate_access_token("u-1".to_string(), tokens.new_token_family(), vec![], BTreeMap::new())
+            .await
+            .unwrap();
+
+        assert!(tokens.consume_refresh_token(&access_token).await.is_err());
+    }
 }
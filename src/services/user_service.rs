@@ -1,79 +1,848 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 
-use tracing::error;
+use tracing::{error, info, warn};
 
-use crate::domain::{LoginUserDto, RegisterUserDto, UserDto};
+use crate::auth::ValidatedTokenDetails;
+use crate::config::{AppConfig, RegistrationMode};
+use crate::domain::req_res::{ChangePasswordRequest, ListUsersQuery, ResetPasswordRequest, UpdateProfileRequest};
+use crate::domain::{LoginUserDto, RegisterUserDto, TokensDto, UserDto};
 use crate::errors::{ApiError, ApiResult};
-use crate::repositories::user_repository::UserRepository;
+use crate::mailer::{Email, Mailer};
+use crate::repositories::user_repository::{UserEntity, UserRepository};
+use crate::services::account_token_service::{AccountTokenPurpose, AccountTokenService};
+use crate::services::invite_service::InviteService;
+use crate::services::login_throttle_service::LoginThrottleService;
 use crate::services::security_service::SecurityService;
 use crate::services::token_service::TokenService;
 
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+const ADMIN_ROLE: &str = "admin";
+/// Sizes of the `name` and `email` columns of `nexus_users`
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_EMAIL_LENGTH: usize = 255;
+
 #[derive(Clone)]
 pub struct UserService {
+    pub config: AppConfig,
     pub user_repository: Arc<UserRepository>,
     pub security_service: Arc<SecurityService>,
     pub token_service: Arc<TokenService>,
+    pub login_throttle: Arc<LoginThrottleService>,
+    pub account_token_service: Arc<AccountTokenService>,
+    pub invite_service: Arc<InviteService>,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 impl UserService {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        config: AppConfig,
         user_repository: Arc<UserRepository>,
         security_service: Arc<SecurityService>,
         token_service: Arc<TokenService>,
+        login_throttle: Arc<LoginThrottleService>,
+        account_token_service: Arc<AccountTokenService>,
+        invite_service: Arc<InviteService>,
+        mailer: Arc<dyn Mailer>,
     ) -> Self {
         Self {
+            config,
             user_repository,
             security_service,
             token_service,
+            login_throttle,
+            account_token_service,
+            invite_service,
+            mailer,
         }
     }
+
+    /// Registers a user, as `api.registration` allows, and mails it a link to verify its email address. An invite
+    /// gives its roles to the new user, and one restricted to the address of the user also verifies it. The invite is
+    /// used up last, once the user is complete; a user whose invite was taken meanwhile is deleted again.
     pub async fn create_user_handler(&self, register_user: RegisterUserDto) -> ApiResult<String> {
-        let existing_user = self.user_repository.does_user_exist(&register_user.email).await;
+        let invite_code = register_user
+            .invite_code
+            .as_deref()
+            .map(str::trim)
+            .filter(|code| !code.is_empty());
+        match (self.config.api.registration, invite_code) {
+            (RegistrationMode::Closed, _) => {
+                info!(
+                    "Refusing the registration of {}: registration is closed",
+                    register_user.email
+                );
+                return Err(ApiError::Forbidden);
+            }
+            (RegistrationMode::InviteOnly, None) => {
+                return Err(ApiError::BadRequest(
+                    "An invite code is required to register".to_string(),
+                ));
+            }
+            _ => {}
+        }
 
-        if let Err(_e) = existing_user {
+        if self.user_repository.does_user_exist(&register_user.email).await? {
             error!("user with email {} already exists", &register_user.email);
             return Err(ApiError::ObjectConflict(
                 "User with that email id already exists".to_string(),
             ));
         }
 
-        let hashed_password = self.security_service.hash_password(&register_user.password)?;
-        Ok(self.user_repository.create_user(register_user, hashed_password).await?)
+        self.security_service.check_password_policy(&register_user.password)?;
+        let invite = match invite_code {
+            Some(code) => Some(self.invite_service.find_redeemable(code, &register_user.email).await?),
+            None => None,
+        };
+        let hashed_password = self.security_service.hash_password(&register_user.password).await?;
+        let email = register_user.email.clone();
+        let registered = self.user_repository.create_user(register_user, hashed_password).await?;
+        let user = self.user_repository.get_user_by_email(&email).await?;
+        let Some(invite) = invite else {
+            self.send_verification_email(&user).await;
+            return Ok(registered);
+        };
+        let invited = async {
+            if !invite.roles.is_empty() {
+                self.user_repository.set_user_roles(&user.id, &invite.roles).await?;
+            }
+            if invite.email.is_some() {
+                self.user_repository.set_email_verified(&user.id, true).await?;
+            }
+            self.invite_service.redeem(&invite, &user.email).await
+        };
+        if let Err(e) = invited.await {
+            warn!("Deleting user {} as its invite could not be redeemed", user.id);
+            self.user_repository.delete_user(&user.id).await?;
+            return Err(e);
+        }
+        if invite.email.is_none() {
+            self.send_verification_email(&user).await;
+        }
+        Ok(registered)
+    }
+
+    /// Creates a user holding the admin role, whatever `api.registration` says. Its email counts as verified.
+    pub async fn create_admin_handler(&self, name: &str, email: &str, password: &str) -> ApiResult<UserDto> {
+        let name = validate_name(name)?;
+        let email = validate_email(email)?;
+        if self.user_repository.get_user_by_email(&email).await.is_ok() {
+            return Err(ApiError::ObjectConflict(
+                "User with that email id already exists".to_string(),
+            ));
+        }
+        self.security_service.check_password_policy(password)?;
+        let hashed_password = self.security_service.hash_password(password).await?;
+        let register_user = RegisterUserDto {
+            name,
+            email: email.clone(),
+            password: password.to_string(),
+            invite_code: None,
+        };
+        self.user_repository.create_user(register_user, hashed_password).await?;
+        let user = self.user_repository.get_user_by_email(&email).await?;
+        self.user_repository.set_email_verified(&user.id, true).await?;
+        self.user_repository
+            .set_user_roles(&user.id, &[ADMIN_ROLE.to_string()])
+            .await?;
+        info!("Admin {} created", user.id);
+        self.get_user(&user.id).await
+    }
+
+    /// Marks the address the token was mailed to as verified, unless the user changed its email since
+    pub async fn verify_email_handler(&self, token: &str) -> ApiResult<()> {
+        let token = self
+            .account_token_service
+            .consume(token, AccountTokenPurpose::VerifyEmail)
+            .await?;
+        let user = self
+            .user_repository
+            .get_user_by_id(&token.user_id)
+            .await
+            .map_err(|_e| ApiError::NotFound("User with that userid does not exist".to_string()))?;
+        if user.email != token.email {
+            return Err(ApiError::BadRequest(
+                "The token was sent to an address the user no longer has".to_string(),
+            ));
+        }
+        self.user_repository.set_email_verified(&user.id, true).await?;
+        self.account_token_service
+            .invalidate(&user.id, AccountTokenPurpose::VerifyEmail)
+            .await?;
+        info!("Email address of user {} verified", user.id);
+        Ok(())
+    }
+
+    /// Mails a new verification link. Nothing tells the caller whether the address belongs to a user.
+    pub async fn resend_verification_handler(&self, email: &str) -> ApiResult<()> {
+        match self.user_repository.get_user_by_email(email).await {
+            Ok(user) if user.email_verified_at.is_none() => self.send_verification_email(&user).await,
+            _ => info!("No unverified user with email {}, no verification mail sent", email),
+        }
+        Ok(())
+    }
+
+    /// Mails a password reset link. Nothing tells the caller whether the address belongs to a user.
+    pub async fn forgot_password_handler(&self, email: &str) -> ApiResult<()> {
+        let user = match self.user_repository.get_user_by_email(email).await {
+            Ok(user) if !user.is_disabled() => user,
+            _ => {
+                info!("No active user with email {}, no password reset mail sent", email);
+                return Ok(());
+            }
+        };
+        let purpose = AccountTokenPurpose::ResetPassword;
+        let result = match self.account_token_service.issue(&user.id, &user.email, purpose).await {
+            Ok(token) => {
+                let body = format!(
+                    "Hello {},\n\nSomeone asked to reset the password of your nexus account. To choose a new \
+                     password, {}\n\nIt expires in {} minutes. If you did not ask for it, ignore this mail: your \
+                     password stays as it is.\n",
+                    user.name,
+                    self.token_instructions("reset-password", "/api/users/reset-password", &token),
+                    self.account_token_service.max_age_mins(purpose)
+                );
+                self.send(&user, "Reset your nexus password", body).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Unable to mail a password reset link to user {}: {}", user.id, e);
+        }
+        Ok(())
+    }
+
+    /// Sets a new password with a token from a password reset mail. Every login of the user is revoked and its
+    /// lockout lifted; the mail also proves that the user reads that address.
+    pub async fn reset_password_handler(&self, request: ResetPasswordRequest) -> ApiResult<()> {
+        self.security_service.check_password_policy(&request.new_password)?;
+        let token = self
+            .account_token_service
+            .consume(&request.token, AccountTokenPurpose::ResetPassword)
+            .await?;
+        let user = self
+            .user_repository
+            .get_user_by_id(&token.user_id)
+            .await
+            .map_err(|_e| ApiError::NotFound("User with that userid does not exist".to_string()))?;
+        let hashed_password = self.security_service.hash_password(&request.new_password).await?;
+        self.user_repository.update_password(&user.id, &hashed_password).await?;
+        self.account_token_service
+            .invalidate(&user.id, AccountTokenPurpose::ResetPassword)
+            .await?;
+        self.token_service.revoke_user_sessions(&user.id).await?;
+        self.login_throttle.unlock_account(&user.email).await?;
+        if user.email == token.email {
+            self.user_repository.set_email_verified(&user.id, true).await?;
+        }
+        info!("Password of user {} reset", user.id);
+        Ok(())
+    }
+
+    /// Mails a verification link to the user. A failure is logged only: the user can ask for another mail.
+    async fn send_verification_email(&self, user: &UserEntity) {
+        let purpose = AccountTokenPurpose::VerifyEmail;
+        let result = match self.account_token_service.issue(&user.id, &user.email, purpose).await {
+            Ok(token) => {
+                let body = format!(
+                    "Hello {},\n\nTo confirm that {} is your email address, {}\n\nIt expires in {} minutes. If you \
+                     did not register with nexus, ignore this mail.\n",
+                    user.name,
+                    user.email,
+                    self.token_instructions("verify-email", "/api/users/verify-email", &token),
+                    self.account_token_service.max_age_mins(purpose)
+                );
+                self.send(user, "Verify your email address", body).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Unable to mail a verification link to user {}: {}", user.id, e);
+        }
+    }
+
+    async fn send(&self, user: &UserEntity, subject: &str, body: String) -> ApiResult<()> {
+        let email = Email {
+            to: user.email.clone(),
+            subject: subject.to_string(),
+            body,
+        };
+        Ok(self.mailer.send(&email).await?)
+    }
+
+    /// A link to the page of `mailer.link_base_url` that takes the token, or the bare token to post to the API
+    fn token_instructions(&self, page: &str, api_path: &str, token: &str) -> String {
+        match self
+            .config
+            .mailer
+            .as_ref()
+            .and_then(|mailer| mailer.link_base_url.as_ref())
+        {
+            Some(base_url) => format!(
+                "open this link:\n\n{}/{}?token={}",
+                base_url.trim_end_matches('/'),
+                page,
+                token
+            ),
+            None => format!("post this token to {}:\n\n{}", api_path, token),
+        }
     }
 
-    pub async fn login_user_handler(&self, login_user: LoginUserDto) -> ApiResult<UserDto> {
+    /// Logs a user in. Failed attempts are counted against the account and the client address, which have to wait
+    /// longer after each one and are locked out after too many.
+    pub async fn login_user_handler(&self, login_user: LoginUserDto, client: Option<IpAddr>) -> ApiResult<UserDto> {
+        let attempt = self.login_throttle.begin(&login_user.email, client).await?;
         let user = self.user_repository.get_user_by_email(&login_user.email).await;
 
-        if let Err(_e) = user {
+        let Ok(user) = user else {
             error!("User with email does not exist: {}", &login_user.email);
-            return Err(ApiError::NotFound("User with that email does not exist".to_string()));
-        }
+            //Takes as long as a wrong password, so that the time taken does not tell whether the account exists
+            self.security_service
+                .verify_unknown_password(&login_user.password)
+                .await?;
+            self.login_throttle.failed(attempt);
+            return Err(ApiError::InvalidLoginAttempt);
+        };
 
-        let user = user.unwrap();
         let is_valid = self
             .security_service
-            .verify_password(&user.password, &login_user.password)?;
+            .verify_password(&user.password, &login_user.password)
+            .await?;
 
         if !is_valid {
+            self.login_throttle.failed(attempt);
             return Err(ApiError::InvalidLoginAttempt);
         }
+        self.login_throttle.succeeded(attempt).await?;
+        if user.is_disabled() {
+            info!("Refusing the login of disabled user {}", user.id);
+            return Err(ApiError::Unauthorized("This account is disabled".to_string()));
+        }
+        if self.config.api.require_email_verification && user.email_verified_at.is_none() {
+            info!("Refusing the login of user {} with an unverified email", user.id);
+            return Err(ApiError::Unauthorized(
+                "Verify your email address before logging in".to_string(),
+            ));
+        }
+        if self.security_service.needs_rehash(&user.password) {
+            self.rehash_password(&user.id, &login_user.password).await;
+        }
 
-        let access_token = self.token_service.generate_access_token(user.id.clone()).await?;
-        //let refresh_token = self.token_service.generate_refresh_token(user.id.clone()).await?;
+        let roles = self.user_repository.get_user_roles(&user.id).await?;
+        let attributes = self.user_repository.get_user_attributes(&user.id).await?;
+        let family_id = self.token_service.new_token_family();
+        let tokens = self
+            .issue_tokens(&user.id, family_id, roles.clone(), attributes.clone())
+            .await?;
 
         let mut user_dto: UserDto = user.into();
-        user_dto.access_token = Some(access_token);
-        //user_dto.refresh_token = Some(refresh_token);
+        user_dto.roles = roles;
+        user_dto.attributes = attributes;
+        user_dto.access_token = Some(tokens.access_token);
+        user_dto.refresh_token = Some(tokens.refresh_token);
 
         Ok(user_dto)
     }
 
+    /// Replaces a hash made with outdated parameters while the password is at hand. The login goes on if it fails.
+    async fn rehash_password(&self, user_id: &str, password: &str) {
+        let rehashed = match self.security_service.hash_password(password).await {
+            Ok(hash) => self
+                .user_repository
+                .update_password(user_id, &hash)
+                .await
+                .map_err(ApiError::from),
+            Err(e) => Err(e),
+        };
+        match rehashed {
+            Ok(()) => info!("Password of user {} rehashed with the current parameters", user_id),
+            Err(e) => warn!("Unable to rehash the password of user {}: {}", user_id, e),
+        }
+    }
+
+    /// Rotates the refresh token. The roles and attributes are read again, so that changes apply from the next refresh
+    /// on.
+    pub async fn refresh_token_handler(&self, refresh_token: &str) -> ApiResult<TokensDto> {
+        let stored = self.token_service.consume_refresh_token(refresh_token).await?;
+        let roles = self.user_repository.get_user_roles(&stored.user_id).await?;
+        let attributes = self.user_repository.get_user_attributes(&stored.user_id).await?;
+        self.issue_tokens(&stored.user_id, stored.family_id, roles, attributes)
+            .await
+    }
+
+    async fn issue_tokens(
+        &self,
+        user_id: &str,
+        family_id: String,
+        roles: Vec<String>,
+        attributes: BTreeMap<String, String>,
+    ) -> ApiResult<TokensDto> {
+        let access_token = self
+            .token_service
+            .generate_access_token(user_id.to_string(), family_id.clone(), roles, attributes)
+            .await?;
+        let refresh_token = self
+            .token_service
+            .generate_refresh_token(user_id.to_string(), family_id)
+            .await?;
+        Ok(TokensDto {
+            access_token,
+            refresh_token,
+        })
+    }
+
+    /// Revokes the login the token was issued for
+    pub async fn logout_handler(&self, validated_token: &ValidatedTokenDetails) -> ApiResult<()> {
+        let family_id = validated_token
+            .family_id
+            .as_deref()
+            .ok_or_else(|| ApiError::BadRequest("The token does not belong to a login session".to_string()))?;
+        self.token_service.revoke_session(family_id).await
+    }
+
+    pub async fn revoke_sessions_handler(&self, user_id: &str) -> ApiResult<()> {
+        self.get_user(user_id).await?;
+        self.token_service.revoke_user_sessions(user_id).await
+    }
+
+    /// Changes the name or the email of the user. A new email has to be verified again.
+    pub async fn update_profile_handler(&self, user_id: &str, request: UpdateProfileRequest) -> ApiResult<UserDto> {
+        let user = self.get_user(user_id).await?;
+        let name = match request.name {
+            Some(name) => validate_name(&name)?,
+            None => user.name,
+        };
+        let email = match request.email {
+            Some(email) => validate_email(&email)?,
+            None => user.email.clone(),
+        };
+        if email != user.email && self.user_repository.get_user_by_email(&email).await.is_ok() {
+            return Err(ApiError::ObjectConflict(
+                "User with that email id already exists".to_string(),
+            ));
+        }
+        self.user_repository.update_profile(user_id, &name, &email).await?;
+        if email != user.email {
+            self.user_repository.set_email_verified(user_id, false).await?;
+            let updated = self.user_repository.get_user_by_id(user_id).await?;
+            self.send_verification_email(&updated).await;
+        }
+        self.get_user(user_id).await
+    }
+
+    /// Replaces the password of the user, who has to give the current one. Every login of the user is revoked, the
+    /// one making the change included. Wrong current passwords count as failed logins.
+    pub async fn change_password_handler(&self, user_id: &str, request: ChangePasswordRequest) -> ApiResult<()> {
+        let user = self
+            .user_repository
+            .get_user_by_id(user_id)
+            .await
+            .map_err(|_e| ApiError::NotFound("User with that userid does not exist".to_string()))?;
+        let attempt = self.login_throttle.begin(&user.email, None).await?;
+        if !self
+            .security_service
+            .verify_password(&user.password, &request.current_password)
+            .await?
+        {
+            self.login_throttle.failed(attempt);
+            return Err(ApiError::InvalidLoginAttempt);
+        }
+        self.login_throttle.succeeded(attempt).await?;
+        self.security_service.check_password_policy(&request.new_password)?;
+        let hashed_password = self.security_service.hash_password(&request.new_password).await?;
+        self.user_repository.update_password(user_id, &hashed_password).await?;
+        self.token_service.revoke_user_sessions(user_id).await?;
+        info!("Password of user {} changed", user_id);
+        Ok(())
+    }
+
+    /// A page of the users, without their roles and attributes
+    pub async fn list_users_handler(&self, query: ListUsersQuery) -> ApiResult<Vec<UserDto>> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        let search = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+        let users = self
+            .user_repository
+            .list_users(search, limit, query.offset.unwrap_or(0))
+            .await?;
+        Ok(users.into_iter().map(UserDto::from).collect())
+    }
+
+    /// Disables the account of a user and revokes its sessions, or enables it again. A disabled user cannot log in,
+    /// and its API keys stop working once their cached lookups expire.
+    pub async fn set_disabled_handler(&self, admin_id: &str, user_id: &str, disabled: bool) -> ApiResult<UserDto> {
+        if disabled && admin_id == user_id {
+            return Err(ApiError::BadRequest(
+                "Admins cannot disable their own account".to_string(),
+            ));
+        }
+        self.get_user(user_id).await?;
+        self.user_repository.set_disabled(user_id, disabled).await?;
+        if disabled {
+            self.token_service.revoke_user_sessions(user_id).await?;
+        }
+        self.get_user(user_id).await
+    }
+
+    pub async fn delete_user_handler(&self, admin_id: &str, user_id: &str) -> ApiResult<()> {
+        if admin_id == user_id {
+            return Err(ApiError::BadRequest(
+                "Admins cannot delete their own account".to_string(),
+            ));
+        }
+        let user = self.get_user(user_id).await?;
+        self.token_service.revoke_user_sessions(user_id).await?;
+        self.user_repository.delete_user(user_id).await?;
+        self.login_throttle.unlock_account(&user.email).await
+    }
+
+    /// Lifts the lockout of the account of the user
+    pub async fn unlock_user_handler(&self, user_id: &str) -> ApiResult<()> {
+        let user = self.get_user(user_id).await?;
+        self.login_throttle.unlock_account(&user.email).await?;
+        info!("Login failures of user {} cleared", user_id);
+        Ok(())
+    }
+
     pub async fn get_user(&self, user_id: &str) -> ApiResult<UserDto> {
         let user = self.user_repository.get_user_by_id(user_id).await;
         if let Err(_e) = user {
             error!("User with userid does not exist: {}", &user_id);
             return Err(ApiError::NotFound("User with that userid does not exist".to_string()));
         }
-        Ok(user.unwrap().into())
+        let mut user_dto: UserDto = user.unwrap().into();
+        user_dto.roles = self.user_repository.get_user_roles(user_id).await?;
+        user_dto.attributes = self.user_repository.get_user_attributes(user_id).await?;
+        Ok(user_dto)
+    }
+
+    /// Replaces the roles of the user and revokes its sessions, so that no token carries the previous roles
+    pub async fn set_roles_handler(&self, user_id: &str, roles: Vec<String>) -> ApiResult<UserDto> {
+        self.get_user(user_id).await?;
+        let mut roles = roles
+            .into_iter()
+            .map(|role| role.trim().to_string())
+            .filter(|role| !role.is_empty())
+            .collect::<Vec<_>>();
+        roles.sort();
+        roles.dedup();
+        self.user_repository.set_user_roles(user_id, &roles).await?;
+        self.token_service.revoke_user_sessions(user_id).await?;
+        self.get_user(user_id).await
+    }
+
+    /// Replaces the attributes of the user, which SQL templates read as `auth.attributes`. Like roles, they are carried
+    /// by access tokens, so the sessions of the user are revoked.
+    pub async fn set_attributes_handler(
+        &self,
+        user_id: &str,
+        attributes: BTreeMap<String, String>,
+    ) -> ApiResult<UserDto> {
+        self.get_user(user_id).await?;
+        let attributes = attributes
+            .into_iter()
+            .map(|(name, value)| (name.trim().to_string(), value))
+            .collect::<BTreeMap<_, _>>();
+        if let Some(name) = attributes.keys().find(|name| !is_attribute_name(name)) {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid attribute name '{}': use letters, digits and underscores only",
+                name
+            )));
+        }
+        self.user_repository.set_user_attributes(user_id, &attributes).await?;
+        self.token_service.revoke_user_sessions(user_id).await?;
+        self.get_user(user_id).await
+    }
+}
+
+fn validate_name(name: &str) -> ApiResult<String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "A name must have between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_email(email: &str) -> ApiResult<String> {
+    let email = email.trim();
+    let well_formed = email
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && !domain.is_empty() && !domain.contains('@'));
+    if !well_formed || email.len() > MAX_EMAIL_LENGTH {
+        return Err(ApiError::BadRequest(format!("{} is not a valid email address", email)));
+    }
+    Ok(email.to_string())
+}
+
+/// Attribute names are read as `auth.attributes.<name>` in templates, so they must be valid identifiers
+fn is_attribute_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::{AppConfig, PasswordHashingConfig};
+    use crate::datasource::sqlite::test_source;
+    use crate::domain::req_res::{ChangePasswordRequest, CreateInviteRequest, ListUsersQuery, UpdateProfileRequest};
+    use crate::domain::{LoginUserDto, RegisterUserDto, UserDto};
+    use crate::errors::{ApiError, ApiResult};
+    use crate::mailer::LogMailer;
+    use crate::repositories::account_token_repository::AccountTokenRepository;
+    use crate::repositories::attempt_store::InMemoryAttemptStore;
+    use crate::repositories::invite_repository::InviteRepository;
+    use crate::repositories::token_repository::TokenRepository;
+    use crate::repositories::user_repository::UserRepository;
+    use crate::services::account_token_service::AccountTokenService;
+    use crate::services::invite_service::InviteService;
+    use crate::services::login_throttle_service::LoginThrottleService;
+    use crate::services::security_service::SecurityService;
+    use crate::services::token_service::TokenService;
+    use crate::services::user_service::UserService;
+
+    const PASSWORD: &str = "correct horse battery";
+
+    /// The services over an in-memory database, with cheap password hashing and no login throttling
+    fn user_service() -> UserService {
+        let mut config = AppConfig::get_configuration("tests/test_nexus.yaml").unwrap();
+        config.api.password_hashing = PasswordHashingConfig {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        config.api.login_throttling.enabled = false;
+        let source = test_source();
+        let security_service = Arc::new(SecurityService::new(Arc::new(config.clone())).unwrap());
+        UserService::new(
+            config.clone(),
+            Arc::new(UserRepository::new(source.clone())),
+            security_service.clone(),
+            Arc::new(TokenService::new(
+                config.clone(),
+                Arc::new(TokenRepository::new(source.clone())),
+            )),
+            Arc::new(LoginThrottleService::new(
+                config.api.login_throttling.clone(),
+                Arc::new(InMemoryAttemptStore::new()),
+            )),
+            Arc::new(
+                AccountTokenService::new(config.clone(), Arc::new(AccountTokenRepository::new(source.clone())))
+                    .unwrap(),
+            ),
+            Arc::new(InviteService::new(
+                config.clone(),
+                Arc::new(InviteRepository::new(source)),
+                security_service,
+                Arc::new(LogMailer),
+            )),
+            Arc::new(LogMailer),
+        )
+    }
+
+    async fn login(users: &UserService, email: &str, password: &str) -> ApiResult<UserDto> {
+        let login_user = LoginUserDto {
+            email: email.to_string(),
+            password: password.to_string(),
+        };
+        users.login_user_handler(login_user, None).await
+    }
+
+    #[tokio::test]
+    async fn test_disabled_users_cannot_log_in_until_enabled() {
+        let users = user_service();
+        let admin = users
+            .create_admin_handler("Admin", "admin@example.com", PASSWORD)
+            .await
+            .unwrap();
+        let user = users
+            .create_admin_handler("Jane", "jane@example.com", PASSWORD)
+            .await
+            .unwrap();
+        assert!(login(&users, "jane@example.com", PASSWORD).await.is_ok());
+
+        let disabled = users.set_disabled_handler(&admin.id, &user.id, true).await.unwrap();
+        assert!(disabled.disabled_at.is_some());
+        assert!(matches!(
+            login(&users, "jane@example.com", PASSWORD).await,
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(users.set_disabled_handler(&admin.id, &admin.id, true).await.is_err());
+
+        let enabled = users.set_disabled_handler(&admin.id, &user.id, false).await.unwrap();
+        assert!(enabled.disabled_at.is_none());
+        assert!(login(&users, "jane@example.com", PASSWORD).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_users_are_gone() {
+        let users = user_service();
+        let admin = users
+            .create_admin_handler("Admin", "admin@example.com", PASSWORD)
+            .await
+            .unwrap();
+        let user = users
+            .create_admin_handler("Jane", "jane@example.com", PASSWORD)
+            .await
+            .unwrap();
+
+        users.delete_user_handler(&admin.id, &user.id).await.unwrap();
+        assert!(matches!(users.get_user(&user.id).await, Err(ApiError::NotFound(_))));
+        assert!(users.user_repository.get_user_roles(&user.id).await.unwrap().is_empty());
+        assert!(matches!(
+            login(&users, "jane@example.com", PASSWORD).await,
+            Err(ApiError::InvalidLoginAttempt)
+        ));
+        assert!(users.delete_user_handler(&admin.id, &admin.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_a_new_email_has_to_be_verified_again() {
+        let users = user_service();
+        let user = users
+            .create_admin_handler("Jane", "jane@example.com", PASSWORD)
+            .await
+            .unwrap();
+        users
+            .create_admin_handler("John", "john@example.com", PASSWORD)
+            .await
+            .unwrap();
+        assert!(user.email_verified_at.is_some());
+
+        let renamed = UpdateProfileRequest {
+            name: Some(" Jane Doe ".to_string()),
+            email: None,
+        };
+        let updated = users.update_profile_handler(&user.id, renamed).await.unwrap();
+        assert_eq!(updated.name, "Jane Doe");
+        assert!(updated.email_verified_at.is_some());
+
+        let taken = UpdateProfileRequest {
+            name: None,
+            email: Some("john@example.com".to_string()),
+        };
+        assert!(matches!(
+            users.update_profile_handler(&user.id, taken).await,
+            Err(ApiError::ObjectConflict(_))
+        ));
+
+        let moved = UpdateProfileRequest {
+            name: None,
+            email: Some("jane.doe@example.com".to_string()),
+        };
+        let updated = users.update_profile_handler(&user.id, moved).await.unwrap();
+        assert_eq!(updated.email, "jane.doe@example.com");
+        assert!(updated.email_verified_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_changing_the_password_needs_the_current_one() {
+        let users = user_service();
+        let user = users
+            .create_admin_handler("Jane", "jane@example.com", PASSWORD)
+            .await
+            .unwrap();
+        let change = |current: &str| ChangePasswordRequest {
+            current_password: current.to_string(),
+            new_password: "staple battery horse".to_string(),
+        };
+
+        assert!(matches!(
+            users.change_password_handler(&user.id, change("wrong password")).await,
+            Err(ApiError::InvalidLoginAttempt)
+        ));
+        users.change_password_handler(&user.id, change(PASSWORD)).await.unwrap();
+        assert!(login(&users, "jane@example.com", PASSWORD).await.is_err());
+        assert!(login(&users, "jane@example.com", "staple battery horse").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_search_wildcards_are_matched_as_they_are() {
+        let users = user_service();
+        users
+            .create_admin_handler("a_b", "ab@example.com", PASSWORD)
+            .await
+            .unwrap();
+        users
+            .create_admin_handler("axb", "axb@example.com", PASSWORD)
+            .await
+            .unwrap();
+        users
+            .create_admin_handler("100%", "full@example.com", PASSWORD)
+            .await
+            .unwrap();
+        let search = |q: &str| ListUsersQuery {
+            q: Some(q.to_string()),
+            limit: None,
+            offset: None,
+        };
+
+        let found = users.list_users_handler(search("A_B")).await.unwrap();
+        assert_eq!(found.iter().map(|user| user.name.as_str()).collect::<Vec<_>>(), ["a_b"]);
+        let found = users.list_users_handler(search("0%")).await.unwrap();
+        assert_eq!(
+            found.iter().map(|user| user.name.as_str()).collect::<Vec<_>>(),
+            ["100%"]
+        );
+        assert_eq!(users.list_users_handler(search("example")).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_an_invite_registers_a_single_user() {
+        let users = user_service();
+        let admin = users
+            .create_admin_handler("Admin", "admin@example.com", PASSWORD)
+            .await
+            .unwrap();
+        let invite = users
+            .invite_service
+            .create_invite(
+                &admin.id,
+                CreateInviteRequest {
+                    email: None,
+                    roles: vec!["analyst".to_string()],
+                    expires_in_days: None,
+                },
+            )
+            .await
+            .unwrap();
+        let register = |email: &str| RegisterUserDto {
+            name: "Jane".to_string(),
+            email: email.to_string(),
+            password: PASSWORD.to_string(),
+            invite_code: Some(invite.invite_code.clone()),
+        };
+
+        users.create_user_handler(register("jane@example.com")).await.unwrap();
+        let jane = users
+            .user_repository
+            .get_user_by_email("jane@example.com")
+            .await
+            .unwrap();
+        assert_eq!(users.get_user(&jane.id).await.unwrap().roles, ["analyst"]);
+        assert!(users.invite_service.list_invites().await.unwrap().is_empty());
+
+        assert!(matches!(
+            users.create_user_handler(register("john@example.com")).await,
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(users
+            .user_repository
+            .get_user_by_email("john@example.com")
+            .await
+            .is_err());
     }
 }